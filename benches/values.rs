@@ -0,0 +1,127 @@
+//! Gorilla against Chimp, Chimp128, the decimal codec and lossy Gorilla on the kinds of series rstz ingests.
+//! Besides the timings, prints the value bits per point each codec needs for every series.
+
+use chrono::{Duration, TimeZone, Utc};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rstz::encodeco::{AnyDecoder, AnyEncoder, ErrorBound, TSDecoder, TsEncoder, ValueCodec};
+use rstz::events::LogEvent;
+use rstz::path::FieldPath;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+const POINTS: usize = 10_000;
+const CODECS: [ValueCodec; 5] = [
+    ValueCodec::Gorilla,
+    ValueCodec::Chimp,
+    ValueCodec::Chimp128,
+    ValueCodec::Decimal,
+    ValueCodec::Lossy(ErrorBound::Relative(0.001)),
+];
+
+/// xorshift64, the series only need to be repeatable.
+struct Noise(u64);
+
+impl Noise {
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Ten second samples of a host: CPU percentages with one decimal, a temperature with two,
+/// a byte counter, an integer gauge and request latencies at full precision.
+fn series() -> Vec<(&'static str, Vec<f64>)> {
+    let mut noise = Noise(0x2545_f491_4f6c_dd1d);
+    let (mut cpu, mut temperature, mut bytes, mut connections) =
+        (35.0f64, 48.0f64, 1.0e9f64, 120.0f64);
+    let mut all: Vec<(&'static str, Vec<f64>)> =
+        ["cpu", "temperature", "bytes", "connections", "latency"]
+            .iter()
+            .map(|name| (*name, Vec::with_capacity(POINTS)))
+            .collect();
+    for _ in 0..POINTS {
+        cpu = (cpu + noise.next() * 4.0 - 2.0).clamp(0.0, 100.0);
+        temperature += noise.next() * 0.2 - 0.1;
+        bytes += (noise.next() * 65_536.0).floor();
+        connections = (connections + (noise.next() * 5.0).floor() - 2.0).max(0.0);
+        let values = [
+            (cpu * 10.0).round() / 10.0,
+            (temperature * 100.0).round() / 100.0,
+            bytes,
+            connections,
+            2.0 + noise.next() * noise.next() * 250.0,
+        ];
+        for ((_, points), value) in all.iter_mut().zip(values.iter()) {
+            points.push(*value);
+        }
+    }
+    all
+}
+
+fn events(values: &[f64]) -> Vec<LogEvent> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let mut fields = BTreeMap::new();
+            fields.insert("value".to_string(), Value::from(*value));
+            LogEvent::new(
+                Utc.timestamp(1_585_411_200 + i as i64 * 10, 0),
+                String::new(),
+                fields,
+            )
+        })
+        .collect()
+}
+
+fn encoder(codec: ValueCodec) -> TsEncoder<AnyEncoder> {
+    TsEncoder::new(FieldPath::key("value"), Duration::hours(2))
+        .with_values(AnyEncoder::for_codec(codec))
+}
+
+fn encode(codec: ValueCodec, events: &[LogEvent]) -> Vec<Vec<u8>> {
+    let mut encoder = encoder(codec);
+    let mut blocks: Vec<Vec<u8>> = events
+        .iter()
+        .filter_map(|event| encoder.compress(event).expect("Values are finite."))
+        .collect();
+    blocks.push(encoder.genblock());
+    blocks
+}
+
+fn values(c: &mut Criterion) {
+    for (name, points) in series() {
+        let events = events(&points);
+        for codec in CODECS.iter().copied() {
+            let mut encoder = encoder(codec);
+            for event in &events {
+                encoder.compress(event).expect("Values are finite.");
+            }
+            let stats = encoder.stats();
+            println!(
+                "{}/{}: {:.2} value bits per point",
+                name,
+                codec,
+                stats.value_bits as f64 / stats.points as f64
+            );
+
+            c.bench_function(&format!("encode/{}/{}", name, codec), |b| {
+                b.iter(|| encode(codec, black_box(&events)))
+            });
+            let blocks = encode(codec, &events);
+            c.bench_function(&format!("decode/{}/{}", name, codec), |b| {
+                b.iter(|| {
+                    black_box(&blocks)
+                        .iter()
+                        .map(|block| TSDecoder::<AnyDecoder>::new(block).count())
+                        .sum::<usize>()
+                })
+            });
+        }
+    }
+}
+
+criterion_group!(benches, values);
+criterion_main!(benches);
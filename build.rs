@@ -0,0 +1,17 @@
+use std::env;
+use std::io;
+use std::path::PathBuf;
+
+fn main() -> io::Result<()> {
+    // The vendored protoc keeps the build independent of a system install.
+    let protoc = protoc_bin_vendored::protoc_bin_path().map_err(io::Error::other)?;
+    let well_known = protoc_bin_vendored::include_path().map_err(io::Error::other)?;
+    env::set_var("PROTOC", protoc);
+    // The generated `connect` helper needs the 2021 prelude, clients build a Channel themselves.
+    tonic_prost_build::configure()
+        .build_transport(false)
+        .compile_protos(
+            &[PathBuf::from("proto/rstz.proto")],
+            &[PathBuf::from("proto"), well_known],
+        )
+}
@@ -0,0 +1,17 @@
+#![no_main]
+
+use chrono::{Duration, TimeZone, Utc};
+use libfuzzer_sys::fuzz_target;
+use rstz::collector::Collector;
+use rstz::query;
+use rstz::store::BlockStore;
+
+// Blocks as they arrive over gRPC, the ones accepted must decode in full when queried.
+fuzz_target!(|data: &[u8]| {
+    let collector = Collector::with_shards(Duration::hours(2), BlockStore::in_memory(), 1);
+    if collector.append_block("fuzz", data.to_vec()).is_ok() {
+        let blocks = collector.blocks("fuzz");
+        let (start, end) = (Utc.timestamp(i32::MIN as i64, 0), Utc.timestamp(i64::from(i32::MAX) * 100, 0));
+        let _ = query::range(&blocks, start, end);
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rstz::encodeco::{AnyDecoder, TSDecoder};
+
+// Any byte string is a candidate block, decoding must end in a point, `None` or an error.
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = TSDecoder::<AnyDecoder>::new(data);
+    let _ = decoder.header();
+    while let Ok(Some(_)) = decoder.try_decompress() {}
+});
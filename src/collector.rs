@@ -0,0 +1,526 @@
+use crate::clock::Clock;
+use crate::encodeco::{
+    Alignment, AnyDecoder, AnyEncoder, BlockLimits, Compression, EncoderStats, TSDecoder, TimestampCodec, TsEncoder,
+    ValueCodec,
+};
+use crate::errors::{Result, RstzError};
+use crate::events::LogEvent;
+use crate::histogram::Histogram;
+use crate::path::FieldPath;
+use crate::store::BlockStore;
+use crate::tree::LazzyTree;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+
+/// One event may feed several series, each read from its own field.
+pub type Route = (String, FieldPath);
+
+/// Encoders of the series hashed to one shard, along with their catalog.
+struct Shard {
+    encoders: BTreeMap<String, TsEncoder<AnyEncoder>>,
+    catalog: LazzyTree,
+}
+
+/// Routes events to one encoder per series and hands sealed blocks to the store.
+/// Series are partitioned across shards by their catalog key, so producers writing to
+/// different series rarely wait on each other, and readers only hold the store for reading.
+/// Sealing a block takes the shard lock and then the store lock, never the other way around.
+pub struct Collector {
+    interval: Duration,
+    alignment: Alignment,
+    limits: BlockLimits,
+    compression: Compression,
+    timestamps: TimestampCodec,
+    series_timestamps: BTreeMap<String, TimestampCodec>,
+    values: ValueCodec,
+    series_values: BTreeMap<String, ValueCodec>,
+    grace: Duration,
+    shards: Vec<Mutex<Shard>>,
+    store: RwLock<BlockStore>,
+}
+
+impl Collector {
+    /// One shard per available core.
+    pub fn new(interval: Duration, store: BlockStore) -> Self {
+        let shards = thread::available_parallelism().map_or(1, |n| n.get());
+        Collector::with_shards(interval, store, shards)
+    }
+
+    pub fn with_shards(interval: Duration, store: BlockStore, shards: usize) -> Self {
+        let shards = (0..shards.max(1))
+            .map(|_| {
+                Mutex::new(Shard {
+                    encoders: BTreeMap::new(),
+                    catalog: LazzyTree::new(),
+                })
+            })
+            .collect();
+        let collector = Collector {
+            interval,
+            alignment: Alignment::default(),
+            limits: BlockLimits::default(),
+            compression: Compression::default(),
+            timestamps: TimestampCodec::default(),
+            series_timestamps: BTreeMap::new(),
+            values: ValueCodec::default(),
+            series_values: BTreeMap::new(),
+            grace: Duration::zero(),
+            shards,
+            store: RwLock::new(store),
+        };
+        for series in read(&collector.store).series() {
+            collector.shard(series).catalog.insert(series);
+        }
+        collector
+    }
+
+    /// Where the windows of new encoders start.
+    pub fn with_alignment(mut self, alignment: Alignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Bounds on the blocks of new encoders, noisy series then seal several blocks per window.
+    pub fn with_limits(mut self, limits: BlockLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// General purpose compression of the blocks new encoders seal.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Timestamp codec of new encoders, unless `with_series_timestamps` names their series.
+    pub fn with_timestamps(mut self, timestamps: TimestampCodec) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Timestamp codec of the series `series`, either a whole series key or a name matching every label set.
+    pub fn with_series_timestamps(mut self, series: &str, timestamps: TimestampCodec) -> Self {
+        self.series_timestamps.insert(series.to_string(), timestamps);
+        self
+    }
+
+    /// The timestamp codec new encoders of `series` use.
+    pub fn timestamps_for(&self, series: &str) -> TimestampCodec {
+        let name = series.split('{').next().unwrap_or(series);
+        self.series_timestamps
+            .get(series)
+            .or_else(|| self.series_timestamps.get(name))
+            .copied()
+            .unwrap_or(self.timestamps)
+    }
+
+    /// Value codec of new encoders, unless `with_series_values` names their series.
+    pub fn with_values(mut self, values: ValueCodec) -> Self {
+        self.values = values;
+        self
+    }
+
+    /// Value codec of the series `series`, either a whole series key or a name matching every label set.
+    pub fn with_series_values(mut self, series: &str, values: ValueCodec) -> Self {
+        self.series_values.insert(series.to_string(), values);
+        self
+    }
+
+    /// The value codec new encoders of `series` use.
+    pub fn values_for(&self, series: &str) -> ValueCodec {
+        let name = series.split('{').next().unwrap_or(series);
+        self.series_values
+            .get(series)
+            .or_else(|| self.series_values.get(name))
+            .copied()
+            .unwrap_or(self.values)
+    }
+
+    /// How long past the end of its window a block stays open for late points before `tick` seals it.
+    pub fn with_grace(mut self, grace: Duration) -> Self {
+        self.grace = grace;
+        self
+    }
+
+    /// Locks the shard owning `series`.
+    /// A producer panicking mid write only loses its own point, so poisoned locks are recovered.
+    fn shard(&self, series: &str) -> MutexGuard<'_, Shard> {
+        let key = LazzyTree::key_for(series);
+        let hash = u64::from_be_bytes(*<&[u8; 8]>::try_from(&key[..8]).expect("Keys are 16 bytes."));
+        lock(&self.shards[(hash % self.shards.len() as u64) as usize])
+    }
+
+    /// Encodes the value found at `field` into the series named `series`.
+    pub fn ingest(&self, series: &str, field: &FieldPath, event: &LogEvent) -> Result<()> {
+        let mut shard = self.shard(series);
+        if !shard.encoders.contains_key(series) {
+            let encoder = TsEncoder::new(field.clone(), self.interval)
+                .with_alignment(self.alignment)
+                .with_limits(self.limits)
+                .with_compression(self.compression)
+                .with_timestamps(self.timestamps_for(series))
+                .with_values(AnyEncoder::for_codec(self.values_for(series)));
+            shard.encoders.insert(series.to_string(), encoder);
+            shard.catalog.insert(series);
+        }
+        let encoder = shard.encoders.get_mut(series).expect("Encoder was just inserted.");
+        if let Some(block) = encoder.compress(event)? {
+            write(&self.store).append(series, block)?;
+        }
+        Ok(())
+    }
+
+    pub fn ingest_routes(&self, event: &LogEvent, routes: &[Route]) -> Result<()> {
+        for (series, field) in routes {
+            self.ingest(series, field, event)?;
+        }
+        Ok(())
+    }
+
+    /// Stores a block sealed elsewhere, such as by a client encoding on the edge.
+    /// The whole block is decoded first, corrupt blocks are rejected instead of stored.
+    pub fn append_block(&self, series: &str, block: Vec<u8>) -> Result<()> {
+        let mut decoder = TSDecoder::<AnyDecoder>::new(&block);
+        decoder.header()?;
+        while decoder.try_decompress()?.is_some() {}
+        let mut shard = self.shard(series);
+        shard.catalog.insert(series);
+        write(&self.store).append(series, block)
+    }
+
+    /// Seals every open block, used before shutting down.
+    pub fn flush(&self) -> Result<()> {
+        for shard in &self.shards {
+            let mut shard = lock(shard);
+            let mut store = write(&self.store);
+            for (series, encoder) in shard.encoders.iter_mut() {
+                store.append(series, encoder.genblock())?;
+            }
+        }
+        write(&self.store).sync()
+    }
+
+    /// Seals the blocks whose window ended more than the grace period before `now`,
+    /// returning how many were sealed.
+    pub fn tick(&self, now: DateTime<Utc>) -> Result<usize> {
+        let mut sealed = 0;
+        for shard in &self.shards {
+            let mut shard = lock(shard);
+            for (series, encoder) in shard.encoders.iter_mut() {
+                if let Some(block) = encoder.tick(now, self.grace) {
+                    write(&self.store).append(series, block)?;
+                    sealed += 1;
+                }
+            }
+        }
+        if sealed > 0 {
+            write(&self.store).sync()?;
+        }
+        Ok(sealed)
+    }
+
+    /// Every known series, sealed or still open, sorted by name.
+    pub fn series(&self) -> Vec<String> {
+        let mut series = Vec::new();
+        for shard in &self.shards {
+            series.extend(lock(shard).catalog.series().into_iter().map(String::from));
+        }
+        series.sort_unstable();
+        series
+    }
+
+    pub fn contains(&self, series: &str) -> bool {
+        self.shard(series).catalog.contains(series)
+    }
+
+    /// Compression statistics of `series` since its encoder was created in this process.
+    pub fn stats(&self, series: &str) -> Option<EncoderStats> {
+        self.shard(series).encoders.get(series).map(TsEncoder::stats)
+    }
+
+    /// Sealed blocks of `series` followed by a snapshot of its open block.
+    pub fn blocks(&self, series: &str) -> Vec<Vec<u8>> {
+        let shard = self.shard(series);
+        let mut blocks = read(&self.store).blocks(series).to_vec();
+        if let Some(encoder) = shard.encoders.get(series) {
+            let open = encoder.snapshot();
+            if !open.is_empty() {
+                blocks.push(open);
+            }
+        }
+        blocks
+    }
+}
+
+/// Ticks `collector` every `every` with the time read from `clock`.
+/// The thread stops once every other handle to the collector was dropped.
+pub fn spawn_ticker(
+    collector: &Arc<Collector>,
+    clock: Arc<dyn Clock>,
+    every: std::time::Duration,
+) -> thread::JoinHandle<()> {
+    let collector = Arc::downgrade(collector);
+    thread::spawn(move || loop {
+        thread::sleep(every);
+        let collector = match collector.upgrade() {
+            Some(collector) => collector,
+            None => return,
+        };
+        if let Err(e) = collector.tick(clock.now()) {
+            eprintln!("Failed to seal quiet series: {}", e);
+        }
+    })
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn read<T>(rwlock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    rwlock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<T>(rwlock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    rwlock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Routes the numeric and histogram `fields` of a log event to `field{host="..."}` series.
+pub fn log_routes(event: &LogEvent, fields: &[FieldPath]) -> Vec<Route> {
+    fields
+        .iter()
+        .filter(|f| {
+            event
+                .get_path(f)
+                .is_some_and(|v| v.is_number() || Histogram::from_value(v).is_ok())
+        })
+        .map(|f| (series_key(&f.to_string(), vec![("host", event.host())]), f.clone()))
+        .collect()
+}
+
+/// Builds a Prometheus style series key, `name{label="value",...}` with labels sorted by name.
+pub fn series_key<'l, I>(name: &str, labels: I) -> String
+where
+    I: IntoIterator<Item = (&'l str, &'l str)>,
+{
+    let mut labels: Vec<_> = labels.into_iter().collect();
+    if labels.is_empty() {
+        return name.to_string();
+    }
+    labels.sort();
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    format!("{}{{{}}}", name, labels.join(","))
+}
+
+/// Splits a key built by `series_key` back into its name and labels.
+pub fn parse_series_key(key: &str) -> Result<(String, Vec<(String, String)>)> {
+    let start = match key.find('{') {
+        Some(start) => start,
+        None => return Ok((key.to_string(), Vec::new())),
+    };
+    let name = key[..start].to_string();
+    let mut labels = Vec::new();
+    let mut chars = key[start + 1..].chars();
+    loop {
+        let mut label = String::new();
+        for c in &mut chars {
+            if c == '=' || c == '}' {
+                break;
+            }
+            if c != ',' {
+                label.push(c);
+            }
+        }
+        if label.is_empty() {
+            return Ok((name, labels));
+        }
+        if chars.next() != Some('"') {
+            return Err(RstzError::new(&format!("Malformed series key: {}", key)));
+        }
+        let mut value = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => value.push(chars.next().ok_or(RstzError::Eof)?),
+                Some(c) => value.push(c),
+                None => return Err(RstzError::Eof),
+            }
+        }
+        labels.push((label, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::query;
+    use chrono::{TimeZone, Utc};
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    fn event(i: i64) -> LogEvent {
+        let mut values = BTreeMap::new();
+        values.insert("value".to_string(), Value::from(i as f64));
+        LogEvent::new(Utc.timestamp(1_585_413_000 + i * 60, 0), String::new(), values)
+    }
+
+    #[test]
+    fn is_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Collector>();
+    }
+
+    #[test]
+    fn ingests_from_many_threads_while_reading() {
+        // 240 one minute points cross two 2 hour windows, so blocks are sealed while readers run.
+        let collector = Arc::new(Collector::with_shards(
+            Duration::minutes(120),
+            BlockStore::in_memory(),
+            4,
+        ));
+        let field = FieldPath::key("value");
+        let writers: Vec<_> = (0..8)
+            .map(|w| {
+                let collector = Arc::clone(&collector);
+                let field = field.clone();
+                thread::spawn(move || {
+                    let series = format!("series_{}", w);
+                    for i in 0..240 {
+                        collector.ingest(&series, &field, &event(i)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        let reader = {
+            let collector = Arc::clone(&collector);
+            thread::spawn(move || {
+                let (start, end) = (event(0).datetime(), event(240).datetime());
+                for _ in 0..50 {
+                    for series in collector.series() {
+                        let points = query::range(&collector.blocks(&series), start, end).unwrap();
+                        assert!(points.windows(2).all(|p| p[0].timestamp() < p[1].timestamp()));
+                    }
+                }
+            })
+        };
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        reader.join().unwrap();
+
+        collector.flush().unwrap();
+        let series = collector.series();
+        assert_eq!(series.len(), 8);
+        for series in series {
+            let points = query::range(&collector.blocks(&series), event(0).datetime(), event(240).datetime()).unwrap();
+            assert_eq!(points.len(), 240);
+        }
+    }
+
+    #[test]
+    fn seals_quiet_series_on_tick() {
+        let collector = Collector::with_shards(Duration::minutes(120), BlockStore::in_memory(), 2)
+            .with_grace(Duration::minutes(5));
+        let field = FieldPath::key("value");
+        for i in 0..10 {
+            collector.ingest("quiet", &field, &event(i)).unwrap();
+        }
+        // Window starts at midnight, the events land in the 16:00 to 18:00 block.
+        let clock = ManualClock::new(Utc.ymd(2020, 3, 28).and_hms(17, 0, 0));
+        assert_eq!(collector.tick(clock.now()).unwrap(), 0);
+        clock.set(Utc.ymd(2020, 3, 28).and_hms(18, 5, 0));
+        assert_eq!(collector.tick(clock.now()).unwrap(), 0);
+        clock.advance(Duration::seconds(1));
+        assert_eq!(collector.tick(clock.now()).unwrap(), 1);
+        assert_eq!(collector.tick(clock.now()).unwrap(), 0);
+
+        let start = event(0).datetime();
+        let sealed = read(&collector.store).blocks("quiet").to_vec();
+        assert_eq!(sealed.len(), 1);
+        assert_eq!(query::range(&sealed, start, clock.now()).unwrap().len(), 10);
+        assert_eq!(collector.blocks("quiet"), sealed);
+
+        // A late point opens a new block rather than being lost.
+        collector.ingest("quiet", &field, &event(10)).unwrap();
+        assert_eq!(query::range(&collector.blocks("quiet"), start, clock.now()).unwrap().len(), 11);
+    }
+
+    #[test]
+    fn picks_timestamp_codecs_per_series() {
+        let collector = Collector::with_shards(Duration::minutes(120), BlockStore::in_memory(), 1)
+            .with_timestamps(TimestampCodec::Seconds)
+            .with_series_timestamps("cpu", TimestampCodec::Regular)
+            .with_series_timestamps("cpu{host=\"b\"}", TimestampCodec::Dod);
+        assert_eq!(collector.timestamps_for("cpu{host=\"a\"}"), TimestampCodec::Regular);
+        assert_eq!(collector.timestamps_for("cpu{host=\"b\"}"), TimestampCodec::Dod);
+        assert_eq!(collector.timestamps_for("mem{host=\"a\"}"), TimestampCodec::Seconds);
+    }
+
+    #[test]
+    fn picks_value_codecs_per_series() {
+        let collector = Collector::with_shards(Duration::minutes(120), BlockStore::in_memory(), 1)
+            .with_series_values("cpu", ValueCodec::Chimp128)
+            .with_series_values("cpu{host=\"b\"}", ValueCodec::Chimp);
+        let field = FieldPath::key("value");
+        for series in &["cpu{host=\"a\"}", "cpu{host=\"b\"}", "mem{host=\"a\"}"] {
+            for i in 0..10 {
+                collector.ingest(series, &field, &event(i)).unwrap();
+            }
+        }
+        collector.flush().unwrap();
+        for (series, codec) in &[
+            ("cpu{host=\"a\"}", ValueCodec::Chimp128),
+            ("cpu{host=\"b\"}", ValueCodec::Chimp),
+            ("mem{host=\"a\"}", ValueCodec::Gorilla),
+        ] {
+            let blocks = collector.blocks(series);
+            assert_eq!(TSDecoder::<AnyDecoder>::new(&blocks[0]).values(), Ok(*codec));
+            let points = query::range(&blocks, event(0).datetime(), event(10).datetime()).unwrap();
+            assert_eq!(points.len(), 10);
+            collector.append_block(series, blocks[0].clone()).unwrap();
+        }
+    }
+
+    #[test]
+    fn merges_histogram_series_over_steps() {
+        let collector = Collector::with_shards(Duration::minutes(120), BlockStore::in_memory(), 1)
+            .with_series_values("latency", ValueCodec::Histogram);
+        let field = FieldPath::key("latency");
+        let mut histograms = Vec::new();
+        for i in 0..10 {
+            let mut histogram = Histogram::new(3).unwrap();
+            for ms in 1..=100 {
+                histogram.observe((ms * (i + 1)) as f64);
+            }
+            let mut values = BTreeMap::new();
+            values.insert("latency".to_string(), histogram.to_value());
+            let entry = LogEvent::new(event(i).datetime(), "a".to_string(), values);
+            let routes = log_routes(&entry, &[field.clone(), FieldPath::key("value")]);
+            assert_eq!(routes, vec![("latency{host=\"a\"}".to_string(), field.clone())]);
+            collector.ingest("latency", &field, &entry).unwrap();
+            histograms.push(histogram);
+        }
+        collector.flush().unwrap();
+        let blocks = collector.blocks("latency");
+        let points = query::range(&blocks, event(0).datetime(), event(10).datetime()).unwrap();
+        let decoded: Vec<Histogram> =
+            points.iter().map(|p| Histogram::from_value(p.value()).unwrap()).collect();
+        assert_eq!(decoded, histograms);
+
+        let merged = query::aggregate(&points, Duration::minutes(5), query::Aggregation::Merge);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(Histogram::from_value(merged[0].value()).unwrap().count(), 500);
+        let p99: query::Aggregation = "quantile:0.99".parse().unwrap();
+        let p99 = query::aggregate(&points, Duration::minutes(5), p99);
+        // Exact quantiles are 475ms and 950ms, schema 3 estimates are within 4.5% of them.
+        assert!((p99[0].value().as_f64().unwrap() - 475.0).abs() < 475.0 * 0.045);
+        assert!((p99[1].value().as_f64().unwrap() - 950.0).abs() < 950.0 * 0.045);
+        assert!("quantile:2".parse::<query::Aggregation>().is_err());
+    }
+}
@@ -0,0 +1,357 @@
+use crate::errors::{Result, RstzError};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::ChaCha20Poly1305;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// 256 bit key, the size both ciphers take.
+pub type Key = [u8; 32];
+
+/// Bytes of the random nonce in front of every encrypted record.
+const NONCE_BYTES: usize = 12;
+
+/// Authenticated encryption of segment records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Cipher {
+    #[default]
+    Aes256Gcm,
+    /// Faster than AES-GCM on CPUs without AES instructions.
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => 1,
+            Cipher::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(Cipher::Aes256Gcm),
+            2 => Ok(Cipher::ChaCha20Poly1305),
+            _ => Err(RstzError::DecryptionFailed(format!(
+                "Unknown cipher {}.",
+                id
+            ))),
+        }
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = RstzError;
+
+    /// `aes-256-gcm` or `chacha20-poly1305`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "aes-256-gcm" => Ok(Cipher::Aes256Gcm),
+            "chacha20-poly1305" => Ok(Cipher::ChaCha20Poly1305),
+            _ => Err(RstzError::new(&format!("Unknown cipher: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cipher::Aes256Gcm => "aes-256-gcm",
+            Cipher::ChaCha20Poly1305 => "chacha20-poly1305",
+        })
+    }
+}
+
+/// Where encryption keys come from, such as a file or a key management service.
+/// Keys are named by ids short enough for a byte to hold their length, segments record the id of theirs.
+pub trait KeyProvider: Send + Sync {
+    /// Id and key new segments are encrypted with.
+    fn current(&self) -> Result<(String, Key)>;
+
+    /// The key named `id`, `RstzError::UnknownKey` when there is none.
+    fn key(&self, id: &str) -> Result<Key>;
+}
+
+/// Keys read from a file of `ID HEX` lines, such as `2024-06 00112233...`, each holding 64 hex digits.
+/// Blank lines and lines starting with `#` are skipped. Keys are rotated by appending a line,
+/// the last key encrypts new segments while the earlier ones still decrypt old segments.
+pub struct FileKeyProvider {
+    keys: Vec<(String, Key)>,
+}
+
+impl FileKeyProvider {
+    pub fn open(path: &Path) -> Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+}
+
+impl FromStr for FileKeyProvider {
+    type Err = RstzError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut keys = Vec::new();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let (id, hex) = match (words.next(), words.next(), words.next()) {
+                (Some(id), Some(hex), None) if id.len() <= u8::MAX as usize => (id, hex),
+                _ => {
+                    return Err(RstzError::new(
+                        "Key lines are an id of at most 255 bytes and a key.",
+                    ))
+                }
+            };
+            let key = parse_key(hex)
+                .ok_or_else(|| RstzError::new(&format!("Key {} is not 64 hex digits.", id)))?;
+            keys.push((id.to_string(), key));
+        }
+        if keys.is_empty() {
+            return Err(RstzError::new("No keys found."));
+        }
+        Ok(FileKeyProvider { keys })
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn current(&self) -> Result<(String, Key)> {
+        self.keys
+            .last()
+            .cloned()
+            .ok_or_else(|| RstzError::UnknownKey(String::new()))
+    }
+
+    fn key(&self, id: &str) -> Result<Key> {
+        self.keys
+            .iter()
+            .rev()
+            .find(|(key_id, _)| key_id == id)
+            .map(|(_, key)| *key)
+            .ok_or_else(|| RstzError::UnknownKey(id.to_string()))
+    }
+}
+
+fn parse_key(hex: &str) -> Option<Key> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut key = [0; 32];
+    for (byte, pair) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(key)
+}
+
+/// How a block store encrypts the segments it writes, and finds the keys of those it reads.
+#[derive(Clone)]
+pub struct Encryption {
+    pub cipher: Cipher,
+    pub keys: Arc<dyn KeyProvider>,
+}
+
+impl Encryption {
+    pub fn new(cipher: Cipher, keys: Arc<dyn KeyProvider>) -> Self {
+        Encryption { cipher, keys }
+    }
+}
+
+enum AnyAead {
+    Aes256Gcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+/// Encrypts the records of one segment under one key.
+/// Each record is authenticated along with the segment header and its position in the segment,
+/// so records cannot be moved between segments or reordered without failing to decrypt.
+/// The number of records is not authenticated: records cut from the end of a segment,
+/// as an interrupted write would leave it, go unnoticed.
+pub(crate) struct RecordCipher {
+    aead: AnyAead,
+    header: Vec<u8>,
+    records: u64,
+}
+
+impl RecordCipher {
+    pub(crate) fn new(cipher: Cipher, key: &Key, header: &[u8]) -> Self {
+        let aead = match cipher {
+            Cipher::Aes256Gcm => AnyAead::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+            Cipher::ChaCha20Poly1305 => {
+                AnyAead::ChaCha20Poly1305(ChaCha20Poly1305::new(key.into()))
+            }
+        };
+        RecordCipher {
+            aead,
+            header: header.to_vec(),
+            records: 0,
+        }
+    }
+
+    fn aad(&self) -> Vec<u8> {
+        let mut aad = self.header.clone();
+        aad.extend_from_slice(&self.records.to_be_bytes());
+        aad
+    }
+
+    /// The next record of the segment as its nonce followed by the ciphertext and tag.
+    pub(crate) fn seal(&mut self, record: &[u8]) -> Result<Vec<u8>> {
+        let aad = self.aad();
+        let payload = Payload {
+            msg: record,
+            aad: &aad,
+        };
+        let (nonce, sealed) = match &self.aead {
+            AnyAead::Aes256Gcm(aead) => {
+                let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+                (nonce, aead.encrypt(&nonce, payload))
+            }
+            AnyAead::ChaCha20Poly1305(aead) => {
+                let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+                (nonce, aead.encrypt(&nonce, payload))
+            }
+        };
+        let sealed = sealed.map_err(|_| RstzError::new("Record too large to encrypt."))?;
+        self.records += 1;
+        let mut out = nonce.to_vec();
+        out.extend(sealed);
+        Ok(out)
+    }
+
+    /// Undoes `seal` for the next record of the segment.
+    pub(crate) fn open(&mut self, sealed: &[u8]) -> Result<Vec<u8>> {
+        let failed = |records| {
+            RstzError::DecryptionFailed(format!("Record {} does not authenticate.", records))
+        };
+        if sealed.len() < NONCE_BYTES {
+            return Err(failed(self.records));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_BYTES);
+        let aad = self.aad();
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &aad,
+        };
+        let record = match &self.aead {
+            AnyAead::Aes256Gcm(aead) => aead.decrypt(nonce.into(), payload),
+            AnyAead::ChaCha20Poly1305(aead) => aead.decrypt(nonce.into(), payload),
+        }
+        .map_err(|_| failed(self.records))?;
+        self.records += 1;
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::BlockStore;
+    use std::path::PathBuf;
+
+    const KEYS: &str = "# rotated yearly
+        2023 000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f
+
+        2024 ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100";
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rstz-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn encryption(cipher: Cipher, keys: &str) -> Encryption {
+        Encryption::new(cipher, Arc::new(keys.parse::<FileKeyProvider>().unwrap()))
+    }
+
+    /// The segment holding records.
+    fn segment(dir: &Path) -> PathBuf {
+        let segments = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path());
+        segments
+            .max_by_key(|p| fs::metadata(p).unwrap().len())
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_key_files() {
+        let keys: FileKeyProvider = KEYS.parse().unwrap();
+        assert_eq!(keys.current().unwrap().0, "2024");
+        assert_eq!(keys.key("2023").unwrap()[31], 0x1f);
+        assert_eq!(
+            keys.key("2022"),
+            Err(RstzError::UnknownKey("2022".to_string()))
+        );
+        assert!("2024 00ff".parse::<FileKeyProvider>().is_err());
+        assert!("# nothing".parse::<FileKeyProvider>().is_err());
+    }
+
+    #[test]
+    fn encrypts_segments_at_rest() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305]
+            .iter()
+            .copied()
+        {
+            let dir = scratch(&cipher.to_string());
+            let block = b"customer 4242 latency".to_vec();
+            let mut store = BlockStore::open_encrypted(&dir, &encryption(cipher, KEYS)).unwrap();
+            store.append("latency{host=\"a\"}", block.clone()).unwrap();
+            store.append("latency{host=\"b\"}", vec![7; 40]).unwrap();
+            store.sync().unwrap();
+            drop(store);
+            let data = fs::read(segment(&dir)).unwrap();
+            assert!(!data
+                .windows(8)
+                .any(|w| w == b"customer" || w == b"latency{"));
+
+            let store = BlockStore::open_encrypted(&dir, &encryption(cipher, KEYS)).unwrap();
+            assert_eq!(store.blocks("latency{host=\"a\"}"), &[block][..]);
+            assert_eq!(store.blocks("latency{host=\"b\"}").len(), 1);
+            drop(store);
+
+            // Without the key, or with a key of the same id but other bytes, nothing loads.
+            match BlockStore::open(&dir) {
+                Err(RstzError::UnknownKey(id)) => assert_eq!(id, "2024"),
+                _ => panic!("Encrypted segments need a key provider."),
+            }
+            let older = KEYS.lines().take(2).collect::<Vec<_>>().join("\n");
+            assert_eq!(
+                BlockStore::open_encrypted(&dir, &encryption(cipher, &older)).err(),
+                Some(RstzError::UnknownKey("2024".to_string()))
+            );
+            let forged = KEYS.replace("ffeedd", "ffeede");
+            assert!(matches!(
+                BlockStore::open_encrypted(&dir, &encryption(cipher, &forged)),
+                Err(RstzError::DecryptionFailed(_))
+            ));
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn detects_tampered_records() {
+        let dir = scratch("tampered");
+        let keys = encryption(Cipher::Aes256Gcm, KEYS);
+        let mut store = BlockStore::open_encrypted(&dir, &keys).unwrap();
+        store.append("cpu", vec![1, 2, 3]).unwrap();
+        store.append("cpu", vec![4, 5, 6]).unwrap();
+        store.sync().unwrap();
+        drop(store);
+        let path = segment(&dir);
+        let data = fs::read(&path).unwrap();
+        for i in (8..data.len()).step_by(7) {
+            let mut tampered = data.clone();
+            tampered[i] ^= 0x01;
+            fs::write(&path, &tampered).unwrap();
+            match BlockStore::open_encrypted(&dir, &keys) {
+                Err(_) => {}
+                // A record length running past the end reads as a record torn by a crash,
+                // which is cut off along with the records after it.
+                Ok(store) => {
+                    assert!(store.blocks("cpu").len() < 2, "Flipped bit in byte {} went unnoticed.", i);
+                }
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+use crate::errors::{Result, RstzError};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Where block windows start.
+/// Windows are half open, a point on the boundary belongs to the window it starts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alignment {
+    /// Multiples of the interval since 1970-01-01T00:00:00Z.
+    Epoch,
+    /// Multiples of the interval since local midnight, the last window of a day is cut short at the next midnight.
+    /// Intervals longer than a day span whole local days, rounded up and counted from 1970-01-01.
+    Midnight(Tz),
+    /// Multiples of the interval since the first event the encoder saw, truncated to the second.
+    FirstEvent,
+}
+
+impl Default for Alignment {
+    fn default() -> Self {
+        Alignment::Midnight(Tz::UTC)
+    }
+}
+
+impl Alignment {
+    /// Start and end of the window holding `time`, `anchor` is the first event seen when aligning to it.
+    pub fn window(
+        &self,
+        time: DateTime<Utc>,
+        interval: Duration,
+        anchor: Option<DateTime<Utc>>,
+    ) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+        let step = interval.num_seconds();
+        if step <= 0 || Duration::seconds(step) != interval {
+            return Err(RstzError::new("Interval must be a positive whole number of seconds."));
+        }
+        match self {
+            Alignment::Epoch => Ok(Self::fixed(time, Utc.timestamp(0, 0), step)),
+            Alignment::FirstEvent => {
+                let anchor = anchor.ok_or_else(|| RstzError::new("First event alignment without an anchor."))?;
+                Ok(Self::fixed(time, Utc.timestamp(anchor.timestamp(), 0), step))
+            }
+            Alignment::Midnight(tz) => {
+                let date = time.with_timezone(tz).date().naive_local();
+                if step <= SECONDS_PER_DAY {
+                    let midnight = local_midnight(tz, date)?;
+                    let (start, end) = Self::fixed(time, midnight, step);
+                    let next = local_midnight(tz, date.succ())?;
+                    Ok((start, end.min(next)))
+                } else {
+                    let days = (step + SECONDS_PER_DAY - 1) / SECONDS_PER_DAY;
+                    let since_epoch = date.signed_duration_since(NaiveDate::from_ymd(1970, 1, 1)).num_days();
+                    let first = date - Duration::days(since_epoch.rem_euclid(days));
+                    let start = local_midnight(tz, first)?;
+                    let end = local_midnight(tz, first + Duration::days(days))?;
+                    Ok((start, end))
+                }
+            }
+        }
+    }
+
+    fn fixed(time: DateTime<Utc>, origin: DateTime<Utc>, step: i64) -> (DateTime<Utc>, DateTime<Utc>) {
+        let elapsed = time.timestamp() - origin.timestamp();
+        let start = origin + Duration::seconds(elapsed.div_euclid(step) * step);
+        (start, start + Duration::seconds(step))
+    }
+}
+
+/// First instant of `date` in `tz`, days starting with a DST gap begin when the clocks jump.
+fn local_midnight(tz: &Tz, date: NaiveDate) -> Result<DateTime<Utc>> {
+    (0..3)
+        .find_map(|hour| tz.from_local_datetime(&date.and_hms(hour, 0, 0)).earliest())
+        .map(|local| local.with_timezone(&Utc))
+        .ok_or_else(|| RstzError::new(&format!("No midnight on {} in {}.", date, tz.name())))
+}
+
+impl FromStr for Alignment {
+    type Err = RstzError;
+
+    /// `epoch`, `first`, `midnight` for UTC or `midnight:<IANA zone>`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "epoch" => Ok(Alignment::Epoch),
+            "first" => Ok(Alignment::FirstEvent),
+            "midnight" => Ok(Alignment::Midnight(Tz::UTC)),
+            _ => match s.strip_prefix("midnight:") {
+                Some(zone) => zone
+                    .parse::<Tz>()
+                    .map(Alignment::Midnight)
+                    .map_err(|e| RstzError::new(&e)),
+                None => Err(RstzError::new(&format!("Unknown alignment: {}", s))),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Alignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alignment::Epoch => f.write_str("epoch"),
+            Alignment::FirstEvent => f.write_str("first"),
+            Alignment::Midnight(tz) => write!(f, "midnight:{}", tz.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn window(alignment: &str, time: &str, interval: Duration) -> (DateTime<Utc>, DateTime<Utc>) {
+        let alignment: Alignment = alignment.parse().unwrap();
+        alignment.window(utc(time), interval, Some(utc(time))).unwrap()
+    }
+
+    #[test]
+    fn aligns_to_the_epoch() {
+        let (start, end) = window("epoch", "2020-03-28T16:29:04Z", Duration::hours(7));
+        assert_eq!(start.timestamp() % (7 * 3600), 0);
+        assert!(start <= utc("2020-03-28T16:29:04Z") && utc("2020-03-28T16:29:04Z") < end);
+        assert_eq!(end - start, Duration::hours(7));
+
+        let (start, _) = window("epoch", "1969-12-31T23:59:59Z", Duration::hours(2));
+        assert_eq!(start, utc("1969-12-31T22:00:00Z"));
+    }
+
+    #[test]
+    fn aligns_to_local_midnight() {
+        assert_eq!(
+            window("midnight", "2020-03-28T16:29:04Z", Duration::hours(2)),
+            (utc("2020-03-28T16:00:00Z"), utc("2020-03-28T18:00:00Z"))
+        );
+        // 23:30 on the 27th in São Paulo.
+        assert_eq!(
+            window("midnight:America/Sao_Paulo", "2020-03-28T02:30:00Z", Duration::hours(2)),
+            (utc("2020-03-28T01:00:00Z"), utc("2020-03-28T03:00:00Z"))
+        );
+        // Seven hours do not divide a day, the last window ends at midnight.
+        assert_eq!(
+            window("midnight", "2020-03-28T22:00:00Z", Duration::hours(7)),
+            (utc("2020-03-28T21:00:00Z"), utc("2020-03-29T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn follows_daylight_saving() {
+        // Berlin skips from 02:00 to 03:00 on 2020-03-29, that day lasts 23 hours.
+        assert_eq!(
+            window("midnight:Europe/Berlin", "2020-03-29T12:00:00Z", Duration::days(1)),
+            (utc("2020-03-28T23:00:00Z"), utc("2020-03-29T22:00:00Z"))
+        );
+        // Havana skipped midnight itself on 2020-03-08.
+        assert_eq!(
+            window("midnight:America/Havana", "2020-03-08T12:00:00Z", Duration::days(1)).0,
+            utc("2020-03-08T05:00:00Z")
+        );
+    }
+
+    #[test]
+    fn spans_several_days() {
+        let (start, end) = window("midnight:Asia/Tokyo", "2020-03-28T16:29:04Z", Duration::hours(36));
+        assert_eq!(end - start, Duration::days(2));
+        assert_eq!(start.with_timezone(&Tz::Asia__Tokyo).time(), chrono::NaiveTime::from_hms(0, 0, 0));
+
+        let (start, end) = window("epoch", "2020-03-28T16:29:04Z", Duration::days(7));
+        assert_eq!(start, utc("2020-03-26T00:00:00Z"));
+        assert_eq!(end, utc("2020-04-02T00:00:00Z"));
+    }
+
+    #[test]
+    fn aligns_to_the_first_event() {
+        let alignment = Alignment::FirstEvent;
+        let anchor = Some(utc("2020-03-28T10:17:23.500Z"));
+        let interval = Duration::minutes(30);
+        assert_eq!(
+            alignment.window(utc("2020-03-28T10:17:23.500Z"), interval, anchor).unwrap().0,
+            utc("2020-03-28T10:17:23Z")
+        );
+        assert_eq!(
+            alignment.window(utc("2020-03-28T12:00:00Z"), interval, anchor).unwrap(),
+            (utc("2020-03-28T11:47:23Z"), utc("2020-03-28T12:17:23Z"))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        let now = utc("2020-03-28T16:29:04Z");
+        assert!(Alignment::Epoch.window(now, Duration::milliseconds(1500), None).is_err());
+        assert!(Alignment::Epoch.window(now, Duration::zero(), None).is_err());
+        assert!("midnight:Mars/Olympus".parse::<Alignment>().is_err());
+        assert_eq!(
+            "midnight:Europe/Berlin".parse::<Alignment>().unwrap().to_string(),
+            "midnight:Europe/Berlin"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use super::chimp_encoder::{index_bits, LEADING_ZEROS};
+use super::value_decoder::{take_u64, ValueDecoder};
+use super::values::ValueCodec;
+use crate::errors::{Result, RstzError};
+use bitvec::prelude::*;
+use serde_json::Value;
+
+/// Reverses `ChimpNEncoder`, values are XORed against one of the previous `N`.
+pub struct ChimpNDecoder<const N: usize> {
+    stored: Vec<u64>,
+    current: Option<usize>,
+    stored_leading: Option<u32>,
+}
+
+pub type ChimpDecoder = ChimpNDecoder<1>;
+pub type Chimp128Decoder = ChimpNDecoder<128>;
+
+impl<const N: usize> ValueDecoder for ChimpNDecoder<N> {
+    fn new() -> Self {
+        ChimpNDecoder {
+            stored: vec![0; N],
+            current: None,
+            stored_leading: None,
+        }
+    }
+
+    fn codec(&self) -> ValueCodec {
+        if N == 1 {
+            ValueCodec::Chimp
+        } else {
+            ValueCodec::Chimp128
+        }
+    }
+
+    fn decompress(&mut self, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<Value> {
+        self.decompress_bits(bitptr)
+            .map(|bits| Value::from(f64::from_bits(bits)))
+    }
+}
+
+impl<const N: usize> ChimpNDecoder<N> {
+    /// Reads the next value as raw `f64` bits, the counterpart of `ChimpNEncoder::compress_f64`.
+    pub(super) fn decompress_bits(&mut self, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<u64> {
+        let current = match self.current {
+            Some(current) => current,
+            None => {
+                let bits = take_u64(bitptr, 64)?;
+                self.stored[0] = bits;
+                self.current = Some(0);
+                return Ok(bits);
+            }
+        };
+        let bits = match take_u64(bitptr, 2)? {
+            0b00 => {
+                self.stored_leading = None;
+                self.stored[take_u64(bitptr, index_bits(N))? as usize]
+            }
+            0b01 => {
+                let reference = self.stored[take_u64(bitptr, index_bits(N))? as usize];
+                let leading = LEADING_ZEROS[take_u64(bitptr, 3)? as usize];
+                let significant = take_u64(bitptr, 6)? as u32;
+                if significant == 0 || leading + significant > 64 {
+                    return Err(RstzError::BadControlBits(format!(
+                        "{} leading zeros and {} significant bits.",
+                        leading, significant
+                    )));
+                }
+                self.stored_leading = None;
+                reference
+                    ^ (take_u64(bitptr, significant as usize)? << (64 - leading - significant))
+            }
+            control => {
+                let leading = if control == 0b10 {
+                    self.stored_leading.ok_or_else(|| {
+                        RstzError::BadControlBits(
+                            "Leading zeros reused before any were written.".to_string(),
+                        )
+                    })?
+                } else {
+                    LEADING_ZEROS[take_u64(bitptr, 3)? as usize]
+                };
+                self.stored_leading = Some(leading);
+                self.stored[current % N] ^ take_u64(bitptr, (64 - leading) as usize)?
+            }
+        };
+        self.stored[(current + 1) % N] = bits;
+        self.current = Some(current + 1);
+        Ok(bits)
+    }
+}
@@ -0,0 +1,173 @@
+use super::value_encoder::ValueEncoder;
+use super::values::ValueCodec;
+use crate::{errors::RstzError, events::LogEvent, path::FieldPath};
+use bitvec::prelude::*;
+
+/// Leading zero counts Chimp can write, a 3 bit index picks one.
+pub(super) const LEADING_ZEROS: [u32; 8] = [0, 8, 12, 16, 18, 20, 22, 24];
+
+/// Bits of the hashed low end of a value, used to find an earlier value sharing it.
+const HASH_BITS: u32 = 14;
+
+/// Chimp XOR compression against one of the previous `N` values.
+/// `N` is 1 for plain Chimp and 128 for Chimp128, which also writes the index of the value it used.
+pub struct ChimpNEncoder<const N: usize> {
+    // Ring of the last `N` values and, per low end hash, the position of the last value with it.
+    stored: Vec<u64>,
+    indices: Vec<usize>,
+    current: Option<usize>,
+    stored_leading: Option<u32>,
+    control: &'static str,
+    block: BitVec<Msb0, u8>,
+}
+
+pub type ChimpEncoder = ChimpNEncoder<1>;
+pub type Chimp128Encoder = ChimpNEncoder<128>;
+
+/// Bits of a ring index.
+pub(super) const fn index_bits(n: usize) -> usize {
+    (usize::BITS - 1 - n.leading_zeros()) as usize
+}
+
+/// Trailing zeros past which a value is written without them.
+pub(super) const fn threshold(n: usize) -> u32 {
+    6 + index_bits(n) as u32
+}
+
+fn hash(value: u64) -> usize {
+    (value & ((1 << HASH_BITS) - 1)) as usize
+}
+
+/// Index of the largest representable leading zero count not above `zeros`.
+pub(super) fn leading_index(zeros: u32) -> usize {
+    LEADING_ZEROS
+        .iter()
+        .rposition(|lz| *lz <= zeros)
+        .unwrap_or(0)
+}
+
+impl<const N: usize> ValueEncoder for ChimpNEncoder<N> {
+    fn new() -> Self {
+        let hashed = if N > 1 { 1 << HASH_BITS } else { 0 };
+        ChimpNEncoder {
+            stored: vec![0; N],
+            indices: vec![0; hashed],
+            current: None,
+            stored_leading: None,
+            control: "raw",
+            block: BitVec::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.current = None;
+        self.indices.iter_mut().for_each(|index| *index = 0);
+        self.stored_leading = None;
+        self.block.clear();
+    }
+
+    fn codec(&self) -> ValueCodec {
+        if N == 1 {
+            ValueCodec::Chimp
+        } else {
+            ValueCodec::Chimp128
+        }
+    }
+
+    fn control(&self) -> &'static str {
+        self.control
+    }
+
+    fn compress(
+        &mut self,
+        field: &FieldPath,
+        entry: &LogEvent,
+    ) -> Result<Option<&BitSlice<Msb0, u8>>, RstzError> {
+        let field_value = entry.get_path(field).ok_or(RstzError::from_none())?;
+        let num = field_value
+            .as_f64()
+            .ok_or(RstzError::new("Cannot represent JSON Value as f64."))?;
+        Ok(Some(self.compress_f64(num)))
+    }
+}
+
+impl<const N: usize> ChimpNEncoder<N> {
+    /// Encodes a bare float, any bit pattern round trips.
+    pub(super) fn compress_f64(&mut self, num: f64) -> &BitSlice<Msb0, u8> {
+        let value = num.to_bits();
+        self.block.clear();
+        let current = match self.current {
+            Some(current) => current,
+            None => {
+                self.control = "raw";
+                self.block.extend_from_raw_slice(&value.to_be_bytes());
+                self.store(0, value);
+                return self.block.as_bitslice();
+            }
+        };
+
+        let last = current % N;
+        let mut previous = last;
+        let mut xor = self.stored[last] ^ value;
+        if N > 1 {
+            let candidate = self.indices[hash(value)];
+            if current - candidate < N {
+                let candidate_xor = self.stored[candidate % N] ^ value;
+                if candidate_xor.trailing_zeros() > threshold(N) {
+                    previous = candidate % N;
+                    xor = candidate_xor;
+                }
+            }
+        }
+
+        let trailing = xor.trailing_zeros();
+        if xor == 0 {
+            self.control = "00";
+            self.push(0b00, 2);
+            self.push(previous as u64, index_bits(N));
+            self.stored_leading = None;
+        } else if trailing > threshold(N) {
+            self.control = "01";
+            let index = leading_index(xor.leading_zeros());
+            let significant = 64 - LEADING_ZEROS[index] - trailing;
+            self.push(0b01, 2);
+            self.push(previous as u64, index_bits(N));
+            self.push(index as u64, 3);
+            self.push(significant as u64, 6);
+            self.push(xor >> trailing, significant as usize);
+            self.stored_leading = None;
+        } else {
+            // Without enough trailing zeros the reference is always the previous value.
+            let xor = self.stored[last] ^ value;
+            let index = leading_index(xor.leading_zeros());
+            let leading = LEADING_ZEROS[index];
+            if self.stored_leading == Some(leading) {
+                self.control = "10";
+                self.push(0b10, 2);
+            } else {
+                self.control = "11";
+                self.push(0b11, 2);
+                self.push(index as u64, 3);
+                self.stored_leading = Some(leading);
+            }
+            self.push(xor, (64 - leading) as usize);
+        }
+        self.store(current + 1, value);
+        self.block.as_bitslice()
+    }
+
+    fn store(&mut self, position: usize, value: u64) {
+        self.stored[position % N] = value;
+        if N > 1 {
+            self.indices[hash(value)] = position;
+        }
+        self.current = Some(position);
+    }
+
+    /// Appends the `n` low bits of `bits`, most significant first.
+    fn push(&mut self, bits: u64, n: usize) {
+        for i in (0..n).rev() {
+            self.block.push((bits >> i) & 1 != 0);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use super::format;
+use crate::errors::{Result, RstzError};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// Bytes of the first header word, which stays readable whatever the compression.
+const WORD_BYTES: usize = 8;
+/// Compression id and uncompressed length, between the first word and the compressed bytes.
+const ENVELOPE_BYTES: usize = WORD_BYTES + 5;
+/// Blocks that would shrink more than this are stored as they are,
+/// so a few corrupt bytes never claim gigabytes once uncompressed.
+const MAX_RATIO: usize = 1024;
+const ZSTD_LEVEL: i32 = 3;
+
+/// General purpose compression of sealed blocks, after the value and timestamp codecs.
+/// Blocks it would not make smaller are stored as they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// LZ4 block format, fast to write and read.
+    Lz4,
+    /// Zstandard at level 3, smaller than LZ4 for a little more CPU.
+    Zstd,
+}
+
+impl Compression {
+    fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd),
+            _ => Err(RstzError::BadControlBits(format!(
+                "Unknown block compression {}.",
+                id
+            ))),
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => data.to_vec(),
+            Compression::Lz4 => lz4_flex::block::compress(data),
+            Compression::Zstd => {
+                zstd::bulk::compress(data, ZSTD_LEVEL).expect("Zstd compresses any input.")
+            }
+        }
+    }
+
+    fn decompress(&self, data: &[u8], len: usize) -> Result<Vec<u8>> {
+        let decompressed = match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => lz4_flex::block::decompress(data, len).map_err(|e| e.to_string()),
+            Compression::Zstd => zstd::bulk::decompress(data, len).map_err(|e| e.to_string()),
+        };
+        match decompressed {
+            Ok(decompressed) if decompressed.len() == len => Ok(decompressed),
+            Ok(_) => Err(RstzError::BadControlBits(
+                "Block is not the length its header says.".to_string(),
+            )),
+            Err(e) => Err(RstzError::BadControlBits(format!(
+                "{} block does not decompress: {}",
+                self, e
+            ))),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = RstzError;
+
+    /// `none`, `lz4` or `zstd`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(RstzError::new(&format!("Unknown compression: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+/// Compresses everything after the first word of a sealed block, marking the word as `V4` and
+/// following it with the compression and the uncompressed length. The block is returned as it is
+/// when that does not make it smaller.
+pub(super) fn pack(block: Vec<u8>, compression: Compression) -> Vec<u8> {
+    if compression == Compression::None || block.len() <= ENVELOPE_BYTES {
+        return block;
+    }
+    let rest = &block[WORD_BYTES..];
+    let len = match u32::try_from(rest.len()) {
+        Ok(len) => len,
+        Err(_) => return block,
+    };
+    let compressed = compression.compress(rest);
+    if ENVELOPE_BYTES + compressed.len() >= block.len()
+        || rest.len() > compressed.len().saturating_mul(MAX_RATIO)
+    {
+        return block;
+    }
+    let mut packed = Vec::with_capacity(ENVELOPE_BYTES + compressed.len());
+    packed.push(format::V4);
+    packed.extend_from_slice(&block[1..WORD_BYTES]);
+    packed.push(compression.id());
+    packed.extend_from_slice(&len.to_be_bytes());
+    packed.extend_from_slice(&compressed);
+    packed
+}
+
+/// Undoes `pack`, leaving the first word, `V4` included, before the decompressed rest.
+/// Blocks of other versions are borrowed as they are.
+pub(super) fn unpack(src: &[u8]) -> Result<(Compression, Cow<'_, [u8]>)> {
+    if src.first() != Some(&format::V4) {
+        return Ok((Compression::None, Cow::Borrowed(src)));
+    }
+    if src.len() < ENVELOPE_BYTES {
+        return Err(RstzError::BadControlBits(
+            "Compressed block is cut short.".to_string(),
+        ));
+    }
+    let compression = Compression::from_id(src[WORD_BYTES])?;
+    let mut len = [0; 4];
+    len.copy_from_slice(&src[WORD_BYTES + 1..ENVELOPE_BYTES]);
+    let len = u32::from_be_bytes(len) as usize;
+    let compressed = &src[ENVELOPE_BYTES..];
+    if len > compressed.len().saturating_mul(MAX_RATIO) {
+        return Err(RstzError::BadControlBits(format!(
+            "Compressed block claims {} bytes.",
+            len
+        )));
+    }
+    let mut block = src[..WORD_BYTES].to_vec();
+    block.extend(compression.decompress(compressed, len)?);
+    Ok((compression, Cow::Owned(block)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed(len: usize) -> Vec<u8> {
+        let mut block = vec![format::V3, 0x20, 0, 0, 0x5e, 0x7f, 0x9a, 0x80];
+        block.extend((0..len).map(|i| (i % 7) as u8));
+        block
+    }
+
+    #[test]
+    fn shrinks_blocks_that_compress() {
+        for compression in [Compression::Lz4, Compression::Zstd].iter().copied() {
+            let block = sealed(4000);
+            let packed = pack(block.clone(), compression);
+            assert!(packed.len() < block.len() / 10, "{}", compression);
+            assert_eq!(packed[0], format::V4);
+            assert_eq!(packed[1..8], block[1..8]);
+            let (found, unpacked) = unpack(&packed).unwrap();
+            assert_eq!(found, compression);
+            assert_eq!(unpacked[1..], block[1..]);
+            // Corrupt lengths and bytes are errors.
+            let mut longer = packed.clone();
+            longer[12] += 1;
+            assert!(unpack(&longer).is_err());
+            assert!(unpack(&packed[..packed.len() - 3]).is_err());
+            assert!(unpack(&packed[..10]).is_err());
+        }
+    }
+
+    #[test]
+    fn keeps_blocks_that_do_not() {
+        let block = sealed(3);
+        assert_eq!(pack(block.clone(), Compression::Zstd), block);
+        assert_eq!(pack(block.clone(), Compression::None), block);
+        assert_eq!(
+            unpack(&block).unwrap(),
+            (Compression::None, Cow::Borrowed(&block[..]))
+        );
+        let mut claims_too_much = pack(sealed(4000), Compression::Lz4);
+        claims_too_much[9] = 0xff;
+        assert!(unpack(&claims_too_much).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use super::decimal_encoder::{unscale, POW10};
+use super::value_decoder::{take, take_u64, ValueDecoder};
+use super::values::ValueCodec;
+use crate::errors::{Result, RstzError};
+use bitvec::prelude::*;
+use serde_json::Value;
+
+/// Reverses `DecimalEncoder`, integers are scaled back by the block's power of ten.
+pub struct DecimalDecoder {
+    exponent: usize,
+    last: i64,
+    width: Option<u32>,
+}
+
+impl ValueDecoder for DecimalDecoder {
+    fn new() -> Self {
+        DecimalDecoder {
+            exponent: 0,
+            last: 0,
+            width: None,
+        }
+    }
+
+    fn codec(&self) -> ValueCodec {
+        ValueCodec::Decimal
+    }
+
+    fn decompress(&mut self, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<Value> {
+        self.decompress_bits(bitptr)
+            .map(|bits| Value::from(f64::from_bits(bits)))
+    }
+}
+
+impl DecimalDecoder {
+    /// Reads the next value as raw `f64` bits, the counterpart of `DecimalEncoder::compress_f64`.
+    pub(super) fn decompress_bits(&mut self, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<u64> {
+        let delta = if !take(bitptr, 1)?[0] {
+            0
+        } else if !take(bitptr, 1)?[0] {
+            let width = self.width.ok_or_else(|| {
+                RstzError::BadControlBits("Width reused before one was written.".to_string())
+            })?;
+            unzigzag(take_u64(bitptr, width as usize)?)
+        } else if !take(bitptr, 1)?[0] {
+            unzigzag(self.take_sized(bitptr)?)
+        } else if !take(bitptr, 1)?[0] {
+            let exponent = take_u64(bitptr, 4)? as usize;
+            if exponent <= self.exponent || exponent >= POW10.len() {
+                return Err(RstzError::BadControlBits(format!(
+                    "Exponent {} after {}.",
+                    exponent, self.exponent
+                )));
+            }
+            self.exponent = exponent;
+            self.last = unzigzag(self.take_sized(bitptr)?);
+            return Ok(unscale(self.last, self.exponent).to_bits());
+        } else {
+            return take_u64(bitptr, 64);
+        };
+        self.last = self
+            .last
+            .checked_add(delta)
+            .ok_or_else(|| RstzError::BadControlBits("Decimal delta overflows.".to_string()))?;
+        Ok(unscale(self.last, self.exponent).to_bits())
+    }
+
+    fn take_sized(&mut self, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<u64> {
+        let width = take_u64(bitptr, 6)? as u32;
+        self.width = Some(width);
+        take_u64(bitptr, width as usize)
+    }
+}
+
+pub(super) fn unzigzag(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
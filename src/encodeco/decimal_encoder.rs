@@ -0,0 +1,177 @@
+use super::value_encoder::ValueEncoder;
+use super::values::ValueCodec;
+use crate::{errors::RstzError, events::LogEvent, path::FieldPath};
+use bitvec::prelude::*;
+
+/// Powers of ten a 4 bit exponent picks, all exact in an `f64`.
+pub(super) const POW10: [f64; 16] = [
+    1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15,
+];
+
+/// Largest scaled integer, beyond it integers are no longer exact in an `f64`.
+const MAX_SCALED: f64 = (1u64 << 53) as f64;
+
+/// Decimal values such as `12.5` or `0.37` stored as integers times a power of ten.
+/// The exponent of a block starts at 0 and grows with the first value needing more digits,
+/// integers are written as deltas packed in a reused bit width.
+/// Values with no short decimal form, such as `0.1 + 0.2`, are written raw as exceptions.
+pub struct DecimalEncoder {
+    exponent: usize,
+    last: i64,
+    width: Option<u32>,
+    control: &'static str,
+    block: BitVec<Msb0, u8>,
+}
+
+impl ValueEncoder for DecimalEncoder {
+    fn new() -> Self {
+        DecimalEncoder {
+            exponent: 0,
+            last: 0,
+            width: None,
+            control: "1111",
+            block: BitVec::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.exponent = 0;
+        self.last = 0;
+        self.width = None;
+        self.block.clear();
+    }
+
+    fn codec(&self) -> ValueCodec {
+        ValueCodec::Decimal
+    }
+
+    fn control(&self) -> &'static str {
+        self.control
+    }
+
+    fn compress(
+        &mut self,
+        field: &FieldPath,
+        entry: &LogEvent,
+    ) -> Result<Option<&BitSlice<Msb0, u8>>, RstzError> {
+        let field_value = entry.get_path(field).ok_or(RstzError::from_none())?;
+        let num = field_value
+            .as_f64()
+            .ok_or(RstzError::new("Cannot represent JSON Value as f64."))?;
+        Ok(Some(self.compress_f64(num)))
+    }
+}
+
+impl DecimalEncoder {
+    /// Encodes a bare float, any bit pattern round trips.
+    pub(super) fn compress_f64(&mut self, num: f64) -> &BitSlice<Msb0, u8> {
+        self.block.clear();
+        if let Some(scaled) = scale(num, self.exponent) {
+            let zigzag = zigzag(scaled.wrapping_sub(self.last));
+            let width = 64 - zigzag.leading_zeros();
+            match self.width {
+                _ if zigzag == 0 => {
+                    self.control = "0";
+                    self.push(0b0, 1);
+                }
+                // A new width costs 7 bits, it is only worth it when it saves more.
+                Some(reused) if width <= reused && reused - width < 7 => {
+                    self.control = "10";
+                    self.push(0b10, 2);
+                    self.push(zigzag, reused as usize);
+                }
+                _ => {
+                    self.control = "110";
+                    self.push(0b110, 3);
+                    self.push_sized(zigzag);
+                }
+            }
+            self.last = scaled;
+            return self.block.as_bitslice();
+        }
+
+        let rescaled = (self.exponent + 1..POW10.len())
+            .find_map(|exponent| scale(num, exponent).map(|scaled| (exponent, scaled)));
+        match rescaled {
+            Some((exponent, scaled)) => {
+                self.control = "1110";
+                self.push(0b1110, 4);
+                self.push(exponent as u64, 4);
+                self.push_sized(zigzag(scaled));
+                self.exponent = exponent;
+                self.last = scaled;
+            }
+            None => {
+                self.control = "1111";
+                self.push(0b1111, 4);
+                self.push(num.to_bits(), 64);
+            }
+        }
+        self.block.as_bitslice()
+    }
+
+    /// Writes `bits` behind its 6 bit width.
+    fn push_sized(&mut self, bits: u64) {
+        let width = 64 - bits.leading_zeros();
+        self.push(width as u64, 6);
+        self.push(bits, width as usize);
+        self.width = Some(width);
+    }
+
+    /// Appends the `n` low bits of `bits`, most significant first.
+    fn push(&mut self, bits: u64, n: usize) {
+        for i in (0..n).rev() {
+            self.block.push((bits >> i) & 1 != 0);
+        }
+    }
+}
+
+/// `num` times `10^exponent` when that integer reads back as exactly `num`.
+fn scale(num: f64, exponent: usize) -> Option<i64> {
+    let scaled = (num * POW10[exponent]).round();
+    if scaled.is_nan() || scaled.abs() > MAX_SCALED {
+        return None;
+    }
+    let scaled = scaled as i64;
+    Some(scaled).filter(|scaled| unscale(*scaled, exponent).to_bits() == num.to_bits())
+}
+
+pub(super) fn unscale(scaled: i64, exponent: usize) -> f64 {
+    scaled as f64 / POW10[exponent]
+}
+
+/// Maps small negative and positive integers alike to small unsigned ones.
+pub(super) fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_decimals_as_scaled_integers() {
+        let mut encoder = DecimalEncoder::new();
+        let mut controls = Vec::new();
+        for value in [12.0, 12.5, 12.5, 12.37, 12.41, 12.38, 0.1 + 0.2, 12.4].iter() {
+            let bits = encoder.compress_f64(*value).len();
+            controls.push((encoder.control(), bits));
+        }
+        assert_eq!(
+            controls,
+            vec![
+                // 12 in 5 bits, then 125 and 1237 once the exponent grows.
+                ("110", 3 + 6 + 5),
+                ("1110", 4 + 4 + 6 + 8),
+                ("0", 1),
+                ("1110", 4 + 4 + 6 + 12),
+                // +4 and -3, zigzagged to 8 and 5.
+                ("110", 3 + 6 + 4),
+                ("10", 2 + 4),
+                ("1111", 4 + 64),
+                ("10", 2 + 4),
+            ]
+        );
+        assert_eq!(encoder.exponent, 2);
+    }
+}
@@ -0,0 +1,140 @@
+use super::timestamps::TimestampCodec;
+use super::value_decoder::take_u64;
+use super::values::ValueCodec;
+use crate::errors::{Result, RstzError};
+use bitvec::prelude::*;
+
+/// Blocks written before versioning: the large delta-of-delta bucket holds 32 bits.
+pub const V1: u8 = 1;
+/// The large bucket holds 32 bits behind a `0` or 64 bits behind a `1`.
+pub const V2: u8 = 2;
+/// The byte after the version names the value codec in its high nibble and the timestamp codec in its low one,
+/// leaving 48 bits for the window start. Codec parameters, such as the bound of lossy values, follow that word.
+pub const V3: u8 = 3;
+/// A `V3` block whose bytes after the first word went through a general purpose `Compression`,
+/// named by the byte after that word, see `compression::pack`.
+pub const V4: u8 = 4;
+/// Version `TsEncoder` writes, blocks it compresses are `V4`.
+pub const CURRENT: u8 = V3;
+
+/// First word of a block header, along with the codec parameters after it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Header {
+    pub version: u8,
+    pub timestamps: TimestampCodec,
+    pub values: ValueCodec,
+    /// Window start in seconds.
+    pub start: i64,
+}
+
+/// Packs the version and codecs into the top bytes of the window start, which needs far fewer than 48 bits.
+/// Unversioned blocks hold a plain i64 there, so a top byte of `0x00` or `0xff` reads as version 1.
+pub(super) fn write(
+    block: &mut BitVec<Msb0, u8>,
+    timestamps: TimestampCodec,
+    values: ValueCodec,
+    start: i64,
+) {
+    let codecs = (values.id() << 4) | timestamps.id();
+    let word =
+        ((CURRENT as u64) << 56) | ((codecs as u64) << 48) | (start as u64 & 0x0000_ffff_ffff_ffff);
+    block.extend_from_raw_slice(&word.to_be_bytes());
+    values.write_parameters(block);
+}
+
+/// Reads the header off `bitptr`, leaving the first point offset.
+pub(super) fn read(bitptr: &mut &BitSlice<Msb0, u8>) -> Result<Header> {
+    let word = take_u64(bitptr, 64)?;
+    let codecs = (word >> 48) as u8;
+    let (version, timestamps, values, start) = match (word >> 56) as u8 {
+        0x00 | 0xff => (V1, TimestampCodec::Dod, ValueCodec::Gorilla, word as i64),
+        V2 => (
+            V2,
+            TimestampCodec::Dod,
+            ValueCodec::Gorilla,
+            ((word << 8) as i64) >> 8,
+        ),
+        version @ V3 | version @ V4 => (
+            version,
+            TimestampCodec::from_id(codecs & 0x0f)?,
+            ValueCodec::from_id(codecs >> 4, bitptr)?,
+            ((word << 16) as i64) >> 16,
+        ),
+        version => return Err(RstzError::UnsupportedVersion(version)),
+    };
+    Ok(Header {
+        version,
+        timestamps,
+        values,
+        start,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encodeco::ErrorBound;
+
+    fn unpack(word: u64) -> Result<Header> {
+        read(&mut word.to_be_bytes().view_bits())
+    }
+
+    #[test]
+    fn keeps_the_window_start() {
+        for start in [0, 1_585_411_200, -86_400, (1 << 47) - 1, -(1 << 47)]
+            .iter()
+            .copied()
+        {
+            let mut block = BitVec::new();
+            write(
+                &mut block,
+                TimestampCodec::Regular,
+                ValueCodec::Chimp128,
+                start,
+            );
+            let header = read(&mut block.as_bitslice()).unwrap();
+            assert_eq!(
+                (
+                    header.version,
+                    header.timestamps,
+                    header.values,
+                    header.start
+                ),
+                (
+                    CURRENT,
+                    TimestampCodec::Regular,
+                    ValueCodec::Chimp128,
+                    start
+                )
+            );
+            let legacy = unpack(start as u64).unwrap();
+            assert_eq!(
+                (
+                    legacy.version,
+                    legacy.timestamps,
+                    legacy.values,
+                    legacy.start
+                ),
+                (V1, TimestampCodec::Dod, ValueCodec::Gorilla, start)
+            );
+        }
+        let v2 = unpack((2 << 56) | 1_585_411_200).unwrap();
+        assert_eq!((v2.version, v2.start), (V2, 1_585_411_200));
+        assert_eq!(unpack(7 << 56), Err(RstzError::UnsupportedVersion(7)));
+        assert!(unpack((3 << 56) | (9 << 48)).is_err());
+        assert!(unpack((3 << 56) | (9 << 52)).is_err());
+    }
+
+    #[test]
+    fn keeps_codec_parameters() {
+        let lossy = ValueCodec::Lossy(ErrorBound::Absolute(0.25));
+        let mut block = BitVec::new();
+        write(&mut block, TimestampCodec::Dod, lossy, 1_585_411_200);
+        assert_eq!(block.len(), 64 + 72);
+        let mut slice = block.as_bitslice();
+        assert_eq!(read(&mut slice).map(|header| header.values), Ok(lossy));
+        assert!(slice.is_empty());
+        // The bound is cut short.
+        assert!(read(&mut &block[..100]).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use super::gorilla_encoder::MAX_LEADING_ZEROS;
+use super::value_decoder::{take, take_u64, ValueDecoder};
+use super::values::ValueCodec;
+use crate::errors::{Result, RstzError};
+use bitvec::prelude::*;
+use serde_json::Value;
+
+/// Reverses `GorillaEncoder`, values are XORed against the previous one.
+pub struct GorillaDecoder {
+    last_value: Option<u64>,
+    last_xor: Option<u64>,
+}
+
+impl ValueDecoder for GorillaDecoder {
+    fn new() -> Self {
+        GorillaDecoder {
+            last_value: None,
+            last_xor: None,
+        }
+    }
+
+    fn codec(&self) -> ValueCodec {
+        ValueCodec::Gorilla
+    }
+
+    fn decompress(&mut self, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<Value> {
+        self.decompress_bits(bitptr).map(|bits| Value::from(f64::from_bits(bits)))
+    }
+}
+
+impl GorillaDecoder {
+    /// Reads the next value as raw `f64` bits, the counterpart of `GorillaEncoder::compress_f64`.
+    pub(super) fn decompress_bits(&mut self, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<u64> {
+        let bits = match self.last_value {
+            None => take_u64(bitptr, 64)?,
+            Some(last_value) => {
+                let xor = if !take(bitptr, 1)?[0] {
+                    0
+                } else if !take(bitptr, 1)?[0] {
+                    let last_xor = self.last_xor.filter(|xor| *xor != 0).ok_or_else(|| {
+                        RstzError::BadControlBits("XOR window reused before one was written.".to_string())
+                    })?;
+                    let (zeros, trailing) = (last_xor.leading_zeros(), last_xor.trailing_zeros());
+                    take_u64(bitptr, (64 - zeros - trailing) as usize)? << trailing
+                } else {
+                    let zeros = take_u64(bitptr, 5)? as u32;
+                    let signif = match take_u64(bitptr, 6)? as u32 {
+                        0 => 64,
+                        s => s,
+                    };
+                    if zeros > MAX_LEADING_ZEROS || zeros + signif > 64 {
+                        return Err(RstzError::BadControlBits(format!(
+                            "{} leading zeros and {} significant bits.",
+                            zeros, signif
+                        )));
+                    }
+                    take_u64(bitptr, signif as usize)? << (64 - zeros - signif)
+                };
+                self.last_xor = Some(xor);
+                last_value ^ xor
+            }
+        };
+        self.last_value = Some(bits);
+        Ok(bits)
+    }
+}
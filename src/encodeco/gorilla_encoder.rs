@@ -1,11 +1,14 @@
 use super::value_encoder::ValueEncoder;
-use crate::{errors::RstzError, events::LogEvent};
+use super::values::ValueCodec;
+use crate::{errors::RstzError, events::LogEvent, path::FieldPath};
 use bitvec::prelude::*;
 
+pub(super) const MAX_LEADING_ZEROS: u32 = 31;
 
 pub struct GorillaEncoder {
     last_value: Option<f64>,
     last_xor: Option<u64>,
+    control: &'static str,
     block: BitVec<Msb0, u8>,
 }
 
@@ -14,6 +17,7 @@ impl ValueEncoder for GorillaEncoder {
         GorillaEncoder {
             last_value: None,
             last_xor: None,
+            control: "raw",
             block: BitVec::new(),
         }
     }
@@ -24,49 +28,70 @@ impl ValueEncoder for GorillaEncoder {
         self.block.clear();
     }
 
+    fn codec(&self) -> ValueCodec {
+        ValueCodec::Gorilla
+    }
+
+    fn control(&self) -> &'static str {
+        self.control
+    }
+
     fn compress(
         &mut self,
-        field: &str,
+        field: &FieldPath,
         entry: &LogEvent,
     ) -> Result<Option<&BitSlice<Msb0, u8>>, RstzError> {
-        let field_value = entry.get_value(&field).ok_or(RstzError::from_none())?;
+        let field_value = entry.get_path(field).ok_or(RstzError::from_none())?;
         let num = field_value
             .as_f64()
             .ok_or(RstzError::new("Cannot represent JSON Value as f64."))?;
-        
+        Ok(Some(self.compress_f64(num)))
+    }
+}
+
+impl GorillaEncoder {
+    /// Encodes a bare float, any bit pattern round trips, NaN payloads included.
+    /// JSON values cannot hold NaN or infinities, so only this path ever writes them.
+    pub(super) fn compress_f64(&mut self, num: f64) -> &BitSlice<Msb0, u8> {
         if let Some(last_value) = self.last_value {
             self.block.clear();
             let xor = fxor(last_value, num).to_bits();
             if xor == 0 {
+                self.control = "0";
                 self.block.push(false);
                 self.last_value = Some(num);
                 self.last_xor = Some(xor);
-                return Ok(Some(&self.block.as_bitslice()));
+                return self.block.as_bitslice();
             }
 
-            if self.last_xor.is_some()
-                && xor.leading_zeros() >= self.last_xor.unwrap().leading_zeros()
-                && xor.trailing_zeros() >= self.last_xor.unwrap().trailing_zeros()
-            {
+            let reuse_window = self.last_xor.filter(|last_xor| {
+                xor.leading_zeros() >= last_xor.leading_zeros()
+                    && xor.trailing_zeros() >= last_xor.trailing_zeros()
+            });
+            if let Some(last_xor) = reuse_window {
+                self.control = "10";
                 let mut aux = xor;
                 for _i in 0..64 {
                     self.block.push((aux & 1) != 0);
                     aux /= 2;
                 }
                 self.block
-                    .drain(((64 - self.last_xor.unwrap().leading_zeros()) as usize)..64);
+                    .drain(((64 - last_xor.leading_zeros()) as usize)..64);
                 self.block
-                    .drain(0..(self.last_xor.unwrap().trailing_zeros() as usize));
+                    .drain(0..(last_xor.trailing_zeros() as usize));
                 self.block.push(false);
             } else {
+                self.control = "11";
                 let mut aux = xor;
-                let mut zeros = xor.leading_zeros();
-                let mut signif = 64 - xor.trailing_zeros() - xor.leading_zeros();
+                // Only 5 bits are available for the leading zeros count.
+                let mut zeros = xor.leading_zeros().min(MAX_LEADING_ZEROS);
+                // 64 significant bits do not fit in 6 bits and are written as 0.
+                let mut signif = 64 - xor.trailing_zeros() - zeros;
                 for _i in 0..64 {
                     self.block.push((aux & 1) != 0);
                     aux /= 2;
                 }
-                self.block.drain(((64 - xor.leading_zeros()) as usize)..64);
+                self.block.drain(((64 - zeros) as usize)..64);
                 self.block.drain(0..(xor.trailing_zeros() as usize));
                 for _i in 0..6 {
                     self.block.push((signif & 1) != 0);
@@ -82,15 +107,16 @@ impl ValueEncoder for GorillaEncoder {
             self.block.reverse();
             self.last_xor = Some(xor);
         } else {
+            self.control = "raw";
             self.block.extend_from_raw_slice(&num.to_be_bytes());
         }
         self.last_value = Some(num);
-        Ok(Some(self.block.as_bitslice()))
+        self.block.as_bitslice()
     }
 }
 
 fn fxor(last_value: f64, value: f64) -> f64 {
     let lv_bytes = last_value.to_bits();
     let v_bytes = value.to_bits();
-    return f64::from_bits(lv_bytes ^ v_bytes);
+    f64::from_bits(lv_bytes ^ v_bytes)
 }
@@ -0,0 +1,119 @@
+use super::decimal_decoder::unzigzag;
+use super::gorilla_decoder::GorillaDecoder;
+use super::value_decoder::{take, take_u64, ValueDecoder};
+use super::values::ValueCodec;
+use crate::errors::{Result, RstzError};
+use crate::histogram::Histogram;
+use bitvec::prelude::*;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// Reverses `HistogramEncoder`, each histogram is rebuilt from the one before.
+pub struct HistogramDecoder {
+    last: Option<Histogram>,
+    sums: GorillaDecoder,
+}
+
+impl ValueDecoder for HistogramDecoder {
+    fn new() -> Self {
+        HistogramDecoder {
+            last: None,
+            sums: GorillaDecoder::new(),
+        }
+    }
+
+    fn codec(&self) -> ValueCodec {
+        ValueCodec::Histogram
+    }
+
+    fn decompress(&mut self, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<Value> {
+        self.decompress_histogram(bitptr)
+            .map(|histogram| histogram.to_value())
+    }
+}
+
+impl HistogramDecoder {
+    pub(super) fn decompress_histogram(
+        &mut self,
+        bitptr: &mut &BitSlice<Msb0, u8>,
+    ) -> Result<Histogram> {
+        let last = if !take(bitptr, 1)?[0] {
+            self.last.take().ok_or_else(|| {
+                RstzError::BadControlBits(
+                    "Histogram schema reused before one was written.".to_string(),
+                )
+            })?
+        } else {
+            Histogram {
+                schema: take_u64(bitptr, 8)? as u8 as i8,
+                ..Histogram::default()
+            }
+        };
+        let histogram = Histogram {
+            schema: last.schema,
+            sum: f64::from_bits(self.sums.decompress_bits(bitptr)?),
+            zero: last
+                .zero
+                .wrapping_add(unzigzag(take_golomb(bitptr)?) as u64),
+            positive: take_buckets(bitptr, &last.positive)?,
+            negative: take_buckets(bitptr, &last.negative)?,
+        }
+        .validate()
+        .map_err(|e| RstzError::BadControlBits(e.to_string()))?;
+        self.last = Some(histogram.clone());
+        Ok(histogram)
+    }
+}
+
+fn take_buckets(
+    bitptr: &mut &BitSlice<Msb0, u8>,
+    last: &BTreeMap<i32, u64>,
+) -> Result<BTreeMap<i32, u64>> {
+    let corrupt = || RstzError::BadControlBits("Histogram buckets out of range.".to_string());
+    let len = (last.len() as i64)
+        .checked_add(unzigzag(take_golomb(bitptr)?))
+        .and_then(|len| usize::try_from(len).ok())
+        // Each bucket takes at least two bits.
+        .filter(|len| *len <= bitptr.len() / 2)
+        .ok_or_else(corrupt)?;
+    let mut buckets = BTreeMap::new();
+    let mut previous: Option<i64> = None;
+    for _ in 0..len {
+        let index = match previous {
+            None => last
+                .keys()
+                .next()
+                .map_or(0, |first| *first as i64)
+                .checked_add(unzigzag(take_golomb(bitptr)?)),
+            Some(previous) => i64::try_from(take_golomb(bitptr)?)
+                .ok()
+                .and_then(|gap| previous.checked_add(gap)?.checked_add(1)),
+        }
+        .ok_or_else(corrupt)?;
+        previous = Some(index);
+        let index = i32::try_from(index).map_err(|_| corrupt())?;
+        let before = last.get(&index).copied().unwrap_or(0);
+        buckets.insert(
+            index,
+            before.wrapping_add(unzigzag(take_golomb(bitptr)?) as u64),
+        );
+    }
+    Ok(buckets)
+}
+
+/// Reads an Exp-Golomb code written by `HistogramEncoder::push_golomb`.
+fn take_golomb(bitptr: &mut &BitSlice<Msb0, u8>) -> Result<u64> {
+    let mut zeros = 0;
+    while !take(bitptr, 1)?[0] {
+        zeros += 1;
+        if zeros > 64 {
+            return Err(RstzError::BadControlBits(
+                "Exp-Golomb code longer than 64 bits.".to_string(),
+            ));
+        }
+    }
+    let coded = (1u128 << zeros) | take_u64(bitptr, zeros)? as u128;
+    u64::try_from(coded - 1)
+        .map_err(|_| RstzError::BadControlBits("Exp-Golomb code longer than 64 bits.".to_string()))
+}
@@ -0,0 +1,119 @@
+use super::decimal_encoder::zigzag;
+use super::gorilla_encoder::GorillaEncoder;
+use super::value_encoder::ValueEncoder;
+use super::values::ValueCodec;
+use crate::histogram::Histogram;
+use crate::{errors::RstzError, events::LogEvent, path::FieldPath};
+use bitvec::prelude::*;
+use std::collections::BTreeMap;
+
+/// Histograms as changes from the previous one of the block: a `0` when the schema is the same,
+/// or `1` and the schema, then the sum as Gorilla XORs and the zero and bucket counts as deltas.
+/// Integers are zigzagged where they may be negative and written as Exp-Golomb codes,
+/// so the unchanged counts of a steady distribution take a single bit each.
+pub struct HistogramEncoder {
+    last: Option<Histogram>,
+    sums: GorillaEncoder,
+    control: &'static str,
+    block: BitVec<Msb0, u8>,
+}
+
+impl ValueEncoder for HistogramEncoder {
+    fn new() -> Self {
+        HistogramEncoder {
+            last: None,
+            sums: GorillaEncoder::new(),
+            control: "1",
+            block: BitVec::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.last = None;
+        self.sums.reset();
+        self.block.clear();
+    }
+
+    fn codec(&self) -> ValueCodec {
+        ValueCodec::Histogram
+    }
+
+    fn control(&self) -> &'static str {
+        self.control
+    }
+
+    fn compress(
+        &mut self,
+        field: &FieldPath,
+        entry: &LogEvent,
+    ) -> Result<Option<&BitSlice<Msb0, u8>>, RstzError> {
+        let field_value = entry.get_path(field).ok_or(RstzError::from_none())?;
+        let histogram = Histogram::from_value(field_value)?;
+        Ok(Some(self.compress_histogram(histogram)))
+    }
+}
+
+impl HistogramEncoder {
+    pub(super) fn compress_histogram(&mut self, histogram: Histogram) -> &BitSlice<Msb0, u8> {
+        self.block.clear();
+        let last = self
+            .last
+            .take()
+            .filter(|last| last.schema == histogram.schema);
+        if last.is_some() {
+            self.control = "0";
+            self.block.push(false);
+        } else {
+            self.control = "1";
+            self.block.push(true);
+            self.push(histogram.schema as u8 as u64, 8);
+        }
+        let last = last.unwrap_or_default();
+        let sum = self.sums.compress_f64(histogram.sum);
+        self.block.extend_from_bitslice(sum);
+        self.push_golomb(zigzag(histogram.zero.wrapping_sub(last.zero) as i64));
+        self.push_buckets(&histogram.positive, &last.positive);
+        self.push_buckets(&histogram.negative, &last.negative);
+        self.last = Some(histogram);
+        self.block.as_bitslice()
+    }
+
+    /// The bucket count, then each index, the first relative to the last histogram's first and the
+    /// others relative to the one before, and each count relative to the same bucket last time.
+    fn push_buckets(&mut self, buckets: &BTreeMap<i32, u64>, last: &BTreeMap<i32, u64>) {
+        self.push_golomb(zigzag(buckets.len() as i64 - last.len() as i64));
+        let mut previous = None;
+        for (index, count) in buckets {
+            let index = *index as i64;
+            match previous {
+                None => {
+                    let first = last.keys().next().map_or(0, |first| *first as i64);
+                    self.push_golomb(zigzag(index - first));
+                }
+                Some(previous) => self.push_golomb((index - previous - 1) as u64),
+            }
+            previous = Some(index);
+            let before = last.get(&(index as i32)).copied().unwrap_or(0);
+            self.push_golomb(zigzag(count.wrapping_sub(before) as i64));
+        }
+    }
+
+    /// Writes `n + 1` behind as many zeros as it has bits after the first.
+    fn push_golomb(&mut self, n: u64) {
+        let coded = n as u128 + 1;
+        let width = 128 - coded.leading_zeros();
+        for _ in 1..width {
+            self.block.push(false);
+        }
+        for i in (0..width).rev() {
+            self.block.push((coded >> i) & 1 != 0);
+        }
+    }
+
+    /// Appends the `n` low bits of `bits`, most significant first.
+    fn push(&mut self, bits: u64, n: usize) {
+        for i in (0..n).rev() {
+            self.block.push((bits >> i) & 1 != 0);
+        }
+    }
+}
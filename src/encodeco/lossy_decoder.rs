@@ -0,0 +1,44 @@
+use super::gorilla_decoder::GorillaDecoder;
+use super::lossy_encoder::ErrorBound;
+use super::value_decoder::ValueDecoder;
+use super::values::ValueCodec;
+use crate::errors::{Result, RstzError};
+use bitvec::prelude::*;
+use serde_json::Value;
+
+/// Reverses `LossyEncoder`, values come back as rounded, within the bound of the block header.
+pub struct LossyDecoder {
+    bound: ErrorBound,
+    inner: GorillaDecoder,
+}
+
+impl ValueDecoder for LossyDecoder {
+    fn new() -> Self {
+        LossyDecoder {
+            bound: ErrorBound::MantissaBits(52),
+            inner: GorillaDecoder::new(),
+        }
+    }
+
+    fn codec(&self) -> ValueCodec {
+        ValueCodec::Lossy(self.bound)
+    }
+
+    /// Takes the bound of any lossy block.
+    fn begin(&mut self, codec: ValueCodec) -> Result<()> {
+        match codec {
+            ValueCodec::Lossy(bound) => {
+                self.bound = bound;
+                Ok(())
+            }
+            codec => Err(RstzError::BadControlBits(format!(
+                "{} values read as lossy.",
+                codec
+            ))),
+        }
+    }
+
+    fn decompress(&mut self, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<Value> {
+        self.inner.decompress(bitptr)
+    }
+}
@@ -0,0 +1,214 @@
+use super::gorilla_encoder::GorillaEncoder;
+use super::value_decoder::take_u64;
+use super::value_encoder::ValueEncoder;
+use super::values::ValueCodec;
+use crate::errors::{Result, RstzError};
+use crate::{events::LogEvent, path::FieldPath};
+use bitvec::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// How far a decoded value of a lossy block may be from the value that was encoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorBound {
+    /// At most this far in absolute terms.
+    Absolute(f64),
+    /// At most this fraction of the encoded value away.
+    Relative(f64),
+    /// Only this many of the 52 mantissa bits are kept, the rest are rounded off.
+    MantissaBits(u8),
+}
+
+impl ErrorBound {
+    /// Written after the first header word of lossy blocks, a kind byte and 64 bits of bound.
+    pub(super) fn write(&self, block: &mut BitVec<Msb0, u8>) {
+        let (kind, bound) = match self {
+            ErrorBound::Absolute(bound) => (0u8, bound.to_bits()),
+            ErrorBound::Relative(bound) => (1, bound.to_bits()),
+            ErrorBound::MantissaBits(bits) => (2, *bits as u64),
+        };
+        block.extend_from_raw_slice(&[kind]);
+        block.extend_from_raw_slice(&bound.to_be_bytes());
+    }
+
+    pub(super) fn read(bitptr: &mut &BitSlice<Msb0, u8>) -> Result<Self> {
+        let kind = take_u64(bitptr, 8)?;
+        let bound = take_u64(bitptr, 64)?;
+        let bound = match kind {
+            0 => ErrorBound::Absolute(f64::from_bits(bound)),
+            1 => ErrorBound::Relative(f64::from_bits(bound)),
+            2 if bound <= 52 => ErrorBound::MantissaBits(bound as u8),
+            _ => {
+                return Err(RstzError::BadControlBits(format!(
+                    "Unknown error bound {} of {}.",
+                    kind, bound
+                )))
+            }
+        };
+        bound.validate()
+    }
+
+    fn validate(self) -> Result<Self> {
+        let valid = match self {
+            ErrorBound::Absolute(bound) => bound.is_finite() && bound > 0.0,
+            ErrorBound::Relative(bound) => bound > 0.0 && bound < 1.0,
+            ErrorBound::MantissaBits(bits) => bits <= 52,
+        };
+        if valid {
+            Ok(self)
+        } else {
+            Err(RstzError::new(&format!("Invalid error bound: {}", self)))
+        }
+    }
+}
+
+impl FromStr for ErrorBound {
+    type Err = RstzError;
+
+    /// `abs:BOUND`, `rel:FRACTION` or `bits:MANTISSA_BITS`.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || RstzError::new(&format!("Invalid error bound: {}", s));
+        let bound = match s.split_once(':').ok_or_else(invalid)? {
+            ("abs", bound) => ErrorBound::Absolute(bound.parse().map_err(|_| invalid())?),
+            ("rel", bound) => ErrorBound::Relative(bound.parse().map_err(|_| invalid())?),
+            ("bits", bits) => ErrorBound::MantissaBits(bits.parse().map_err(|_| invalid())?),
+            _ => return Err(invalid()),
+        };
+        bound.validate()
+    }
+}
+
+impl fmt::Display for ErrorBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorBound::Absolute(bound) => write!(f, "abs:{}", bound),
+            ErrorBound::Relative(bound) => write!(f, "rel:{}", bound),
+            ErrorBound::MantissaBits(bits) => write!(f, "bits:{}", bits),
+        }
+    }
+}
+
+/// Gorilla over values rounded to within an error bound, so their XORs end in long runs of zeros.
+/// Values the rounding would move too far, such as near the largest floats, are kept exact.
+pub struct LossyEncoder {
+    bound: ErrorBound,
+    inner: GorillaEncoder,
+}
+
+impl LossyEncoder {
+    pub fn with_bound(bound: ErrorBound) -> Self {
+        LossyEncoder {
+            bound,
+            inner: GorillaEncoder::new(),
+        }
+    }
+
+    /// Encodes a bare float, within the bound of the encoder.
+    pub(super) fn compress_f64(&mut self, num: f64) -> &BitSlice<Msb0, u8> {
+        let quantized = quantize(num, self.bound);
+        self.inner.compress_f64(quantized)
+    }
+}
+
+impl ValueEncoder for LossyEncoder {
+    /// Keeps every mantissa bit, so nothing is lost until a bound is given.
+    fn new() -> Self {
+        LossyEncoder::with_bound(ErrorBound::MantissaBits(52))
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+
+    fn codec(&self) -> ValueCodec {
+        ValueCodec::Lossy(self.bound)
+    }
+
+    fn control(&self) -> &'static str {
+        self.inner.control()
+    }
+
+    fn compress(
+        &mut self,
+        field: &FieldPath,
+        entry: &LogEvent,
+    ) -> Result<Option<&BitSlice<Msb0, u8>>> {
+        let field_value = entry.get_path(field).ok_or(RstzError::from_none())?;
+        let num = field_value
+            .as_f64()
+            .ok_or(RstzError::new("Cannot represent JSON Value as f64."))?;
+        Ok(Some(self.compress_f64(num)))
+    }
+}
+
+/// `num` rounded as coarsely as `bound` allows, or `num` itself when rounding would break the bound.
+pub(super) fn quantize(num: f64, bound: ErrorBound) -> f64 {
+    if !num.is_finite() {
+        return num;
+    }
+    let (quantized, allowed) = match bound {
+        ErrorBound::Absolute(bound) => {
+            // A power of two step keeps the low mantissa bits of every multiple clear.
+            let step = 2f64.powi((2.0 * bound).log2().floor() as i32);
+            ((num / step).round() * step, bound)
+        }
+        ErrorBound::Relative(bound) => {
+            // Rounding to `bits` mantissa bits moves a value by at most 2^-(bits + 1) of it.
+            let bits = ((-bound.log2()).ceil() - 1.0).clamp(0.0, 52.0) as u32;
+            (round_mantissa(num, bits), bound * num.abs())
+        }
+        ErrorBound::MantissaBits(bits) => (
+            round_mantissa(num, bits as u32),
+            num.abs() / 2f64.powi(bits as i32 + 1),
+        ),
+    };
+    if quantized.is_finite() && (quantized - num).abs() <= allowed {
+        quantized
+    } else {
+        num
+    }
+}
+
+/// Rounds the mantissa of a finite `num` to its `bits` highest bits, half away from zero.
+fn round_mantissa(num: f64, bits: u32) -> f64 {
+    let dropped = 52 - bits.min(52);
+    if dropped == 0 {
+        return num;
+    }
+    let raw = num.to_bits() + (1u64 << (dropped - 1));
+    f64::from_bits(raw & !((1u64 << dropped) - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_within_the_bound() {
+        let cases = [
+            (ErrorBound::Absolute(0.01), 12.3456, 12.34375),
+            (ErrorBound::Absolute(0.5), -3.7, -4.0),
+            (ErrorBound::Relative(0.01), 1000.0, 1000.0),
+            (ErrorBound::Relative(0.1), 0.37, 0.375),
+            (ErrorBound::MantissaBits(2), 1.3, 1.25),
+            (ErrorBound::MantissaBits(0), 1.6, 2.0),
+            // Rounding up would overflow to infinity.
+            (ErrorBound::MantissaBits(0), f64::MAX, f64::MAX),
+        ];
+        for (bound, num, expected) in cases.iter().copied() {
+            assert_eq!(quantize(num, bound), expected, "{} of {}", bound, num);
+        }
+    }
+
+    #[test]
+    fn parses_bounds() {
+        assert_eq!("abs:0.5".parse(), Ok(ErrorBound::Absolute(0.5)));
+        assert_eq!("rel:0.001".parse(), Ok(ErrorBound::Relative(0.001)));
+        assert_eq!("bits:12".parse(), Ok(ErrorBound::MantissaBits(12)));
+        for invalid in ["abs:-1", "rel:2", "bits:53", "abs", "ulp:1"].iter() {
+            assert!(invalid.parse::<ErrorBound>().is_err(), "{}", invalid);
+        }
+        let bound = ErrorBound::Relative(0.001);
+        assert_eq!(bound.to_string().parse(), Ok(bound));
+    }
+}
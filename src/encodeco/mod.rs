@@ -1,8 +1,42 @@
+mod alignment;
+mod chimp_decoder;
+mod chimp_encoder;
+mod compression;
+mod decimal_decoder;
+mod decimal_encoder;
+pub mod format;
+mod gorilla_decoder;
 mod gorilla_encoder;
+mod histogram_decoder;
+mod histogram_encoder;
+mod lossy_decoder;
+mod lossy_encoder;
 mod value_encoder;
 mod ts_encoder;
+mod stats;
+mod stream;
+mod timestamps;
 mod ts_decoder;
 mod value_decoder;
+mod values;
+#[cfg(test)]
+mod roundtrip;
 
-pub use self::ts_encoder::TsEncoder;
+pub use self::alignment::Alignment;
+pub use self::compression::Compression;
+pub use self::ts_encoder::{BlockLimits, TsEncoder};
+pub use self::stats::{DodBucket, DodHistogram, EncoderStats};
+pub use self::timestamps::TimestampCodec;
+pub use self::stream::{BlockSink, PointStream};
+pub use self::ts_decoder::TSDecoder;
 pub use self::gorilla_encoder::GorillaEncoder;
+pub use self::gorilla_decoder::GorillaDecoder;
+pub use self::chimp_encoder::{Chimp128Encoder, ChimpEncoder, ChimpNEncoder};
+pub use self::chimp_decoder::{Chimp128Decoder, ChimpDecoder, ChimpNDecoder};
+pub use self::decimal_encoder::DecimalEncoder;
+pub use self::decimal_decoder::DecimalDecoder;
+pub use self::histogram_encoder::HistogramEncoder;
+pub use self::histogram_decoder::HistogramDecoder;
+pub use self::lossy_encoder::{ErrorBound, LossyEncoder};
+pub use self::lossy_decoder::LossyDecoder;
+pub use self::values::{AnyDecoder, AnyEncoder, ValueCodec};
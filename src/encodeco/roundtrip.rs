@@ -0,0 +1,463 @@
+//! Property based round trips through the encoders and decoders.
+
+use super::chimp_decoder::ChimpNDecoder;
+use super::chimp_encoder::ChimpNEncoder;
+use super::decimal_decoder::DecimalDecoder;
+use super::decimal_encoder::DecimalEncoder;
+use super::gorilla_decoder::GorillaDecoder;
+use super::gorilla_encoder::GorillaEncoder;
+use super::histogram_decoder::HistogramDecoder;
+use super::histogram_encoder::HistogramEncoder;
+use super::lossy_encoder::ErrorBound;
+use super::value_decoder::ValueDecoder;
+use super::value_encoder::ValueEncoder;
+use super::{
+    Alignment, AnyDecoder, AnyEncoder, BlockLimits, TSDecoder, TimestampCodec, TsEncoder, ValueCodec,
+};
+use crate::events::{DataPoint, LogEvent};
+use crate::histogram::{Histogram, MAX_SCHEMA, MIN_SCHEMA};
+use crate::path::FieldPath;
+use bitvec::prelude::*;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use proptest::prelude::*;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Delta-of-delta values on both sides of every bucket edge of `TsEncoder::encode_dod`.
+const DOD_BOUNDARIES: [i64; 24] = [
+    -1,
+    1,
+    -63,
+    -64,
+    63,
+    64,
+    65,
+    -255,
+    -256,
+    256,
+    257,
+    -2047,
+    -2048,
+    2048,
+    2049,
+    100_000,
+    -100_000,
+    i32::MAX as i64,
+    i32::MAX as i64 + 1,
+    i32::MIN as i64,
+    i32::MIN as i64 - 1,
+    // Ten years, forwards and backfilled.
+    315_360_000_000,
+    -315_360_000_000,
+    1_000_000_000,
+];
+
+/// 2020-04-08T00:00:00Z, the first event and so the start of its window.
+const WINDOW_START: i64 = 1_586_304_000_000;
+
+fn event(millis: i64, value: f64) -> LogEvent {
+    let mut values = BTreeMap::new();
+    values.insert("value".to_string(), Value::from(value));
+    LogEvent::new(Utc.timestamp_millis(millis), String::new(), values)
+}
+
+fn special_f64() -> impl Strategy<Value = f64> {
+    prop_oneof![
+        Just(0.0),
+        Just(-0.0),
+        Just(f64::NAN),
+        Just(-f64::NAN),
+        Just(f64::INFINITY),
+        Just(f64::NEG_INFINITY),
+        Just(f64::MIN_POSITIVE),
+        Just(f64::MIN_POSITIVE / 2.0),
+        Just(-f64::from_bits(1)),
+        Just(f64::MAX),
+        Just(f64::MIN),
+        Just(f64::EPSILON),
+    ]
+}
+
+/// Any bit pattern, NaN payloads included, with the special values and repeats weighted up.
+fn any_f64() -> impl Strategy<Value = f64> {
+    prop_oneof![
+        3 => any::<f64>(),
+        2 => any::<u64>().prop_map(f64::from_bits),
+        2 => special_f64(),
+        1 => (-1000i32..1000).prop_map(|n| n as f64 / 8.0),
+    ]
+}
+
+/// Decimals with up to 17 digits after the point, some past what the decimal codec scales.
+fn decimal_f64() -> impl Strategy<Value = f64> {
+    (-10_000_000i64..10_000_000, 0i32..18).prop_map(|(n, digits)| n as f64 / 10f64.powi(digits))
+}
+
+/// Values a JSON event can carry.
+fn finite_f64() -> impl Strategy<Value = f64> {
+    any_f64().prop_filter("JSON numbers are finite", |v| v.is_finite())
+}
+
+/// Gaps between points: regular, jittered, on the bucket edges, backfilled and huge jumps.
+fn gap() -> impl Strategy<Value = i64> {
+    prop_oneof![
+        4 => Just(1000i64),
+        2 => 0i64..3,
+        2 => 0i64..10_000,
+        2 => proptest::sample::select(DOD_BOUNDARIES.to_vec()).prop_map(|dod| 1000 + dod),
+        1 => -(60 * 24 * 3600 * 1000i64)..0,
+        1 => 0i64..(4000 * 24 * 3600 * 1000),
+    ]
+}
+
+fn alignment() -> impl Strategy<Value = Alignment> {
+    prop_oneof![
+        Just(Alignment::Epoch),
+        Just(Alignment::FirstEvent),
+        Just(Alignment::Midnight(chrono_tz::Tz::UTC)),
+        Just(Alignment::Midnight(chrono_tz::Tz::America__Sao_Paulo)),
+    ]
+}
+
+fn interval() -> impl Strategy<Value = Duration> {
+    prop_oneof![
+        Just(Duration::minutes(1)),
+        Just(Duration::hours(2)),
+        Just(Duration::days(1)),
+        Just(Duration::days(24)),
+        Just(Duration::days(400)),
+        Just(Duration::days(36_500)),
+    ]
+}
+
+fn timestamp_codec() -> impl Strategy<Value = TimestampCodec> {
+    prop_oneof![
+        Just(TimestampCodec::Dod),
+        Just(TimestampCodec::Seconds),
+        Just(TimestampCodec::Regular),
+    ]
+}
+
+fn value_codec() -> impl Strategy<Value = ValueCodec> {
+    prop_oneof![
+        Just(ValueCodec::Gorilla),
+        Just(ValueCodec::Chimp),
+        Just(ValueCodec::Chimp128),
+        Just(ValueCodec::Decimal),
+    ]
+}
+
+fn error_bound() -> impl Strategy<Value = ErrorBound> {
+    prop_oneof![
+        (1e-9f64..1e6).prop_map(ErrorBound::Absolute),
+        (1e-12f64..0.9).prop_map(ErrorBound::Relative),
+        (0u8..=52).prop_map(ErrorBound::MantissaBits),
+    ]
+}
+
+/// Histograms mostly sharing a schema and buckets near zero, so that deltas stay small.
+fn histogram() -> impl Strategy<Value = Histogram> {
+    let index = || prop_oneof![9 => -20i32..20, 1 => any::<i32>()];
+    let buckets = || prop::collection::btree_map(index(), any::<u64>(), 0..12);
+    (
+        prop_oneof![3 => Just(3i8), 1 => MIN_SCHEMA..=MAX_SCHEMA],
+        any::<u64>(),
+        finite_f64(),
+        buckets(),
+        buckets(),
+    )
+        .prop_map(|(schema, zero, sum, positive, negative)| Histogram {
+            schema,
+            zero,
+            sum,
+            positive,
+            negative,
+        })
+}
+
+fn points(start: i64, gaps: &[i64], values: &[f64]) -> Vec<(DateTime<Utc>, f64)> {
+    let mut millis = start;
+    gaps.iter()
+        .zip(values)
+        .map(|(gap, value)| {
+            millis += gap;
+            (Utc.timestamp_millis(millis), *value)
+        })
+        .collect()
+}
+
+fn roundtrip(
+    points: &[(DateTime<Utc>, f64)],
+    interval: Duration,
+    alignment: Alignment,
+    limits: BlockLimits,
+    timestamps: TimestampCodec,
+    values: ValueCodec,
+) -> (Vec<Vec<u8>>, Vec<DataPoint>) {
+    let mut encoder = TsEncoder::new(FieldPath::key("value"), interval)
+        .with_alignment(alignment)
+        .with_limits(limits)
+        .with_timestamps(timestamps)
+        .with_values(AnyEncoder::for_codec(values));
+    let mut blocks = Vec::new();
+    for (time, value) in points {
+        if let Some(block) = encoder
+            .compress(&event(time.timestamp_millis(), *value))
+            .unwrap()
+        {
+            blocks.push(block);
+        }
+    }
+    blocks.push(encoder.genblock());
+    let decoded = blocks
+        .iter()
+        .flat_map(|block| TSDecoder::<AnyDecoder>::new(block))
+        .collect();
+    (blocks, decoded)
+}
+
+/// Chimp over `values`, read back as raw bits.
+fn chimp_roundtrip<const N: usize>(values: &[f64]) -> Result<(), TestCaseError> {
+    let mut encoder = ChimpNEncoder::<N>::new();
+    let mut block: BitVec<Msb0, u8> = BitVec::new();
+    for value in values {
+        block.extend_from_bitslice(encoder.compress_f64(*value));
+    }
+    let mut decoder = ChimpNDecoder::<N>::new();
+    let mut slice = block.as_bitslice();
+    for value in values {
+        prop_assert_eq!(decoder.decompress_bits(&mut slice), Ok(value.to_bits()));
+    }
+    prop_assert!(slice.is_empty());
+    Ok(())
+}
+
+fn bits(point: &DataPoint) -> Option<u64> {
+    point.value().as_f64().map(f64::to_bits)
+}
+
+proptest! {
+    #[test]
+    fn values_roundtrip_bit_for_bit(values in prop::collection::vec(any_f64(), 1..200)) {
+        let mut encoder = GorillaEncoder::new();
+        let mut block: BitVec<Msb0, u8> = BitVec::new();
+        for value in &values {
+            block.extend_from_bitslice(encoder.compress_f64(*value));
+        }
+        let mut decoder = GorillaDecoder::new();
+        let mut slice = block.as_bitslice();
+        for value in &values {
+            prop_assert_eq!(decoder.decompress_bits(&mut slice), Ok(value.to_bits()));
+        }
+        prop_assert!(slice.is_empty());
+    }
+
+    #[test]
+    fn chimp_values_roundtrip_bit_for_bit(values in prop::collection::vec(any_f64(), 1..400)) {
+        chimp_roundtrip::<1>(&values)?;
+        chimp_roundtrip::<128>(&values)?;
+    }
+
+    #[test]
+    fn decimal_values_roundtrip_bit_for_bit(
+        values in prop::collection::vec(prop_oneof![3 => decimal_f64(), 1 => any_f64()], 1..200),
+    ) {
+        let mut encoder = DecimalEncoder::new();
+        let mut block: BitVec<Msb0, u8> = BitVec::new();
+        for value in &values {
+            block.extend_from_bitslice(encoder.compress_f64(*value));
+        }
+        let mut decoder = DecimalDecoder::new();
+        let mut slice = block.as_bitslice();
+        for value in &values {
+            prop_assert_eq!(decoder.decompress_bits(&mut slice), Ok(value.to_bits()));
+        }
+        prop_assert!(slice.is_empty());
+    }
+
+    #[test]
+    fn histograms_roundtrip_exactly(histograms in prop::collection::vec(histogram(), 1..50)) {
+        let mut encoder = HistogramEncoder::new();
+        let mut block: BitVec<Msb0, u8> = BitVec::new();
+        for histogram in &histograms {
+            block.extend_from_bitslice(encoder.compress_histogram(histogram.clone()));
+        }
+        let mut decoder = HistogramDecoder::new();
+        let mut slice = block.as_bitslice();
+        for histogram in &histograms {
+            prop_assert_eq!(decoder.decompress_histogram(&mut slice), Ok(histogram.clone()));
+        }
+        prop_assert!(slice.is_empty());
+    }
+
+    #[test]
+    fn series_roundtrip_exactly(
+        start in 1_000_000_000_000i64..2_000_000_000_000,
+        series in prop::collection::vec((gap(), finite_f64()), 1..300),
+        interval in interval(),
+        alignment in alignment(),
+        max_points in prop::option::of(1usize..64),
+        max_bytes in prop::option::of(1usize..512),
+        timestamps in timestamp_codec(),
+        values_codec in value_codec(),
+    ) {
+        let (mut gaps, values): (Vec<i64>, Vec<f64>) = series.into_iter().unzip();
+        let mut start = start;
+        if timestamps == TimestampCodec::Seconds {
+            start -= start % 1000;
+            gaps.iter_mut().for_each(|gap| *gap -= *gap % 1000);
+        }
+        let points = points(start, &gaps, &values);
+        let limits = BlockLimits { max_points, max_bytes };
+        let (blocks, decoded) = roundtrip(&points, interval, alignment, limits, timestamps, values_codec);
+
+        prop_assert_eq!(decoded.len(), points.len());
+        for ((time, value), point) in points.iter().zip(&decoded) {
+            prop_assert_eq!(point.timestamp(), *time);
+            // -0.0 == 0.0, compare the bits.
+            prop_assert_eq!(bits(point), Some(value.to_bits()));
+        }
+        for block in &blocks[..blocks.len() - 1] {
+            let count = TSDecoder::<AnyDecoder>::new(block).count();
+            prop_assert!(max_points.is_none_or(|max| count <= max));
+            prop_assert!(max_bytes.is_none_or(|max| count == 1 || block.len() <= max));
+        }
+    }
+
+    #[test]
+    fn lossy_series_stay_within_their_bound(
+        values in prop::collection::vec(prop_oneof![finite_f64(), decimal_f64()], 1..300),
+        bound in error_bound(),
+    ) {
+        let gaps = vec![1000; values.len()];
+        let points = points(WINDOW_START, &gaps, &values);
+        let (blocks, decoded) = roundtrip(
+            &points,
+            Duration::hours(2),
+            Alignment::FirstEvent,
+            BlockLimits::default(),
+            TimestampCodec::Dod,
+            ValueCodec::Lossy(bound),
+        );
+        prop_assert_eq!(crate::query::error_bounds(&blocks).unwrap(), vec![bound]);
+        prop_assert_eq!(decoded.len(), points.len());
+        for ((_, value), point) in points.iter().zip(&decoded) {
+            let error = (point.value().as_f64().unwrap() - value).abs();
+            let allowed = match bound {
+                ErrorBound::Absolute(bound) => bound,
+                ErrorBound::Relative(bound) => bound * value.abs(),
+                ErrorBound::MantissaBits(bits) => value.abs() / 2f64.powi(bits as i32 + 1),
+            };
+            prop_assert!(error <= allowed, "{} off {} under {}", error, value, bound);
+        }
+    }
+
+    #[test]
+    fn regular_series_roundtrip_exactly(
+        start in 1_000_000_000_000i64..2_000_000_000_000,
+        step in prop_oneof![Just(0i64), 1i64..100_000, Just(60_000)],
+        count in 1usize..500,
+        late in prop::option::of((0usize..500, 1i64..5000)),
+    ) {
+        let mut gaps = vec![step; count];
+        gaps[0] = 0;
+        if let Some((at, by)) = late {
+            if let Some(gap) = gaps.get_mut(at) {
+                *gap += by;
+            }
+        }
+        let values: Vec<f64> = (0..count).map(|i| (i % 4) as f64).collect();
+        let points = points(start, &gaps, &values);
+        let (_, decoded) = roundtrip(
+            &points,
+            Duration::days(400),
+            Alignment::FirstEvent,
+            BlockLimits::default(),
+            TimestampCodec::Regular,
+            ValueCodec::Chimp128,
+        );
+        let times: Vec<_> = decoded.iter().map(DataPoint::timestamp).collect();
+        let expected: Vec<_> = points.iter().map(|(time, _)| *time).collect();
+        prop_assert_eq!(times, expected);
+    }
+}
+
+#[test]
+fn every_dod_bucket_boundary_roundtrips() {
+    for dod in DOD_BOUNDARIES.iter().copied().chain(Some(0)) {
+        // Backfilled points stay in the open block, so a century long window holds all three.
+        let points = points(WINDOW_START, &[0, 1000, 1000 + dod], &[1.0, -0.0, 2.5]);
+        let (blocks, decoded) = roundtrip(
+            &points,
+            Duration::days(36_500),
+            Alignment::FirstEvent,
+            BlockLimits::default(),
+            TimestampCodec::Dod,
+            ValueCodec::Gorilla,
+        );
+        assert_eq!(blocks.len(), 1, "dod {}", dod);
+        let times: Vec<_> = decoded.iter().map(DataPoint::timestamp).collect();
+        let expected: Vec<_> = points.iter().map(|(time, _)| *time).collect();
+        assert_eq!(times, expected, "dod {}", dod);
+        assert_eq!(bits(&decoded[1]), Some((-0.0f64).to_bits()), "dod {}", dod);
+    }
+}
+
+#[test]
+fn dod_lands_in_the_expected_bucket() {
+    let cases = [
+        (0, "zero"),
+        (-63, "tiny"),
+        (64, "tiny"),
+        (-64, "small"),
+        (65, "small"),
+        (-255, "small"),
+        (256, "small"),
+        (-256, "medium"),
+        (257, "medium"),
+        (-2047, "medium"),
+        (2048, "medium"),
+        (-2048, "large"),
+        (2049, "large"),
+        (i32::MAX as i64, "large"),
+        (i32::MIN as i64, "large"),
+        (i32::MAX as i64 + 1, "huge"),
+        (i32::MIN as i64 - 1, "huge"),
+    ];
+    for (dod, bucket) in cases.iter().copied() {
+        // The first point sits on the window start, so the second delta-of-delta is a large 1_000_000.
+        let points = points(
+            WINDOW_START,
+            &[0, 1_000_000, 1_000_000 + dod],
+            &[1.0, 1.0, 1.0],
+        );
+        let mut encoder =
+            TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::days(36_500))
+                .with_alignment(Alignment::FirstEvent);
+        for (time, value) in &points {
+            encoder
+                .compress(&event(time.timestamp_millis(), *value))
+                .unwrap();
+        }
+        let mut expected = serde_json::json!({"zero": 0, "tiny": 0, "small": 0, "medium": 0, "large": 1, "huge": 0});
+        expected[bucket] = Value::from(expected[bucket].as_u64().unwrap() + 1);
+        assert_eq!(
+            serde_json::to_value(&encoder.stats().dod).unwrap(),
+            expected,
+            "dod {}",
+            dod
+        );
+    }
+}
+
+#[test]
+fn rejects_values_json_cannot_hold() {
+    // serde_json turns non-finite floats into null, which is not a number.
+    let mut encoder = TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::hours(2));
+    assert!(encoder
+        .compress(&event(1_585_413_000_000, f64::NAN))
+        .is_err());
+    assert!(encoder
+        .compress(&event(1_585_413_000_000, f64::INFINITY))
+        .is_err());
+}
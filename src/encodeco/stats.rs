@@ -0,0 +1,107 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io;
+
+/// Delta-of-delta buckets written by `TsEncoder`, from the single `0` bit up to the 64 bit escape.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DodBucket {
+    Zero,
+    Tiny,
+    Small,
+    Medium,
+    Large,
+    Huge,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DodHistogram {
+    pub zero: u64,
+    pub tiny: u64,
+    pub small: u64,
+    pub medium: u64,
+    pub large: u64,
+    pub huge: u64,
+}
+
+impl DodHistogram {
+    pub fn add(&mut self, bucket: DodBucket) {
+        match bucket {
+            DodBucket::Zero => self.zero += 1,
+            DodBucket::Tiny => self.tiny += 1,
+            DodBucket::Small => self.small += 1,
+            DodBucket::Medium => self.medium += 1,
+            DodBucket::Large => self.large += 1,
+            DodBucket::Huge => self.huge += 1,
+        }
+    }
+}
+
+/// Running totals of what an encoder wrote, kept across blocks.
+/// The open block is included, its padding only counts once it is sealed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EncoderStats {
+    pub points: u64,
+    /// Sealed blocks.
+    pub blocks: u64,
+    /// Window starts and first point offsets, 128 bits per block plus any codec parameters.
+    pub header_bits: u64,
+    pub timestamp_bits: u64,
+    pub value_bits: u64,
+    /// Padding of the last byte plus the byte counting it.
+    pub padding_bits: u64,
+    /// Sealed blocks the general purpose compression made smaller, and by how much.
+    pub compressed_blocks: u64,
+    pub compression_saved_bytes: u64,
+    pub dod: DodHistogram,
+    /// Values per control code of the value encoder, such as Gorilla's `0`, `10` and `11`.
+    pub value_controls: BTreeMap<&'static str, u64>,
+    /// Size of the same points written as JSON `DataPoint`s, not of the events they came from.
+    pub json_point_bytes: u64,
+    pub encoded_bytes: u64,
+    /// `json_point_bytes` over `encoded_bytes`.
+    pub compression_ratio: f64,
+}
+
+impl EncoderStats {
+    /// Fills in the totals derived from the counters.
+    pub(super) fn summarize(mut self) -> Self {
+        let bits = self.header_bits + self.timestamp_bits + self.value_bits + self.padding_bits;
+        self.encoded_bytes = bits.div_ceil(8) - self.compression_saved_bytes;
+        self.compression_ratio = if self.encoded_bytes == 0 {
+            0.0
+        } else {
+            self.json_point_bytes as f64 / self.encoded_bytes as f64
+        };
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct JsonPoint<'v> {
+    timestamp: DateTime<Utc>,
+    value: &'v Value,
+}
+
+struct ByteCounter(u64);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Length of the point as JSON, counted without building the string.
+pub(super) fn json_point_size(timestamp: DateTime<Utc>, value: &Value) -> u64 {
+    let mut counter = ByteCounter(0);
+    match serde_json::to_writer(&mut counter, &JsonPoint { timestamp, value }) {
+        Ok(()) => counter.0,
+        Err(_) => 0,
+    }
+}
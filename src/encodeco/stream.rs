@@ -0,0 +1,242 @@
+use super::ts_decoder::TSDecoder;
+use super::ts_encoder::TsEncoder;
+use super::value_decoder::ValueDecoder;
+use super::value_encoder::ValueEncoder;
+use crate::errors::{Result, RstzError};
+use crate::events::{DataPoint, LogEvent};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+/// Async face of `TsEncoder`: events are sent in as a `Sink` and sealed blocks come out as a `Stream`.
+/// At most `capacity` sealed blocks wait to be read, past that the sink stops accepting events.
+/// Closing the sink seals the open block and ends the stream once every block was read.
+pub struct BlockSink<E: ValueEncoder> {
+    encoder: TsEncoder<E>,
+    sealed: VecDeque<Vec<u8>>,
+    capacity: usize,
+    closed: bool,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+impl<E> BlockSink<E>
+where
+    E: ValueEncoder,
+{
+    pub fn new(encoder: TsEncoder<E>) -> Self {
+        BlockSink::with_capacity(encoder, 1)
+    }
+
+    pub fn with_capacity(encoder: TsEncoder<E>, capacity: usize) -> Self {
+        BlockSink {
+            encoder,
+            sealed: VecDeque::new(),
+            capacity: capacity.max(1),
+            closed: false,
+            reader: None,
+            writer: None,
+        }
+    }
+
+    fn push(&mut self, block: Vec<u8>) {
+        if block.is_empty() {
+            return;
+        }
+        self.sealed.push_back(block);
+        if let Some(reader) = self.reader.take() {
+            reader.wake();
+        }
+    }
+}
+
+impl<E> Sink<LogEvent> for BlockSink<E>
+where
+    E: ValueEncoder + Unpin,
+{
+    type Error = RstzError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if this.closed {
+            return Poll::Ready(Err(RstzError::new("Sink is closed.")));
+        }
+        if this.sealed.len() >= this.capacity {
+            this.writer = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, event: LogEvent) -> Result<()> {
+        let this = self.get_mut();
+        if let Some(block) = this.encoder.compress(&event)? {
+            this.push(block);
+        }
+        Ok(())
+    }
+
+    /// Events are encoded as they are sent, there is nothing buffered to flush.
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if !this.closed {
+            let block = this.encoder.genblock();
+            this.push(block);
+            this.closed = true;
+            if let Some(reader) = this.reader.take() {
+                reader.wake();
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<E> Stream for BlockSink<E>
+where
+    E: ValueEncoder + Unpin,
+{
+    type Item = Vec<u8>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        let this = self.get_mut();
+        match this.sealed.pop_front() {
+            Some(block) => {
+                if let Some(writer) = this.writer.take() {
+                    writer.wake();
+                }
+                Poll::Ready(Some(block))
+            }
+            None if this.closed => Poll::Ready(None),
+            None => {
+                this.reader = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Decodes a stream of blocks into `(timestamp millis, value)` pairs, one block at a time.
+/// A corrupt block or a non numeric value is yielded as an error and decoding goes on with the next block.
+pub struct PointStream<S, D: ValueDecoder> {
+    blocks: S,
+    current: Option<TSDecoder<D>>,
+}
+
+impl<S, D> PointStream<S, D>
+where
+    S: Stream<Item = Vec<u8>> + Unpin,
+    D: ValueDecoder,
+{
+    pub fn new(blocks: S) -> Self {
+        PointStream {
+            blocks,
+            current: None,
+        }
+    }
+}
+
+impl<S, D> Stream for PointStream<S, D>
+where
+    S: Stream<Item = Vec<u8>> + Unpin,
+    D: ValueDecoder + Unpin,
+{
+    type Item = Result<(i64, f64)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<(i64, f64)>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(decoder) = this.current.as_mut() {
+                match decoder.try_decompress() {
+                    Ok(Some(point)) => return Poll::Ready(Some(sample(&point))),
+                    Ok(None) => this.current = None,
+                    Err(e) => {
+                        this.current = None;
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                }
+            }
+            match Pin::new(&mut this.blocks).poll_next(cx) {
+                Poll::Ready(Some(block)) => this.current = Some(TSDecoder::new(&block)),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+fn sample(point: &DataPoint) -> Result<(i64, f64)> {
+    let value = point
+        .value()
+        .as_f64()
+        .ok_or_else(|| RstzError::new("Point value is not a number."))?;
+    Ok((point.timestamp().timestamp_millis(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encodeco::{GorillaDecoder, GorillaEncoder};
+    use crate::path::FieldPath;
+    use chrono::{Duration, TimeZone, Utc};
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+
+    fn event(i: i64) -> LogEvent {
+        let mut values = BTreeMap::new();
+        values.insert("value".to_string(), Value::from(i as f64 / 4.0));
+        LogEvent::new(Utc.timestamp(1_585_413_000 + i * 90, 0), String::new(), values)
+    }
+
+    #[tokio::test]
+    async fn encodes_and_decodes_through_streams() {
+        let encoder = TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::minutes(120));
+        let (mut sink, blocks) = BlockSink::new(encoder).split();
+        let writer = tokio::spawn(async move {
+            for i in 0..300 {
+                sink.send(event(i)).await.unwrap();
+            }
+            sink.close().await.unwrap();
+        });
+        let points: Vec<Result<(i64, f64)>> = PointStream::<_, GorillaDecoder>::new(blocks).collect().await;
+        writer.await.unwrap();
+
+        let points: Vec<(i64, f64)> = points.into_iter().map(Result::unwrap).collect();
+        let expected: Vec<(i64, f64)> = (0..300)
+            .map(|i| (event(i).datetime().timestamp_millis(), i as f64 / 4.0))
+            .collect();
+        assert_eq!(points, expected);
+    }
+
+    #[tokio::test]
+    async fn rejects_events_after_close() {
+        let encoder = TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::minutes(120));
+        let mut sink = BlockSink::new(encoder);
+        sink.send(event(0)).await.unwrap();
+        sink.close().await.unwrap();
+        assert!(sink.send(event(1)).await.is_err());
+        assert_eq!(sink.next().await.map(|b| b.is_empty()), Some(false));
+        assert_eq!(sink.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn yields_errors_for_corrupt_blocks() {
+        let mut encoder = TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::minutes(120));
+        encoder.compress(&event(0)).unwrap();
+        let good = encoder.genblock();
+        let mut corrupt = good.clone();
+        corrupt[0] = 0x07;
+        let blocks = futures_util::stream::iter(vec![corrupt, good]);
+        let points: Vec<Result<(i64, f64)>> = PointStream::<_, GorillaDecoder>::new(blocks).collect().await;
+
+        assert_eq!(points.len(), 2);
+        assert!(points[0].as_ref().unwrap_err().is_corrupt_block());
+        assert_eq!(points[1].as_ref().unwrap(), &(event(0).datetime().timestamp_millis(), 0.0));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::errors::{Result, RstzError};
+use std::fmt;
+use std::str::FromStr;
+
+/// How a block stores the timestamps of its points, recorded in the block header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampCodec {
+    /// Delta-of-delta in milliseconds.
+    #[default]
+    Dod,
+    /// Delta-of-delta in seconds, with the bucket sizes of the Gorilla paper.
+    /// Points must fall on whole seconds.
+    Seconds,
+    /// The first offset, a step and a count when every delta of the block is the same,
+    /// falling back to millisecond delta-of-delta otherwise.
+    Regular,
+}
+
+impl TimestampCodec {
+    /// Milliseconds per delta-of-delta unit.
+    pub(super) fn unit(&self) -> i64 {
+        match self {
+            TimestampCodec::Seconds => 1000,
+            TimestampCodec::Dod | TimestampCodec::Regular => 1,
+        }
+    }
+
+    pub(super) fn id(&self) -> u8 {
+        match self {
+            TimestampCodec::Dod => 0,
+            TimestampCodec::Seconds => 1,
+            TimestampCodec::Regular => 2,
+        }
+    }
+
+    pub(super) fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(TimestampCodec::Dod),
+            1 => Ok(TimestampCodec::Seconds),
+            2 => Ok(TimestampCodec::Regular),
+            _ => Err(RstzError::BadControlBits(format!(
+                "Unknown timestamp codec {}.",
+                id
+            ))),
+        }
+    }
+}
+
+impl FromStr for TimestampCodec {
+    type Err = RstzError;
+
+    /// `dod`, `seconds` or `regular`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dod" => Ok(TimestampCodec::Dod),
+            "seconds" => Ok(TimestampCodec::Seconds),
+            "regular" => Ok(TimestampCodec::Regular),
+            _ => Err(RstzError::new(&format!("Unknown timestamp codec: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for TimestampCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimestampCodec::Dod => f.write_str("dod"),
+            TimestampCodec::Seconds => f.write_str("seconds"),
+            TimestampCodec::Regular => f.write_str("regular"),
+        }
+    }
+}
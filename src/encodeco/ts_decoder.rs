@@ -1,109 +1,421 @@
-use super::value_decoder::ValueDecoder;
+use super::compression::{self, Compression};
+use super::format::{self, Header};
+use super::timestamps::TimestampCodec;
+use super::value_decoder::{take, take_u64, ValueDecoder};
+use super::values::ValueCodec;
+use crate::errors::{Result, RstzError};
 use crate::events::DataPoint;
 use bitvec::prelude::*;
 use chrono::{DateTime, Duration, TimeZone, Utc};
-use std::convert::TryFrom;
+
+/// Format version and window start in seconds, then the first point offset in milliseconds,
+/// without the codec parameters some blocks have in between.
+const HEADER_BITS: usize = 128;
 
 enum DtsRange {
     Tinny,
     Small,
     Medium,
-    Large
+    Large,
 }
 
-pub struct TSDecoder<'s, D: ValueDecoder> {
+/// Iterates over the points of a block sealed by `TsEncoder`.
+/// Corrupt or truncated blocks never panic, `try_decompress` reports why decoding stopped.
+pub struct TSDecoder<D: ValueDecoder> {
     block: BitVec<Msb0, u8>,
+    bitptr: usize,
     value_decoder: D,
     curtime: Option<DateTime<Utc>>,
-    bitptr: Option<&'s BitSlice<Msb0, u8>>,
     last_delta: Option<i64>,
+    version: u8,
+    compression: Compression,
+    codec: TimestampCodec,
+    // Step and points left of a regular block.
+    step: Option<i64>,
+    remaining: Option<u32>,
+    error: Option<RstzError>,
 }
 
-impl<'s, D> TSDecoder<'s, D>
+impl<D> TSDecoder<D>
 where
     D: ValueDecoder,
 {
     pub fn new(src: &[u8]) -> Self {
+        let mut block = BitVec::new();
+        let mut error = None;
+        let (compression, src) = match compression::unpack(src) {
+            Ok(unpacked) => unpacked,
+            Err(e) => {
+                error = Some(e);
+                (Compression::None, Default::default())
+            }
+        };
+        if let Some((padding, data)) = src.split_last() {
+            if *padding > 7 {
+                error = Some(RstzError::BadControlBits(format!(
+                    "{} padding bits in a byte.",
+                    padding
+                )));
+            } else {
+                block.extend_from_raw_slice(data);
+                let len = block.len().saturating_sub(*padding as usize);
+                block.truncate(len);
+            }
+        }
         TSDecoder {
-            block: BitVec::from_slice(src).expect("Slice to BitVec convertion error"),
-            value_decoder: ValueDecoder::new(),
+            block,
+            bitptr: 0,
+            value_decoder: D::new(),
             curtime: None,
-            bitptr: None,
             last_delta: None,
+            version: format::CURRENT,
+            compression,
+            codec: TimestampCodec::Dod,
+            step: None,
+            remaining: None,
+            error,
         }
     }
 
-    pub fn decompress(&'s mut self) -> Option<DataPoint> {
-        if let Some(bitslice) = self.bitptr {
-            if bitslice.is_empty() {
-                return None;
-            }
+    /// Start of the window this block belongs to.
+    pub fn header(&self) -> Result<DateTime<Utc>> {
+        self.read_header().map(|(_, start)| start)
+    }
+
+    /// Format version the block was written with, see `format`.
+    pub fn version(&self) -> Result<u8> {
+        self.read_header().map(|(header, _)| header.version)
+    }
+
+    /// General purpose compression the block was stored with, undone by `new`.
+    pub fn compression(&self) -> Result<Compression> {
+        self.read_header().map(|_| self.compression)
+    }
+
+    /// How the block stores its timestamps.
+    pub fn timestamps(&self) -> Result<TimestampCodec> {
+        self.read_header().map(|(header, _)| header.timestamps)
+    }
+
+    /// How the block stores its values.
+    pub fn values(&self) -> Result<ValueCodec> {
+        self.read_header().map(|(header, _)| header.values)
+    }
+
+    fn read_header(&self) -> Result<(Header, DateTime<Utc>)> {
+        if let Some(e) = &self.error {
+            return Err(e.clone());
         }
-        if let Some(curtime) = self.curtime {
-            let mut slice = self.bitptr.unwrap();
-            None
-        } else {
-            let mut time = Utc.timestamp(
-                i64::from_be_bytes(*<&[u8; 8]>::try_from(&self.block.as_raw_slice()[..8]).unwrap()),
-                0,
-            );
-            let mut slice = &self.block.as_bitslice()[64..];
-            let delta =
-                i64::from_be_bytes(*<&[u8; 8]>::try_from(&slice.as_raw_slice()[..8]).unwrap());
-            time = time + Duration::milliseconds(delta);
-            slice = &slice[64..];
-            let value = self.value_decoder.decompress(&mut slice);
-            self.curtime = Some(time);
-            self.last_delta = Some(delta);
-            self.bitptr = Some(slice);
-            Some(DataPoint::new(time, value))
-        }
-    }
-
-    fn decode_dod(slice: &'s mut BitSlice<Msb0, u8>) -> i64 {
-        if slice[0] == false {
-            slice = &mut slice[1..];
-            return 0;
-        }
-        let range = Self::decode_range(&mut slice);
-        match range {
-            Tinny => {
-                let dod_bits: BitSlice<Msb0, u8> = bits![mut 0; 7];
-                dod_bits.copy_from_bitslice(&slice[..7]);
-                
-                slice = &mut slice[7..];
+        window_start(&mut self.block.as_bitslice())
+    }
+
+    /// The next point, `None` once the block is exhausted or at the first corrupt bit.
+    /// Meant for blocks already known to decode, such as in benchmarks and tests,
+    /// reads that must not return part of a block use `try_decompress`.
+    pub fn decompress(&mut self) -> Option<DataPoint> {
+        self.try_decompress().ok().flatten()
+    }
+
+    /// The next point, `Ok(None)` once the block is exhausted.
+    /// After an error the decoder is exhausted too.
+    pub fn try_decompress(&mut self) -> Result<Option<DataPoint>> {
+        if let Some(e) = self.error.take() {
+            self.block.clear();
+            return Err(e);
+        }
+        match self.read_point() {
+            Ok(point) => Ok(point),
+            Err(e) => {
+                self.block.clear();
+                self.bitptr = 0;
+                Err(e)
             }
         }
     }
 
-    fn decode_range(slice: &'s mut BitSlice<Msb0, u8>) -> DtsRange {
-        if slice[1] == false {
-            slice = &mut slice[2..];
-            return DtsRange::Tinny;
+    fn read_point(&mut self) -> Result<Option<DataPoint>> {
+        let mut slice = &self.block.as_bitslice()[self.bitptr..];
+        match self.remaining {
+            Some(0) if !slice.is_empty() => {
+                return Err(RstzError::BadControlBits(
+                    "Bits left past the count of a regular block.".to_string(),
+                ))
+            }
+            Some(0) => return Ok(None),
+            None if slice.is_empty() => return Ok(None),
+            _ => {}
+        }
+        let (time, delta) = match (self.curtime, self.last_delta) {
+            (Some(curtime), Some(last_delta)) => {
+                let delta = match self.step {
+                    Some(step) => step,
+                    None => last_delta
+                        .checked_add(Self::decode_dod(&mut slice, self.version)?)
+                        .ok_or_else(|| RstzError::new("Timestamp delta overflows."))?,
+                };
+                (add_millis(curtime, self.scale(delta)?)?, delta)
+            }
+            _ => {
+                let (header, start) = window_start(&mut slice)?;
+                self.version = header.version;
+                self.codec = header.timestamps;
+                self.value_decoder.begin(header.values)?;
+                let offset = take_u64(&mut slice, 64)? as i64;
+                if header.timestamps == TimestampCodec::Regular {
+                    self.step = Some(take_u64(&mut slice, 64)? as i64);
+                    match take_u64(&mut slice, 32)? as u32 {
+                        0 => {
+                            return Err(RstzError::BadControlBits(
+                                "Regular block without points.".to_string(),
+                            ))
+                        }
+                        count => self.remaining = Some(count),
+                    }
+                }
+                (add_millis(start, offset)?, offset / header.timestamps.unit())
+            }
+        };
+        let value = self.value_decoder.decompress(&mut slice)?;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= 1;
         }
+        self.bitptr = self.block.len() - slice.len();
+        self.curtime = Some(time);
+        self.last_delta = Some(delta);
+        Ok(Some(DataPoint::new(time, value)))
+    }
+
+    /// Milliseconds in `delta` units of the block's timestamp codec.
+    fn scale(&self, delta: i64) -> Result<i64> {
+        delta
+            .checked_mul(self.codec.unit())
+            .ok_or_else(|| RstzError::new("Timestamp delta overflows."))
+    }
 
-        if slice[2] == false {
-            slice = &mut slice[3..];
-            return DtsRange::Small;
+    fn decode_dod(slice: &mut &BitSlice<Msb0, u8>, version: u8) -> Result<i64> {
+        if !take(slice, 1)?[0] {
+            return Ok(0);
         }
+        let (bits, overflow) = match Self::decode_range(slice)? {
+            DtsRange::Tinny => (7, 64),
+            DtsRange::Small => (9, 256),
+            DtsRange::Medium => (12, 2048),
+            DtsRange::Large if version == format::V1 || !take(slice, 1)?[0] => {
+                return Ok(take_u64(slice, 32)? as u32 as i32 as i64)
+            }
+            DtsRange::Large => return Ok(take_u64(slice, 64)? as i64),
+        };
+        let neg = take(slice, 1)?[0];
+        let magnitude = take_u64(slice, bits - 1)? as i64;
+        // A negative zero stands for the upper bound of the range, which does not fit in the magnitude bits.
+        Ok(match (neg, magnitude) {
+            (true, 0) => overflow,
+            (true, m) => -m,
+            (false, m) => m,
+        })
+    }
 
-        if slice[3] == false {
-            slice = &mut slice[4..];
-            return DtsRange::Medium;
+    fn decode_range(slice: &mut &BitSlice<Msb0, u8>) -> Result<DtsRange> {
+        if !take(slice, 1)?[0] {
+            return Ok(DtsRange::Tinny);
+        }
+        if !take(slice, 1)?[0] {
+            return Ok(DtsRange::Small);
+        }
+        if !take(slice, 1)?[0] {
+            return Ok(DtsRange::Medium);
         }
+        Ok(DtsRange::Large)
+    }
+}
 
-        slice = &mut slice[4..];
-        DtsRange::Large
+/// Reads the header, leaving the first point offset in `slice`.
+fn window_start(slice: &mut &BitSlice<Msb0, u8>) -> Result<(Header, DateTime<Utc>)> {
+    if slice.len() < HEADER_BITS {
+        return Err(RstzError::TruncatedHeader);
     }
+    let header = format::read(slice)?;
+    let start = Utc
+        .timestamp_opt(header.start, 0)
+        .single()
+        .ok_or_else(|| RstzError::new("Block header out of range."))?;
+    Ok((header, start))
+}
 
+fn add_millis(time: DateTime<Utc>, millis: i64) -> Result<DateTime<Utc>> {
+    time.checked_add_signed(Duration::milliseconds(millis))
+        .ok_or_else(|| RstzError::new("Timestamp out of range."))
 }
 
-/* impl<D> Iterator for TSDecoder<D>
+/// Points until the block is exhausted or corrupt, a truncated block simply ends early.
+/// See `decompress`.
+impl<D> Iterator for TSDecoder<D>
 where
     D: ValueDecoder,
 {
     type Item = DataPoint;
 
     fn next(&mut self) -> Option<DataPoint> {
+        self.decompress()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encodeco::{AnyDecoder, AnyEncoder, GorillaDecoder, GorillaEncoder, TsEncoder};
+    use crate::events::LogEvent;
+    use crate::path::FieldPath;
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+
+    fn block(points: i64) -> Vec<u8> {
+        let mut encoder =
+            TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::hours(2));
+        for i in 0..points {
+            let mut values = BTreeMap::new();
+            values.insert("value".to_string(), Value::from((i % 7) as f64 * 0.25));
+            let event = LogEvent::new(
+                Utc.timestamp_millis(1_585_413_000_000 + i * 1000 + i % 3),
+                String::new(),
+                values,
+            );
+            assert_eq!(encoder.compress(&event).unwrap(), None);
+        }
+        encoder.genblock()
+    }
+
+    fn decode_all(block: &[u8]) -> Result<usize> {
+        let mut decoder = TSDecoder::<AnyDecoder>::new(block);
+        let mut points = 0;
+        while decoder.try_decompress()?.is_some() {
+            points += 1;
+        }
+        Ok(points)
     }
-} */
+
+    #[test]
+    fn reports_truncated_headers() {
+        let mut short = block(1)[..10].to_vec();
+        short.push(0);
+        let mut decoder = TSDecoder::<GorillaDecoder>::new(&short);
+        assert_eq!(decoder.header(), Err(RstzError::TruncatedHeader));
+        assert_eq!(decoder.try_decompress(), Err(RstzError::TruncatedHeader));
+        assert_eq!(decoder.try_decompress(), Ok(None));
+    }
+
+    #[test]
+    fn reports_bad_control_bits() {
+        let mut padding = block(3);
+        *padding.last_mut().unwrap() = 9;
+        assert!(matches!(
+            decode_all(&padding),
+            Err(RstzError::BadControlBits(_))
+        ));
+
+        // A repeated value followed by a `10` reusing an XOR window that was never written.
+        let mut bits: BitVec<Msb0, u8> = BitVec::new();
+        bits.extend_from_raw_slice(&1_585_411_200i64.to_be_bytes());
+        bits.extend_from_raw_slice(&0i64.to_be_bytes());
+        bits.extend_from_raw_slice(&1.5f64.to_be_bytes());
+        for bit in [false, false, false, true, false, true, true].iter() {
+            bits.push(*bit);
+        }
+        let padding = (8 - bits.len() % 8) % 8;
+        let mut forged = bits.into_vec();
+        forged.push(padding as u8);
+        let mut decoder = TSDecoder::<GorillaDecoder>::new(&forged);
+        assert!(decoder.try_decompress().unwrap().is_some());
+        assert!(decoder.try_decompress().unwrap().is_some());
+        assert!(matches!(
+            decoder.try_decompress(),
+            Err(RstzError::BadControlBits(_))
+        ));
+    }
+
+    #[test]
+    fn reads_unversioned_blocks() {
+        // A plain i64 window start and a 32 bit large delta-of-delta, as written before versions.
+        let mut bits: BitVec<Msb0, u8> = BitVec::new();
+        bits.extend_from_raw_slice(&1_585_411_200i64.to_be_bytes());
+        bits.extend_from_raw_slice(&0i64.to_be_bytes());
+        bits.extend_from_raw_slice(&1.5f64.to_be_bytes());
+        for bit in [true, true, true, true].iter() {
+            bits.push(*bit);
+        }
+        bits.extend_from_raw_slice(&(-5000i32).to_be_bytes());
+        bits.push(false);
+        let padding = (8 - bits.len() % 8) % 8;
+        let mut legacy = bits.into_vec();
+        legacy.push(padding as u8);
+
+        let decoder = TSDecoder::<GorillaDecoder>::new(&legacy);
+        assert_eq!(decoder.version(), Ok(format::V1));
+        let times: Vec<_> = decoder.map(|p| p.timestamp()).collect();
+        assert_eq!(
+            times,
+            vec![
+                Utc.timestamp(1_585_411_200, 0),
+                Utc.timestamp(1_585_411_195, 0)
+            ]
+        );
+        assert_eq!(
+            TSDecoder::<GorillaDecoder>::new(&block(2)).version(),
+            Ok(format::CURRENT)
+        );
+    }
+
+    #[test]
+    fn reports_bit_overruns() {
+        let mut cut = block(20);
+        let padding = cut.pop().unwrap();
+        cut.truncate(cut.len() - 1);
+        cut.push(padding);
+        assert!(matches!(
+            decode_all(&cut),
+            Err(RstzError::BitOverrun { .. })
+        ));
+    }
+
+    #[test]
+    fn never_panics_on_corrupt_blocks() {
+        let valid = block(40);
+        assert_eq!(decode_all(&valid), Ok(40));
+        let mut encoder = TsEncoder::new(FieldPath::key("value"), Duration::hours(2))
+            .with_timestamps(TimestampCodec::Regular)
+            .with_values(AnyEncoder::for_codec(ValueCodec::Chimp128));
+        for i in 0..200 {
+            let mut values = BTreeMap::new();
+            values.insert("value".to_string(), Value::from(i as f64));
+            encoder
+                .compress(&LogEvent::new(
+                    Utc.timestamp(1_585_413_000 + i * 10, 0),
+                    String::new(),
+                    values,
+                ))
+                .unwrap();
+        }
+        let regular = encoder.genblock();
+        assert_eq!(decode_all(&regular), Ok(200));
+        assert_eq!(
+            TSDecoder::<GorillaDecoder>::new(&regular).timestamps(),
+            Ok(TimestampCodec::Regular)
+        );
+        // Decoders of a single codec refuse blocks of another.
+        assert!(matches!(
+            TSDecoder::<GorillaDecoder>::new(&regular).try_decompress(),
+            Err(RstzError::BadControlBits(_))
+        ));
+        for valid in [valid, regular].iter() {
+            for len in 0..valid.len() {
+                let _ = decode_all(&valid[..len]);
+            }
+            for bit in 0..valid.len() * 8 {
+                let mut flipped = valid.clone();
+                flipped[bit / 8] ^= 0x80 >> (bit % 8);
+                let _ = decode_all(&flipped);
+                let _ = TSDecoder::<AnyDecoder>::new(&flipped).header();
+            }
+        }
+    }
+}
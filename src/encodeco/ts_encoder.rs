@@ -1,8 +1,18 @@
+// bitarr! expands to transmutes between identical types in const context.
+#![allow(clippy::useless_transmute)]
+
+use super::alignment::Alignment;
+use super::compression::{self, Compression};
+use super::format;
+use super::stats::{json_point_size, DodBucket, EncoderStats};
+use super::timestamps::TimestampCodec;
 use super::value_encoder::ValueEncoder;
 use crate::errors::RstzError;
 use crate::events::LogEvent;
+use crate::path::FieldPath;
 use bitvec::prelude::*;
 use chrono::{DateTime, Duration, Utc};
+use std::convert::TryFrom;
 use std::ops::Range;
 
 const TINNY_DTS: BitArray<Msb0, [u8; 1]> = bitarr![const Msb0, u8; 1, 0];
@@ -19,14 +29,57 @@ const ENCODED_256_9: BitArray<Msb0, [u16; 1]> = bitarr![const Msb0, u16; 1, 0, 0
 const ENCODED_2048_12: BitArray<Msb0, [u16; 1]> =
     bitarr![const Msb0, u16; 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
 
+/// Step and point count of a regular block, in place of its delta-of-delta bits.
+const REGULAR_BITS: usize = 96;
+
+/// Bounds on a single block, whichever is reached first seals it.
+/// A window then spans several blocks, each one starting with the window header.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BlockLimits {
+    pub max_points: Option<usize>,
+    /// Size of the sealed block, padding byte included and before any `Compression`.
+    /// A block always holds at least one point.
+    pub max_bytes: Option<usize>,
+}
+
+impl BlockLimits {
+    fn exceeded(&self, points: usize, bits: usize) -> bool {
+        self.max_points.is_some_and(|max| points > max)
+            || self.max_bytes.is_some_and(|max| bits.div_ceil(8) + 1 > max)
+    }
+}
+
+/// Compresses one field of a series into time windowed blocks.
+/// A block holds the format version, timestamp and value codecs and window start in seconds, the first point
+/// offset in milliseconds and its value, followed by delta-of-delta timestamps and encoded values.
+/// Regular blocks hold a step and a count after the offset, then only values.
+/// Sealed blocks end with a byte counting the padding bits added to fill the last byte,
+/// and may then be compressed past their first word, see `Compression`.
 pub struct TsEncoder<E: ValueEncoder> {
     interval: Duration,
-    field: String,
+    alignment: Alignment,
+    limits: BlockLimits,
+    timestamps: TimestampCodec,
+    compression: Compression,
+    field: FieldPath,
     value_encoder: E,
-    cur_header: Option<DateTime<Utc>>,
+    anchor: Option<DateTime<Utc>>,
+    cur_end: Option<DateTime<Utc>>,
     last_delta: Option<i64>,
-    last_entry: Option<LogEvent>,
+    last_timestamp: Option<DateTime<Utc>>,
+    points: usize,
+    stats: EncoderStats,
     block: BitVec<Msb0, u8>,
+    window_start: i64,
+    // Header of the open block, codec parameters and first point offset included.
+    header_bits: usize,
+    block_timestamp_bits: usize,
+    // Value bits of the open block and its step while every delta matched, for regular blocks.
+    values: BitVec<Msb0, u8>,
+    step: Option<i64>,
+    regular: bool,
+    // Block sealed by an entry that then failed to open the next one, handed back by the next call.
+    pending: Option<Vec<u8>>,
 }
 
 impl<E> TsEncoder<E>
@@ -34,116 +87,321 @@ where
     E: ValueEncoder,
 {
     /// Returns a new encoder that can be used to compress series.
-    pub fn new(field: String, interval: Duration) -> Self {
+    /// `field` may point to a nested value, several encoders can be fed from the same events.
+    pub fn new(field: FieldPath, interval: Duration) -> Self {
         TsEncoder {
             interval,
+            alignment: Alignment::default(),
+            limits: BlockLimits::default(),
+            timestamps: TimestampCodec::default(),
+            compression: Compression::default(),
             field,
             value_encoder: ValueEncoder::new(),
-            cur_header: None,
+            anchor: None,
+            cur_end: None,
             last_delta: None,
-            last_entry: None,
+            last_timestamp: None,
+            points: 0,
+            stats: EncoderStats::default(),
             block: BitVec::new(),
+            window_start: 0,
+            header_bits: 0,
+            block_timestamp_bits: 0,
+            values: BitVec::new(),
+            step: None,
+            regular: false,
+            pending: None,
+        }
+    }
+
+    /// Windows start at midnight UTC unless told otherwise.
+    pub fn with_alignment(mut self, alignment: Alignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Blocks are only bounded by their window unless told otherwise.
+    pub fn with_limits(mut self, limits: BlockLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Timestamps are millisecond delta-of-deltas unless told otherwise.
+    pub fn with_timestamps(mut self, timestamps: TimestampCodec) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Sealed blocks are stored as they are unless told otherwise.
+    /// Blocks the compression does not make smaller are always stored as they are.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Values are compressed by `E::new()` unless told otherwise.
+    pub fn with_values(mut self, value_encoder: E) -> Self {
+        self.value_encoder = value_encoder;
+        self
+    }
+
+    /// Adds `entry` to the open block, returning the block it sealed if any.
+    /// A block sealed by an entry that fails to encode is kept and returned by the next call,
+    /// or by `genblock`.
+    pub fn compress(&mut self, entry: &LogEvent) -> Result<Option<Vec<u8>>, RstzError> {
+        match self.pending.take() {
+            // The open block is empty while a sealed one waits, so this entry cannot seal another.
+            Some(sealed) => match self.encode(entry) {
+                Ok(_) => Ok(Some(sealed)),
+                Err(e) => {
+                    self.pending = Some(sealed);
+                    Err(e)
+                }
+            },
+            None => self.encode(entry),
         }
     }
 
-    pub fn compress(&mut self, entry: LogEvent) -> Result<Option<Vec<u8>>, RstzError> {
-        match self.cur_header {
-            Some(header) => {
-                if entry.datetime().signed_duration_since(header) > self.interval {
-                    let result = self.block.clone().into_vec();
-                    self.block.clear();
-                    self.cur_header = None;
-                    Ok(Some(result))
+    fn encode(&mut self, entry: &LogEvent) -> Result<Option<Vec<u8>>, RstzError> {
+        match self.cur_end {
+            Some(end) => {
+                if entry.datetime() >= end {
+                    // The entry crossing the window opens the next block.
+                    self.seal_then_encode(entry)
                 } else {
-                    if let Some(last_delta) = self.last_delta {
-                        let last_entry = self
-                            .last_entry
-                            .as_ref()
-                            .expect("Bad gen state encountered.");
-                        let delta = entry
-                            .datetime()
-                            .signed_duration_since(last_entry.datetime())
-                            .num_milliseconds();
-                        let dod = delta - last_delta;
-                        self.encode_dod(dod);
-                        let value_encoded =
-                            self.value_encoder.compress(self.field.as_str(), &entry)?;
-                        if let Some(slice) = value_encoded {
-                            self.block.extend_from_bitslice(slice);
+                    let last_delta = self.last_delta.expect("Bad gen state encountered.");
+                    let last_timestamp = self.last_timestamp.expect("Bad gen state encountered.");
+                    // Deltas are taken between truncated timestamps so rounding never accumulates.
+                    let delta = (self.millis(entry)? - last_timestamp.timestamp_millis())
+                        / self.timestamps.unit();
+                    let value_encoded = self.value_encoder.compress(&self.field, entry)?;
+                    let sealed_len = self.block.len();
+                    let bucket = Self::encode_dod(&mut self.block, delta - last_delta);
+                    let timestamp_bits = self.block.len() - sealed_len;
+                    if let Some(slice) = value_encoded {
+                        self.block.extend_from_bitslice(slice);
+                    }
+                    if self.limits.exceeded(self.points + 1, self.block.len()) {
+                        // The point does not fit, it opens the next block of the same window instead.
+                        self.block.truncate(sealed_len);
+                        return self.seal_then_encode(entry);
+                    }
+                    self.stats.timestamp_bits += timestamp_bits as u64;
+                    self.stats.dod.add(bucket);
+                    self.block_timestamp_bits += timestamp_bits;
+                    if self.timestamps == TimestampCodec::Regular {
+                        self.values.extend_from_bitslice(&self.block[sealed_len + timestamp_bits..]);
+                        match self.step {
+                            Some(step) => self.regular &= step == delta,
+                            None => self.step = Some(delta),
                         }
                     }
+                    self.record(entry, self.block.len() - sealed_len - timestamp_bits);
+                    self.points += 1;
+                    self.last_delta = Some(delta);
+                    self.last_timestamp = Some(entry.datetime());
                     Ok(None)
                 }
             }
             None => {
-                let mut header = entry.date().and_hms(0, 0, 0);
-                while entry.datetime().signed_duration_since(header) > self.interval {
-                    header = header
-                        .checked_add_signed(self.interval)
-                        .ok_or(RstzError::from_none())?;
-                }
-                let delta = entry
-                    .datetime()
-                    .signed_duration_since(header)
-                    .num_milliseconds();
-                self.cur_header = Some(header);
-                self.last_delta = Some(delta);
-                self.block
-                    .extend_from_raw_slice(&header.timestamp().to_be_bytes());
+                let anchor = *self.anchor.get_or_insert(entry.datetime());
+                let (header, end) = self
+                    .alignment
+                    .window(entry.datetime(), self.interval, Some(anchor))?;
+                let delta = self.millis(entry)? - header.timestamp_millis();
+                // Regular blocks are written as delta-of-delta ones until they are sealed.
+                let layout = match self.timestamps {
+                    TimestampCodec::Regular => TimestampCodec::Dod,
+                    codec => codec,
+                };
+                let codec = self.value_encoder.codec();
+                // A value that fails to encode must not leave a header behind.
+                let value_encoded = self.value_encoder.compress(&self.field, entry)?;
+                format::write(&mut self.block, layout, codec, header.timestamp());
                 self.block.extend_from_raw_slice(&delta.to_be_bytes());
-                let value_encoded = self.value_encoder.compress(self.field.as_str(), &entry)?;
+                self.header_bits = self.block.len();
                 if let Some(slice) = value_encoded {
                     self.block.extend_from_bitslice(slice);
                 }
-                self.last_entry = Some(entry);
+                if self.timestamps == TimestampCodec::Regular {
+                    self.values.extend_from_bitslice(&self.block[self.header_bits..]);
+                    self.regular = true;
+                }
+                self.stats.header_bits += self.header_bits as u64;
+                self.record(entry, self.block.len() - self.header_bits);
+                self.window_start = header.timestamp();
+                self.cur_end = Some(end);
+                self.points = 1;
+                self.last_delta = Some(delta / self.timestamps.unit());
+                self.last_timestamp = Some(entry.datetime());
                 Ok(None)
             }
         }
     }
 
+    fn seal_then_encode(&mut self, entry: &LogEvent) -> Result<Option<Vec<u8>>, RstzError> {
+        let sealed = self.genblock();
+        if let Err(e) = self.encode(entry) {
+            self.pending = Some(sealed);
+            return Err(e);
+        }
+        Ok(Some(sealed))
+    }
+
+    /// End of the open block's window, the first instant that belongs to the next window.
+    pub fn window_end(&self) -> Option<DateTime<Utc>> {
+        self.cur_end
+    }
+
+    /// Seals the open block once `now` is more than `grace` past the end of its window,
+    /// so a series that went quiet does not keep its last block open.
+    pub fn tick(&mut self, now: DateTime<Utc>, grace: Duration) -> Option<Vec<u8>> {
+        if self.pending.is_some() {
+            return self.pending.take();
+        }
+        let end = self.window_end()?;
+        if now > end + grace {
+            Some(self.genblock())
+        } else {
+            None
+        }
+    }
+
+    /// Counts of what was written so far, across every block.
+    pub fn stats(&self) -> EncoderStats {
+        self.stats.clone().summarize()
+    }
+
+    /// Timestamp of `entry` in milliseconds, on a whole unit of the timestamp codec.
+    fn millis(&self, entry: &LogEvent) -> Result<i64, RstzError> {
+        let millis = entry.datetime().timestamp_millis();
+        if millis % self.timestamps.unit() != 0 {
+            return Err(RstzError::new(&format!(
+                "{} timestamps need points on whole seconds, got {}.",
+                self.timestamps,
+                entry.datetime()
+            )));
+        }
+        Ok(millis)
+    }
+
+    /// The open block in its regular layout, when every delta matched and that layout is smaller.
+    fn regular_block(&self) -> Option<BitVec<Msb0, u8>> {
+        let step = self.step.filter(|_| self.regular && self.block_timestamp_bits > REGULAR_BITS)?;
+        let count = u32::try_from(self.points).ok()?;
+        let mut block = BitVec::new();
+        format::write(&mut block, TimestampCodec::Regular, self.value_encoder.codec(), self.window_start);
+        block.extend_from_bitslice(&self.block[self.header_bits - 64..self.header_bits]);
+        block.extend_from_raw_slice(&step.to_be_bytes());
+        block.extend_from_raw_slice(&count.to_be_bytes());
+        block.extend_from_bitslice(&self.values);
+        Some(block)
+    }
+
+    fn record(&mut self, entry: &LogEvent, value_bits: usize) {
+        self.stats.points += 1;
+        self.stats.value_bits += value_bits as u64;
+        *self.stats.value_controls.entry(self.value_encoder.control()).or_default() += 1;
+        if let Some(value) = entry.get_path(&self.field) {
+            self.stats.json_point_bytes += json_point_size(entry.datetime(), value);
+        }
+    }
+
+    /// Seals the current block and resets the encoder.
+    /// A block still waiting to be handed back is returned first, the open block is empty then.
     pub fn genblock(&mut self) -> Vec<u8> {
-        let b = self.block.clone().into_vec();
+        if let Some(sealed) = self.pending.take() {
+            return sealed;
+        }
+        let regular = self.regular_block();
+        let sealed = regular.as_ref().unwrap_or(&self.block);
+        let b = seal(sealed);
+        if !b.is_empty() {
+            self.stats.blocks += 1;
+            self.stats.padding_bits += ((b.len() * 8) - sealed.len()) as u64;
+        }
+        let sealed_len = b.len();
+        let b = compression::pack(b, self.compression);
+        if b.len() < sealed_len {
+            self.stats.compressed_blocks += 1;
+            self.stats.compression_saved_bytes += (sealed_len - b.len()) as u64;
+        }
+        if regular.is_some() {
+            self.stats.timestamp_bits -= self.block_timestamp_bits as u64;
+            self.stats.timestamp_bits += REGULAR_BITS as u64;
+        }
         self.block.clear();
-        self.cur_header = None;
+        self.values.clear();
+        self.step = None;
+        self.regular = false;
+        self.block_timestamp_bits = 0;
+        self.cur_end = None;
         self.last_delta = None;
-        self.last_entry = None;
+        self.last_timestamp = None;
+        self.points = 0;
         self.value_encoder.reset();
         b
     }
 
-    fn encode_dod(&mut self, dod: i64) {
+    /// Returns the open block as if it was sealed, without resetting the encoder.
+    pub fn snapshot(&self) -> Vec<u8> {
+        if let Some(sealed) = &self.pending {
+            return sealed.clone();
+        }
+        match self.regular_block() {
+            Some(block) => seal(&block),
+            None => seal(&self.block),
+        }
+    }
+
+    fn encode_dod(block: &mut BitVec<Msb0, u8>, dod: i64) -> DodBucket {
         let mut neg: bool = false;
         let mut encode = 0;
         if dod == 0 {
-            self.block.push(false);
-            return;
+            block.push(false);
+            return DodBucket::Zero;
         }
+        let bucket;
         if TINNY_DTS_RANGE.contains(&dod) {
             encode = 7;
-            self.block.extend_from_bitslice(TINNY_DTS.as_bitslice());
+            bucket = DodBucket::Tiny;
+            block.extend_from_bitslice(&TINNY_DTS[..2]);
             if dod == 64 {
-                self.block.extend_from_bitslice(ENCODED_64_7.as_bitslice());
-                return;
+                block.extend_from_bitslice(&ENCODED_64_7[..7]);
+                return bucket;
             }
         } else if SMALL_DTS_RANGE.contains(&dod) {
             encode = 9;
-            self.block.extend_from_bitslice(SMALL_DTS.as_bitslice());
+            bucket = DodBucket::Small;
+            block.extend_from_bitslice(&SMALL_DTS[..3]);
             if dod == 256 {
-                self.block.extend_from_bitslice(ENCODED_256_9.as_bitslice());
-                return;
+                block.extend_from_bitslice(&ENCODED_256_9[..9]);
+                return bucket;
             }
         } else if MEDIUM_DTS_RANGE.contains(&dod) {
             encode = 12;
-            self.block.extend_from_bitslice(MEDIUM_DTS.as_bitslice());
+            bucket = DodBucket::Medium;
+            block.extend_from_bitslice(&MEDIUM_DTS[..4]);
             if dod == 2048 {
-                self.block
-                    .extend_from_bitslice(ENCODED_2048_12.as_bitslice());
-                return;
+                block
+                    .extend_from_bitslice(&ENCODED_2048_12[..12]);
+                return bucket;
             }
         } else {
-            self.block.extend_from_bitslice(LARGE_DTS.as_bitslice());
-            let d = dod as i32; // Following the paper specification here.
-            self.block.extend_from_raw_slice(&d.to_be_bytes());
+            block.extend_from_bitslice(&LARGE_DTS[..4]);
+            // The paper stops at 32 bits, about 24.8 days in milliseconds. Longer gaps take 64.
+            match i32::try_from(dod) {
+                Ok(d) => {
+                    bucket = DodBucket::Large;
+                    block.push(false);
+                    block.extend_from_raw_slice(&d.to_be_bytes());
+                }
+                Err(_) => {
+                    bucket = DodBucket::Huge;
+                    block.push(true);
+                    block.extend_from_raw_slice(&dod.to_be_bytes());
+                }
+            }
         }
 
         if encode != 0 {
@@ -160,7 +418,302 @@ where
             }
             res.push(neg);
             res.reverse();
-            self.block.append(&mut res);
+            block.append(&mut res);
+        }
+        bucket
+    }
+}
+
+/// Appends the byte counting the padding bits that fill the last byte.
+fn seal(block: &BitSlice<Msb0, u8>) -> Vec<u8> {
+    if block.is_empty() {
+        return Vec::new();
+    }
+    let padding = (8 - block.len() % 8) % 8;
+    let mut b = block.to_bitvec().into_vec();
+    b.push(padding as u8);
+    b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encodeco::{GorillaDecoder, GorillaEncoder, TSDecoder};
+    use crate::events::DataPoint;
+    use crate::query;
+    use chrono::TimeZone;
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+
+    // Points one to three milliseconds apart with a cycling value, so both take a few bits.
+    fn event(i: i64) -> LogEvent {
+        let mut values = BTreeMap::new();
+        values.insert("value".to_string(), Value::from((i % 17) as f64 * 1.5));
+        LogEvent::new(Utc.timestamp_millis(1_585_413_000_000 + i * 2 + i % 2), String::new(), values)
+    }
+
+    fn encode(limits: BlockLimits, events: i64) -> Vec<Vec<u8>> {
+        let mut encoder =
+            TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::minutes(120)).with_limits(limits);
+        let mut blocks: Vec<Vec<u8>> = (0..events).filter_map(|i| encoder.compress(&event(i)).unwrap()).collect();
+        blocks.push(encoder.genblock());
+        blocks
+    }
+
+    fn expected(events: i64) -> Vec<DataPoint> {
+        (0..events)
+            .map(|i| {
+                let e = event(i);
+                DataPoint::new(e.datetime(), e.values()["value"].clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bounds_points_per_block() {
+        let limits = BlockLimits {
+            max_points: Some(100),
+            max_bytes: None,
+        };
+        let blocks = encode(limits, 1050);
+        assert_eq!(blocks.len(), 11);
+        for (n, block) in blocks.iter().enumerate() {
+            let decoder = TSDecoder::<GorillaDecoder>::new(block);
+            let header = decoder.header();
+            assert_eq!(decoder.count(), if n < 10 { 100 } else { 50 });
+            assert_eq!(header, Ok(Utc.ymd(2020, 3, 28).and_hms(16, 0, 0)));
+        }
+        let (start, end) = (event(0).datetime(), event(1050).datetime());
+        assert_eq!(query::range(&blocks, start, end).unwrap(), expected(1050));
+    }
+
+    #[test]
+    fn range_fails_on_corrupt_blocks() {
+        let limits = BlockLimits {
+            max_points: Some(100),
+            max_bytes: None,
+        };
+        let mut blocks = encode(limits, 300);
+        let (start, end) = (event(0).datetime(), event(300).datetime());
+        let half = blocks[1].len() / 2;
+        blocks[1].truncate(half);
+        assert!(query::range(&blocks, start, end).is_err());
+        blocks[1] = vec![0x07; 16];
+        blocks[1].push(0);
+        assert_eq!(query::range(&blocks, start, end), Err(RstzError::UnsupportedVersion(0x07)));
+        assert_eq!(query::error_bounds_header(&blocks), Err(RstzError::UnsupportedVersion(0x07)));
+    }
+
+    #[test]
+    fn bounds_bytes_per_block() {
+        let limits = BlockLimits {
+            max_points: None,
+            max_bytes: Some(256),
+        };
+        let blocks = encode(limits, 2000);
+        assert!(blocks.len() > 2);
+        assert!(blocks.iter().all(|b| b.len() <= 256));
+        assert!(blocks[..blocks.len() - 1].iter().all(|b| b.len() > 240));
+        let (start, end) = (event(0).datetime(), event(2000).datetime());
+        assert_eq!(query::range(&blocks, start, end).unwrap(), expected(2000));
+    }
+
+    #[test]
+    fn first_limit_reached_wins() {
+        let limits = BlockLimits {
+            max_points: Some(50),
+            max_bytes: Some(4096),
+        };
+        assert!(encode(limits, 500).iter().all(|b| TSDecoder::<GorillaDecoder>::new(b).count() == 50));
+
+        // Every block keeps at least one point, even when that point alone is over the limit.
+        let limits = BlockLimits {
+            max_points: None,
+            max_bytes: Some(1),
+        };
+        let blocks = encode(limits, 5);
+        assert_eq!(blocks.len(), 5);
+        let (start, end) = (event(0).datetime(), event(5).datetime());
+        assert_eq!(query::range(&blocks, start, end).unwrap(), expected(5));
+    }
+
+    #[test]
+    fn counts_what_it_writes() {
+        let limits = BlockLimits {
+            max_points: Some(100),
+            max_bytes: None,
+        };
+        let mut encoder =
+            TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::minutes(120)).with_limits(limits);
+        let mut blocks: Vec<Vec<u8>> = (0..250).filter_map(|i| encoder.compress(&event(i)).unwrap()).collect();
+        blocks.push(encoder.genblock());
+        let stats = encoder.stats();
+
+        assert_eq!(stats.points, 250);
+        assert_eq!(stats.blocks, 3);
+        assert_eq!(stats.header_bits, 3 * 128);
+        let dod = &stats.dod;
+        assert_eq!(dod.zero + dod.tiny + dod.small + dod.medium + dod.large + dod.huge, 250 - 3);
+        assert_eq!(stats.value_controls.values().sum::<u64>(), 250);
+        assert_eq!(stats.value_controls["raw"], 3);
+        let written: usize = blocks.iter().map(Vec::len).sum();
+        assert_eq!(stats.encoded_bytes, written as u64);
+        let json: usize = (0..250)
+            .map(|i| {
+                let event = event(i);
+                let value = event.get_path(&FieldPath::key("value")).unwrap().clone();
+                serde_json::to_vec(&DataPoint::new(event.datetime(), value)).unwrap().len()
+            })
+            .sum();
+        assert_eq!(stats.json_point_bytes, json as u64);
+        assert!(stats.compression_ratio > 1.0);
+    }
+
+    fn encode_with(timestamps: TimestampCodec, times: &[i64]) -> (Vec<Vec<u8>>, EncoderStats) {
+        let mut encoder = TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::minutes(120))
+            .with_timestamps(timestamps);
+        let mut blocks = Vec::new();
+        for (i, millis) in times.iter().enumerate() {
+            let mut values = BTreeMap::new();
+            values.insert("value".to_string(), Value::from((i % 5) as f64));
+            let event = LogEvent::new(Utc.timestamp_millis(*millis), String::new(), values);
+            blocks.extend(encoder.compress(&event).unwrap());
+        }
+        blocks.push(encoder.genblock());
+        (blocks, encoder.stats())
+    }
+
+    fn decoded_times(blocks: &[Vec<u8>]) -> Vec<i64> {
+        blocks
+            .iter()
+            .flat_map(|b| TSDecoder::<GorillaDecoder>::new(b))
+            .map(|p| p.timestamp().timestamp_millis())
+            .collect()
+    }
+
+    #[test]
+    fn compresses_sealed_blocks_past_their_first_word() {
+        let plain = encode(BlockLimits::default(), 2000);
+        for compression in [Compression::Lz4, Compression::Zstd].iter().copied() {
+            let mut encoder = TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::minutes(120))
+                .with_compression(compression);
+            let mut blocks: Vec<Vec<u8>> = (0..2000).filter_map(|i| encoder.compress(&event(i)).unwrap()).collect();
+            blocks.push(encoder.genblock());
+            assert_eq!(blocks.len(), 1);
+            assert!(blocks[0].len() < plain[0].len(), "{}", compression);
+            let decoder = TSDecoder::<GorillaDecoder>::new(&blocks[0]);
+            assert_eq!(decoder.version(), Ok(format::V4));
+            assert_eq!(decoder.compression(), Ok(compression));
+            assert_eq!(decoder.header(), TSDecoder::<GorillaDecoder>::new(&plain[0]).header());
+            assert_eq!(decoder.collect::<Vec<_>>(), expected(2000));
+            let stats = encoder.stats();
+            assert_eq!(stats.compressed_blocks, 1);
+            assert_eq!(stats.encoded_bytes, blocks[0].len() as u64);
+        }
+    }
+
+    #[test]
+    fn stores_regular_series_as_step_and_count() {
+        let times: Vec<i64> = (0..600).map(|i| 1_585_411_200_000 + 250 + i * 10_000).collect();
+        let (blocks, stats) = encode_with(TimestampCodec::Regular, &times);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(TSDecoder::<GorillaDecoder>::new(&blocks[0]).timestamps(), Ok(TimestampCodec::Regular));
+        assert_eq!(decoded_times(&blocks), times);
+        assert_eq!(stats.timestamp_bits, 96);
+        assert_eq!(stats.encoded_bytes, blocks[0].len() as u64);
+
+        // One late point and the block falls back to delta-of-delta.
+        let mut jittered = times.clone();
+        jittered[300] += 3;
+        let (blocks, _) = encode_with(TimestampCodec::Regular, &jittered);
+        assert_eq!(TSDecoder::<GorillaDecoder>::new(&blocks[0]).timestamps(), Ok(TimestampCodec::Dod));
+        assert_eq!(decoded_times(&blocks), jittered);
+    }
+
+    #[test]
+    fn counts_second_resolution_deltas_in_seconds() {
+        let times: Vec<i64> = (0..600).map(|i| 1_585_411_200_000 + (i * 10 + i % 3) * 1000).collect();
+        let (blocks, seconds) = encode_with(TimestampCodec::Seconds, &times);
+        assert_eq!(decoded_times(&blocks), times);
+        let (_, millis) = encode_with(TimestampCodec::Dod, &times);
+        assert!(seconds.timestamp_bits < millis.timestamp_bits / 2);
+
+        let mut encoder = TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::minutes(120))
+            .with_timestamps(TimestampCodec::Seconds);
+        assert!(encoder.compress(&event(1)).is_err());
+    }
+
+    #[test]
+    fn leaves_nothing_behind_for_values_it_rejects() {
+        let mut encoder = TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::minutes(120));
+        let mut values = BTreeMap::new();
+        values.insert("value".to_string(), Value::from("high"));
+        let text = LogEvent::new(event(0).datetime(), String::new(), values);
+        assert!(encoder.compress(&text).is_err());
+        for i in 1..4 {
+            assert_eq!(encoder.compress(&event(i)), Ok(None));
+        }
+        assert!(encoder.compress(&text).is_err());
+        let block = encoder.genblock();
+        assert_eq!(query::range(&[block], event(0).datetime(), event(4).datetime()), Ok(expected(4)[1..].to_vec()));
+    }
+
+    fn text(at: DateTime<Utc>) -> LogEvent {
+        let mut values = BTreeMap::new();
+        values.insert("value".to_string(), Value::from("high"));
+        LogEvent::new(at, String::new(), values)
+    }
+
+    #[test]
+    fn keeps_the_block_sealed_by_an_entry_it_rejects() {
+        let mut encoder = TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::minutes(120));
+        for i in 0..10 {
+            assert_eq!(encoder.compress(&event(i)), Ok(None));
+        }
+        let next_window = encoder.window_end().unwrap();
+        assert!(encoder.compress(&text(next_window)).is_err());
+        assert!(encoder.compress(&text(next_window)).is_err());
+        assert_eq!(encoder.snapshot(), encode(BlockLimits::default(), 10)[0]);
+
+        let later = LogEvent::new(next_window, String::new(), event(0).values().clone());
+        let sealed = encoder.compress(&later).unwrap().unwrap();
+        assert_eq!(query::range(&[sealed], event(0).datetime(), next_window), Ok(expected(10)));
+        let open = encoder.genblock();
+        assert_eq!(TSDecoder::<GorillaDecoder>::new(&open).count(), 1);
+
+        // Without a later entry, the sealed block comes back from `genblock`.
+        for i in 0..10 {
+            encoder.compress(&event(i)).unwrap();
+        }
+        assert!(encoder.compress(&text(next_window)).is_err());
+        let sealed = encoder.genblock();
+        assert_eq!(query::range(&[sealed], event(0).datetime(), next_window), Ok(expected(10)));
+        assert!(encoder.genblock().is_empty());
+    }
+
+    #[test]
+    fn keeps_full_blocks_when_the_next_entry_is_rejected() {
+        let limits = BlockLimits {
+            max_points: Some(5),
+            max_bytes: None,
+        };
+        let mut encoder =
+            TsEncoder::<GorillaEncoder>::new(FieldPath::key("value"), Duration::minutes(120)).with_limits(limits);
+        for i in 0..5 {
+            assert_eq!(encoder.compress(&event(i)), Ok(None));
+        }
+        assert!(encoder.compress(&text(event(5).datetime())).is_err());
+        let sealed = encoder.compress(&event(5)).unwrap().unwrap();
+        assert_eq!(query::range(&[sealed], event(0).datetime(), event(5).datetime()), Ok(expected(5)));
+
+        for i in 6..10 {
+            assert_eq!(encoder.compress(&event(i)), Ok(None));
         }
+        assert!(encoder.compress(&text(event(10).datetime())).is_err());
+        let sealed = encoder.genblock();
+        assert_eq!(
+            query::range(&[sealed], event(5).datetime(), event(10).datetime()),
+            Ok(expected(10)[5..].to_vec())
+        );
     }
 }
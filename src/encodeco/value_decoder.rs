@@ -1,7 +1,42 @@
+use super::values::ValueCodec;
+use crate::errors::{Result, RstzError};
 use serde_json::Value;
 use bitvec::prelude::*;
 
 pub trait ValueDecoder {
     fn new() -> Self;
-    fn decompress(&mut self, bitptr: &mut BitSlice<Msb0, u8>) -> Value;
+    fn codec(&self) -> ValueCodec;
+    /// Prepares for a block whose header names `codec`, decoders of a single codec reject the others.
+    fn begin(&mut self, codec: ValueCodec) -> Result<()> {
+        if codec == self.codec() {
+            Ok(())
+        } else {
+            Err(RstzError::BadControlBits(format!(
+                "{} values read as {}.",
+                codec,
+                self.codec()
+            )))
+        }
+    }
+    /// Reads the next value and advances `bitptr` past it, corrupt bits are an error.
+    fn decompress(&mut self, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<Value>;
+}
+
+/// Splits `n` bits off the front of `bitptr`.
+pub(super) fn take<'b>(bitptr: &mut &'b BitSlice<Msb0, u8>, n: usize) -> Result<&'b BitSlice<Msb0, u8>> {
+    if bitptr.len() < n {
+        return Err(RstzError::BitOverrun {
+            needed: n,
+            left: bitptr.len(),
+        });
+    }
+    let (head, tail) = bitptr.split_at(n);
+    *bitptr = tail;
+    Ok(head)
+}
+
+/// Reads `n` (at most 64) bits as an unsigned integer, most significant bit first.
+pub(super) fn take_u64(bitptr: &mut &BitSlice<Msb0, u8>, n: usize) -> Result<u64> {
+    let bits = take(bitptr, n)?;
+    Ok(bits.iter().fold(0u64, |acc, bit| (acc << 1) | (*bit as u64)))
 }
@@ -1,13 +1,19 @@
+use super::values::ValueCodec;
 use crate::errors::RstzError;
 use crate::events::LogEvent;
+use crate::path::FieldPath;
 use bitvec::prelude::*;
 
 pub trait ValueEncoder {
     fn new() -> Self;
     fn reset(&mut self);
+    /// Codec recorded in the header of the blocks this encoder writes values to.
+    fn codec(&self) -> ValueCodec;
+    /// Control code written ahead of the last compressed value, tallied in the encoder statistics.
+    fn control(&self) -> &'static str;
     fn compress(
         &mut self,
-        field: &str,
+        field: &FieldPath,
         entry: &LogEvent,
     ) -> Result<Option<&BitSlice<Msb0, u8>>, RstzError>;
 }
@@ -0,0 +1,244 @@
+use super::chimp_decoder::{Chimp128Decoder, ChimpDecoder};
+use super::chimp_encoder::{Chimp128Encoder, ChimpEncoder};
+use super::decimal_decoder::DecimalDecoder;
+use super::decimal_encoder::DecimalEncoder;
+use super::gorilla_decoder::GorillaDecoder;
+use super::gorilla_encoder::GorillaEncoder;
+use super::histogram_decoder::HistogramDecoder;
+use super::histogram_encoder::HistogramEncoder;
+use super::lossy_decoder::LossyDecoder;
+use super::lossy_encoder::{ErrorBound, LossyEncoder};
+use super::value_decoder::ValueDecoder;
+use super::value_encoder::ValueEncoder;
+use crate::errors::{Result, RstzError};
+use crate::events::LogEvent;
+use crate::path::FieldPath;
+use bitvec::prelude::*;
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+/// How a block stores the values of its points, recorded in the block header.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ValueCodec {
+    /// XOR against the previous value, reusing the last window of meaningful bits.
+    #[default]
+    Gorilla,
+    /// XOR against the previous value, with rounded leading zero counts.
+    Chimp,
+    /// Chimp against whichever of the last 128 values shares the most trailing bits.
+    Chimp128,
+    /// Integers times a power of ten picked per block, for values with few decimal digits.
+    Decimal,
+    /// Gorilla over values rounded within a bound, which the header records.
+    Lossy(ErrorBound),
+    /// Histograms, such as latency distributions, as changes from the previous one.
+    Histogram,
+}
+
+impl ValueCodec {
+    pub(super) fn id(&self) -> u8 {
+        match self {
+            ValueCodec::Gorilla => 0,
+            ValueCodec::Chimp => 1,
+            ValueCodec::Chimp128 => 2,
+            ValueCodec::Decimal => 3,
+            ValueCodec::Lossy(_) => 4,
+            ValueCodec::Histogram => 5,
+        }
+    }
+
+    /// Parameters written after the first header word, only lossy blocks have any.
+    pub(super) fn write_parameters(&self, block: &mut BitVec<Msb0, u8>) {
+        if let ValueCodec::Lossy(bound) = self {
+            bound.write(block);
+        }
+    }
+
+    /// The codec `id` names, reading its parameters off `bitptr`.
+    pub(super) fn from_id(id: u8, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<Self> {
+        match id {
+            0 => Ok(ValueCodec::Gorilla),
+            1 => Ok(ValueCodec::Chimp),
+            2 => Ok(ValueCodec::Chimp128),
+            3 => Ok(ValueCodec::Decimal),
+            4 => Ok(ValueCodec::Lossy(ErrorBound::read(bitptr)?)),
+            5 => Ok(ValueCodec::Histogram),
+            _ => Err(RstzError::BadControlBits(format!(
+                "Unknown value codec {}.",
+                id
+            ))),
+        }
+    }
+
+    /// How far decoded values may be from the encoded ones, `None` when they are exact.
+    pub fn error_bound(&self) -> Option<ErrorBound> {
+        match self {
+            ValueCodec::Lossy(bound) => Some(*bound),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ValueCodec {
+    type Err = RstzError;
+
+    /// `gorilla`, `chimp`, `chimp128`, `decimal`, `lossy:BOUND`, see `ErrorBound`, or `histogram`.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(bound) = s.strip_prefix("lossy:") {
+            return Ok(ValueCodec::Lossy(bound.parse()?));
+        }
+        match s {
+            "gorilla" => Ok(ValueCodec::Gorilla),
+            "chimp" => Ok(ValueCodec::Chimp),
+            "chimp128" => Ok(ValueCodec::Chimp128),
+            "decimal" => Ok(ValueCodec::Decimal),
+            "histogram" => Ok(ValueCodec::Histogram),
+            _ => Err(RstzError::new(&format!("Unknown value codec: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for ValueCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueCodec::Gorilla => f.write_str("gorilla"),
+            ValueCodec::Chimp => f.write_str("chimp"),
+            ValueCodec::Chimp128 => f.write_str("chimp128"),
+            ValueCodec::Decimal => f.write_str("decimal"),
+            ValueCodec::Lossy(bound) => write!(f, "lossy:{}", bound),
+            ValueCodec::Histogram => f.write_str("histogram"),
+        }
+    }
+}
+
+/// Value encoder picked at runtime, Gorilla unless built `for_codec` another one.
+pub enum AnyEncoder {
+    Gorilla(GorillaEncoder),
+    Chimp(ChimpEncoder),
+    Chimp128(Chimp128Encoder),
+    Decimal(DecimalEncoder),
+    Lossy(LossyEncoder),
+    Histogram(HistogramEncoder),
+}
+
+impl AnyEncoder {
+    pub fn for_codec(codec: ValueCodec) -> Self {
+        match codec {
+            ValueCodec::Gorilla => AnyEncoder::Gorilla(GorillaEncoder::new()),
+            ValueCodec::Chimp => AnyEncoder::Chimp(ChimpEncoder::new()),
+            ValueCodec::Chimp128 => AnyEncoder::Chimp128(Chimp128Encoder::new()),
+            ValueCodec::Decimal => AnyEncoder::Decimal(DecimalEncoder::new()),
+            ValueCodec::Lossy(bound) => AnyEncoder::Lossy(LossyEncoder::with_bound(bound)),
+            ValueCodec::Histogram => AnyEncoder::Histogram(HistogramEncoder::new()),
+        }
+    }
+}
+
+impl ValueEncoder for AnyEncoder {
+    fn new() -> Self {
+        AnyEncoder::for_codec(ValueCodec::default())
+    }
+
+    fn reset(&mut self) {
+        match self {
+            AnyEncoder::Gorilla(encoder) => encoder.reset(),
+            AnyEncoder::Chimp(encoder) => encoder.reset(),
+            AnyEncoder::Chimp128(encoder) => encoder.reset(),
+            AnyEncoder::Decimal(encoder) => encoder.reset(),
+            AnyEncoder::Lossy(encoder) => encoder.reset(),
+            AnyEncoder::Histogram(encoder) => encoder.reset(),
+        }
+    }
+
+    fn codec(&self) -> ValueCodec {
+        match self {
+            AnyEncoder::Gorilla(encoder) => encoder.codec(),
+            AnyEncoder::Chimp(encoder) => encoder.codec(),
+            AnyEncoder::Chimp128(encoder) => encoder.codec(),
+            AnyEncoder::Decimal(encoder) => encoder.codec(),
+            AnyEncoder::Lossy(encoder) => encoder.codec(),
+            AnyEncoder::Histogram(encoder) => encoder.codec(),
+        }
+    }
+
+    fn control(&self) -> &'static str {
+        match self {
+            AnyEncoder::Gorilla(encoder) => encoder.control(),
+            AnyEncoder::Chimp(encoder) => encoder.control(),
+            AnyEncoder::Chimp128(encoder) => encoder.control(),
+            AnyEncoder::Decimal(encoder) => encoder.control(),
+            AnyEncoder::Lossy(encoder) => encoder.control(),
+            AnyEncoder::Histogram(encoder) => encoder.control(),
+        }
+    }
+
+    fn compress(
+        &mut self,
+        field: &FieldPath,
+        entry: &LogEvent,
+    ) -> Result<Option<&BitSlice<Msb0, u8>>> {
+        match self {
+            AnyEncoder::Gorilla(encoder) => encoder.compress(field, entry),
+            AnyEncoder::Chimp(encoder) => encoder.compress(field, entry),
+            AnyEncoder::Chimp128(encoder) => encoder.compress(field, entry),
+            AnyEncoder::Decimal(encoder) => encoder.compress(field, entry),
+            AnyEncoder::Lossy(encoder) => encoder.compress(field, entry),
+            AnyEncoder::Histogram(encoder) => encoder.compress(field, entry),
+        }
+    }
+}
+
+/// Value decoder following the codec named by each block header.
+pub enum AnyDecoder {
+    Gorilla(GorillaDecoder),
+    Chimp(ChimpDecoder),
+    Chimp128(Chimp128Decoder),
+    Decimal(DecimalDecoder),
+    Lossy(LossyDecoder),
+    Histogram(HistogramDecoder),
+}
+
+impl ValueDecoder for AnyDecoder {
+    fn new() -> Self {
+        AnyDecoder::Gorilla(GorillaDecoder::new())
+    }
+
+    fn codec(&self) -> ValueCodec {
+        match self {
+            AnyDecoder::Gorilla(decoder) => decoder.codec(),
+            AnyDecoder::Chimp(decoder) => decoder.codec(),
+            AnyDecoder::Chimp128(decoder) => decoder.codec(),
+            AnyDecoder::Decimal(decoder) => decoder.codec(),
+            AnyDecoder::Lossy(decoder) => decoder.codec(),
+            AnyDecoder::Histogram(decoder) => decoder.codec(),
+        }
+    }
+
+    fn begin(&mut self, codec: ValueCodec) -> Result<()> {
+        *self = match codec {
+            ValueCodec::Gorilla => AnyDecoder::Gorilla(GorillaDecoder::new()),
+            ValueCodec::Chimp => AnyDecoder::Chimp(ChimpDecoder::new()),
+            ValueCodec::Chimp128 => AnyDecoder::Chimp128(Chimp128Decoder::new()),
+            ValueCodec::Decimal => AnyDecoder::Decimal(DecimalDecoder::new()),
+            ValueCodec::Lossy(_) => AnyDecoder::Lossy(LossyDecoder::new()),
+            ValueCodec::Histogram => AnyDecoder::Histogram(HistogramDecoder::new()),
+        };
+        match self {
+            AnyDecoder::Lossy(decoder) => decoder.begin(codec),
+            AnyDecoder::Histogram(decoder) => decoder.begin(codec),
+            _ => Ok(()),
+        }
+    }
+
+    fn decompress(&mut self, bitptr: &mut &BitSlice<Msb0, u8>) -> Result<Value> {
+        match self {
+            AnyDecoder::Gorilla(decoder) => decoder.decompress(bitptr),
+            AnyDecoder::Chimp(decoder) => decoder.decompress(bitptr),
+            AnyDecoder::Chimp128(decoder) => decoder.decompress(bitptr),
+            AnyDecoder::Decimal(decoder) => decoder.decompress(bitptr),
+            AnyDecoder::Lossy(decoder) => decoder.decompress(bitptr),
+            AnyDecoder::Histogram(decoder) => decoder.decompress(bitptr),
+        }
+    }
+}
@@ -1,5 +1,4 @@
 use serde::{de, ser};
-use std;
 use std::fmt::{self, Display};
 
 pub type Result<T> = std::result::Result<T, RstzError>;
@@ -23,6 +22,18 @@ pub enum RstzError {
     Eof,
     StdIoError(String),
     NoneError,
+
+    // Corrupt blocks met while decoding, so bad input from disk or the network
+    // surfaces as an error instead of a panic.
+    TruncatedHeader,
+    BadControlBits(String),
+    BitOverrun { needed: usize, left: usize },
+    UnsupportedVersion(u8),
+
+    // Encrypted segments whose key the key provider does not have, named by its id,
+    // or whose records do not decrypt, because of a wrong key or tampered bytes.
+    UnknownKey(String),
+    DecryptionFailed(String),
 }
 
 impl ser::Error for RstzError {
@@ -44,6 +55,16 @@ impl Display for RstzError {
             RstzError::Eof => formatter.write_str("unexpected end of input"),
             RstzError::StdIoError(msg) => formatter.write_str(msg),
             RstzError::NoneError => formatter.write_str("Unespected option None value"),
+            RstzError::TruncatedHeader => formatter.write_str("Block ends inside its header."),
+            RstzError::BadControlBits(msg) => write!(formatter, "Bad control bits: {}", msg),
+            RstzError::BitOverrun { needed, left } => {
+                write!(formatter, "Block overrun, {} bits needed but {} left.", needed, left)
+            }
+            RstzError::UnsupportedVersion(version) => {
+                write!(formatter, "Unsupported block format version {}.", version)
+            }
+            RstzError::UnknownKey(id) => write!(formatter, "Unknown encryption key {:?}.", id),
+            RstzError::DecryptionFailed(msg) => write!(formatter, "Decryption failed: {}", msg),
         }
     }
 }
@@ -56,6 +77,12 @@ impl From<std::io::Error> for RstzError {
     }
 }
 
+impl From<serde_json::Error> for RstzError {
+    fn from(e: serde_json::Error) -> Self {
+        RstzError::Message(e.to_string())
+    }
+}
+
 impl RstzError {
     pub fn new(msg: &str) -> RstzError {
         RstzError::Message(msg.to_string())
@@ -64,4 +91,16 @@ impl RstzError {
     pub fn from_none() -> RstzError {
         RstzError::NoneError
     }
+
+    /// Whether the error comes from a stored block that does not decode,
+    /// rather than from the request that asked for it.
+    pub fn is_corrupt_block(&self) -> bool {
+        matches!(
+            self,
+            RstzError::TruncatedHeader
+                | RstzError::BadControlBits(_)
+                | RstzError::BitOverrun { .. }
+                | RstzError::UnsupportedVersion(_)
+        )
+    }
 }
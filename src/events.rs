@@ -3,9 +3,10 @@ use chrono::{Date, DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::path::FieldPath;
+
 use std::collections::BTreeMap;
 use std::fmt;
-use std::{fs::File, io::BufReader};
 
 ///Most basic implementation of a Log Event, contains the same caracteristics defined by vector.
 ///Timestamp and host fields are requierd.
@@ -27,8 +28,8 @@ impl LogEvent {
         }
     }
 
-    pub fn to_string(&self) -> String {
-        serde_json::ser::to_string(self).unwrap_or_default()
+    pub fn host(&self) -> &str {
+        &self.host
     }
 
     pub fn date(&self) -> Date<Utc> {
@@ -39,8 +40,12 @@ impl LogEvent {
         self.timestamp
     }
 
-    pub fn get_value(&self, key: &str) -> Option<&Value> {
-        self.values.get(key)
+    pub fn values(&self) -> &BTreeMap<String, Value> {
+        &self.values
+    }
+
+    pub fn get_path(&self, path: &FieldPath) -> Option<&Value> {
+        path.lookup(&self.values)
     }
 }
 
@@ -50,15 +55,8 @@ impl fmt::Display for LogEvent {
     }
 }
 
-pub fn stream_from_file<'fs>(
-  file: &'fs File,
-) -> serde_json::StreamDeserializer<'fs, serde_json::de::IoRead<BufReader<&File>>, LogEvent> {
-    let reader = BufReader::new(file);
-    let deserializer = serde_json::Deserializer::from_reader(reader);
-    deserializer.into_iter::<LogEvent>()
-}
-
 /// Basic DataPoint representation
+#[derive(Serialize, Debug, Clone, PartialEq)]
 pub struct DataPoint {
     timestamp: DateTime<Utc>,
     value: Value,
@@ -68,4 +66,12 @@ impl DataPoint {
     pub fn new(timestamp: DateTime<Utc>, value: Value) -> Self {
         DataPoint { timestamp, value }
     }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
 }
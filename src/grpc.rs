@@ -0,0 +1,445 @@
+use crate::collector::{log_routes, Collector};
+use crate::errors::{Result, RstzError};
+use crate::events;
+use crate::path::FieldPath;
+use crate::query;
+use chrono::{DateTime, TimeZone, Utc};
+use prost_types::value::Kind;
+use serde_json::{Map, Number, Value};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status, Streaming};
+
+/// Messages and stubs generated from `proto/rstz.proto`.
+pub mod proto {
+    tonic::include_proto!("rstz.v1");
+}
+
+pub use proto::rstz_client::RstzClient;
+pub use proto::rstz_server::RstzServer;
+
+type Reply<T> = std::result::Result<Response<T>, Status>;
+type ReplyStream<T> = Pin<Box<dyn Stream<Item = std::result::Result<T, Status>> + Send>>;
+
+/// The gRPC API, sharing its collector with whatever other listeners are running.
+pub struct Service {
+    collector: Arc<Collector>,
+    fields: Arc<Vec<FieldPath>>,
+}
+
+impl Service {
+    /// `fields` are the values routed out of streamed log events, as in the JSON daemon.
+    pub fn new(collector: Arc<Collector>, fields: Arc<Vec<FieldPath>>) -> Self {
+        Service { collector, fields }
+    }
+
+    pub fn into_server(self) -> RstzServer<Service> {
+        RstzServer::new(self)
+    }
+
+    fn write_event(&self, event: proto::LogEvent) -> Result<bool> {
+        let event = events::LogEvent::try_from(event)?;
+        let routes = log_routes(&event, &self.fields);
+        self.collector.ingest_routes(&event, &routes)?;
+        Ok(!routes.is_empty())
+    }
+
+    fn write_point(&self, point: proto::Point) -> Result<()> {
+        let data = point
+            .point
+            .ok_or_else(|| RstzError::new("Point without data."))?;
+        let data = events::DataPoint::try_from(data)?;
+        let mut values = BTreeMap::new();
+        values.insert("value".to_string(), data.value().clone());
+        let event = events::LogEvent::new(data.timestamp(), String::new(), values);
+        self.collector
+            .ingest(&point.series, &FieldPath::key("value"), &event)
+    }
+
+    fn write_block(&self, block: proto::Block) -> Result<()> {
+        self.collector.append_block(&block.series, block.data)
+    }
+
+    fn blocks(&self, series: &str) -> std::result::Result<Vec<Vec<u8>>, Status> {
+        if !self.collector.contains(series) {
+            return Err(Status::not_found(format!("Unknown series: {}", series)));
+        }
+        Ok(self.collector.blocks(series))
+    }
+}
+
+#[tonic::async_trait]
+impl proto::rstz_server::Rstz for Service {
+    async fn write_events(
+        &self,
+        request: Request<Streaming<proto::LogEvent>>,
+    ) -> Reply<proto::WriteSummary> {
+        let mut stream = request.into_inner();
+        let mut summary = proto::WriteSummary::default();
+        while let Some(event) = stream.message().await? {
+            match self.write_event(event) {
+                Ok(true) => summary.accepted += 1,
+                Ok(false) => summary.rejected += 1,
+                Err(e) => {
+                    eprintln!("Skipping event: {}", e);
+                    summary.rejected += 1;
+                }
+            }
+        }
+        Ok(Response::new(summary))
+    }
+
+    async fn write_points(
+        &self,
+        request: Request<Streaming<proto::Point>>,
+    ) -> Reply<proto::WriteSummary> {
+        let mut stream = request.into_inner();
+        let mut summary = proto::WriteSummary::default();
+        while let Some(point) = stream.message().await? {
+            match self.write_point(point) {
+                Ok(()) => summary.accepted += 1,
+                Err(e) => {
+                    eprintln!("Skipping point: {}", e);
+                    summary.rejected += 1;
+                }
+            }
+        }
+        Ok(Response::new(summary))
+    }
+
+    async fn write_blocks(
+        &self,
+        request: Request<Streaming<proto::Block>>,
+    ) -> Reply<proto::WriteSummary> {
+        let mut stream = request.into_inner();
+        let mut summary = proto::WriteSummary::default();
+        while let Some(block) = stream.message().await? {
+            match self.write_block(block) {
+                Ok(()) => summary.accepted += 1,
+                Err(e) => {
+                    eprintln!("Skipping block: {}", e);
+                    summary.rejected += 1;
+                }
+            }
+        }
+        Ok(Response::new(summary))
+    }
+
+    type ReadStream = ReplyStream<proto::DataPoint>;
+
+    async fn read(&self, request: Request<proto::ReadRequest>) -> Reply<Self::ReadStream> {
+        let request = request.into_inner();
+        let start = match request.start {
+            Some(start) => {
+                to_datetime(&start).map_err(|e| Status::invalid_argument(e.to_string()))?
+            }
+            None => Utc.timestamp_opt(0, 0).unwrap(),
+        };
+        let end = match request.end {
+            Some(end) => to_datetime(&end).map_err(|e| Status::invalid_argument(e.to_string()))?,
+            None => Utc::now(),
+        };
+        let blocks = self.blocks(&request.series)?;
+        let points = query::range(&blocks, start, end)
+            .map_err(|e| Status::data_loss(e.to_string()))?
+            .into_iter()
+            .map(|p| Ok(proto::DataPoint::from(p)));
+        let mut response = Response::new(Box::pin(tokio_stream::iter(points)) as Self::ReadStream);
+        let bounds = query::error_bounds_header(&blocks).map_err(|e| Status::data_loss(e.to_string()))?;
+        if let Some(bounds) = bounds {
+            let bounds = bounds.parse().map_err(|_| Status::internal("Error bounds are not valid metadata."))?;
+            response.metadata_mut().insert("x-error-bound", bounds);
+        }
+        Ok(response)
+    }
+
+    type ReadBlocksStream = ReplyStream<proto::Block>;
+
+    async fn read_blocks(
+        &self,
+        request: Request<proto::ReadBlocksRequest>,
+    ) -> Reply<Self::ReadBlocksStream> {
+        let series = request.into_inner().series;
+        let blocks = self.blocks(&series)?.into_iter().map(move |data| {
+            Ok(proto::Block {
+                series: series.clone(),
+                data,
+            })
+        });
+        Ok(Response::new(Box::pin(tokio_stream::iter(blocks))))
+    }
+
+    async fn list_series(
+        &self,
+        _request: Request<proto::ListSeriesRequest>,
+    ) -> Reply<proto::ListSeriesResponse> {
+        let series = self.collector.series();
+        Ok(Response::new(proto::ListSeriesResponse { series }))
+    }
+}
+
+impl From<&events::LogEvent> for proto::LogEvent {
+    fn from(event: &events::LogEvent) -> Self {
+        proto::LogEvent {
+            timestamp: Some(to_timestamp(event.datetime())),
+            host: event.host().to_string(),
+            values: Some(to_struct(event.values().iter())),
+        }
+    }
+}
+
+impl TryFrom<proto::LogEvent> for events::LogEvent {
+    type Error = RstzError;
+
+    fn try_from(event: proto::LogEvent) -> Result<Self> {
+        let timestamp = event
+            .timestamp
+            .ok_or_else(|| RstzError::new("Event without timestamp."))?;
+        let values = event
+            .values
+            .map(|s| {
+                s.fields
+                    .into_iter()
+                    .map(|(k, v)| (k, from_value(v)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(events::LogEvent::new(
+            to_datetime(&timestamp)?,
+            event.host,
+            values,
+        ))
+    }
+}
+
+impl From<events::DataPoint> for proto::DataPoint {
+    fn from(point: events::DataPoint) -> Self {
+        proto::DataPoint {
+            timestamp: Some(to_timestamp(point.timestamp())),
+            value: Some(to_value(point.value())),
+        }
+    }
+}
+
+impl TryFrom<proto::DataPoint> for events::DataPoint {
+    type Error = RstzError;
+
+    fn try_from(point: proto::DataPoint) -> Result<Self> {
+        let timestamp = point
+            .timestamp
+            .ok_or_else(|| RstzError::new("Point without timestamp."))?;
+        let value = point.value.map(from_value).unwrap_or(Value::Null);
+        Ok(events::DataPoint::new(to_datetime(&timestamp)?, value))
+    }
+}
+
+fn to_timestamp(time: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn to_datetime(timestamp: &prost_types::Timestamp) -> Result<DateTime<Utc>> {
+    u32::try_from(timestamp.nanos)
+        .ok()
+        .and_then(|nanos| Utc.timestamp_opt(timestamp.seconds, nanos).single())
+        .ok_or_else(|| RstzError::new("Timestamp out of range."))
+}
+
+fn to_struct<'v, I>(values: I) -> prost_types::Struct
+where
+    I: Iterator<Item = (&'v String, &'v Value)>,
+{
+    prost_types::Struct {
+        fields: values.map(|(k, v)| (k.clone(), to_value(v))).collect(),
+    }
+}
+
+/// Protobuf values only carry doubles, integers wider than 53 bits lose precision.
+fn to_value(value: &Value) -> prost_types::Value {
+    let kind = match value {
+        Value::Null => Kind::NullValue(0),
+        Value::Bool(b) => Kind::BoolValue(*b),
+        Value::Number(n) => Kind::NumberValue(n.as_f64().unwrap_or(f64::NAN)),
+        Value::String(s) => Kind::StringValue(s.clone()),
+        Value::Array(items) => Kind::ListValue(prost_types::ListValue {
+            values: items.iter().map(to_value).collect(),
+        }),
+        Value::Object(map) => Kind::StructValue(to_struct(map.iter())),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+/// Non finite numbers have no JSON form and come out as null.
+fn from_value(value: prost_types::Value) -> Value {
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => Value::Null,
+        Some(Kind::BoolValue(b)) => Value::Bool(b),
+        Some(Kind::NumberValue(n)) => Number::from_f64(n).map_or(Value::Null, Value::Number),
+        Some(Kind::StringValue(s)) => Value::String(s),
+        Some(Kind::ListValue(list)) => {
+            Value::Array(list.values.into_iter().map(from_value).collect())
+        }
+        Some(Kind::StructValue(s)) => Value::Object(
+            s.fields
+                .into_iter()
+                .map(|(k, v)| (k, from_value(v)))
+                .collect::<Map<String, Value>>(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encodeco::{ErrorBound, LossyEncoder, TsEncoder};
+    use crate::store::BlockStore;
+    use chrono::Duration;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tokio_stream::StreamExt;
+    use tonic::transport::{Endpoint, Server};
+
+    fn event(i: i64) -> events::LogEvent {
+        let mut values = BTreeMap::new();
+        values.insert("value".to_string(), json!(i as f64 * 0.5));
+        values.insert("tags".to_string(), json!({"zone": "b", "ids": [1.0, 2.0]}));
+        let timestamp = Utc.timestamp(1_585_413_000 + i * 10, 0);
+        events::LogEvent::new(timestamp, "web-1".to_string(), values)
+    }
+
+    #[test]
+    fn converts_log_events() {
+        let original = event(3);
+        let back = events::LogEvent::try_from(proto::LogEvent::from(&original)).unwrap();
+        assert_eq!(back.datetime(), original.datetime());
+        assert_eq!(back.host(), original.host());
+        assert_eq!(back.values(), original.values());
+    }
+
+    #[tokio::test]
+    async fn streams_points_and_blocks() {
+        let collector = Collector::new(Duration::minutes(120), BlockStore::in_memory());
+        let service = Service::new(Arc::new(collector), Arc::new(vec![FieldPath::key("value")]));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(service.into_server())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let channel = Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = RstzClient::new(channel);
+
+        let points: Vec<proto::Point> = (0..10)
+            .map(|i| proto::Point {
+                series: "cpu".to_string(),
+                point: Some(proto::DataPoint::from(events::DataPoint::new(
+                    event(i).datetime(),
+                    json!(i as f64),
+                ))),
+            })
+            .collect();
+        let summary = client
+            .write_points(tokio_stream::iter(points))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((summary.accepted, summary.rejected), (10, 0));
+
+        let summary = client
+            .write_events(tokio_stream::iter(
+                (0..5).map(|i| proto::LogEvent::from(&event(i))),
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((summary.accepted, summary.rejected), (5, 0));
+
+        // Values are multiples of 0.5, which the lossy encoder keeps exact.
+        let mut encoder = TsEncoder::new(FieldPath::key("value"), Duration::minutes(120))
+            .with_values(LossyEncoder::with_bound(ErrorBound::Absolute(0.25)));
+        for i in 0..20 {
+            encoder.compress(&event(i)).unwrap();
+        }
+        let blocks = vec![
+            proto::Block {
+                series: "edge".to_string(),
+                data: encoder.genblock(),
+            },
+            proto::Block {
+                series: "edge".to_string(),
+                data: vec![1, 2, 3],
+            },
+        ];
+        let summary = client
+            .write_blocks(tokio_stream::iter(blocks))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((summary.accepted, summary.rejected), (1, 1));
+
+        let series = client
+            .list_series(proto::ListSeriesRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .series;
+        assert_eq!(series, vec!["cpu", "edge", "value{host=\"web-1\"}"]);
+
+        let request = proto::ReadRequest {
+            series: "edge".to_string(),
+            start: Some(to_timestamp(event(5).datetime())),
+            end: Some(to_timestamp(event(9).datetime())),
+        };
+        let read = client.read(request).await.unwrap();
+        assert_eq!(
+            read.metadata().get("x-error-bound").unwrap(),
+            "abs:0.25"
+        );
+        let read: Vec<events::DataPoint> = read
+            .into_inner()
+            .map(|p| events::DataPoint::try_from(p.unwrap()).unwrap())
+            .collect()
+            .await;
+        let expected: Vec<_> = (5..10)
+            .map(|i| events::DataPoint::new(event(i).datetime(), json!(i as f64 * 0.5)))
+            .collect();
+        assert_eq!(read, expected);
+
+        let blocks: Vec<proto::Block> = client
+            .read_blocks(proto::ReadBlocksRequest {
+                series: "cpu".to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .map(|b| b.unwrap())
+            .collect()
+            .await;
+        let data: Vec<Vec<u8>> = blocks.into_iter().map(|b| b.data).collect();
+        assert_eq!(
+            query::range(&data, event(0).datetime(), event(9).datetime()).unwrap().len(),
+            10
+        );
+
+        let missing = client
+            .read(proto::ReadRequest {
+                series: "nope".to_string(),
+                start: None,
+                end: None,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+    }
+}
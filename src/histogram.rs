@@ -0,0 +1,196 @@
+use crate::errors::{Result, RstzError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Coarsest and finest bucket schemas, as in Prometheus native histograms.
+pub const MIN_SCHEMA: i8 = -4;
+pub const MAX_SCHEMA: i8 = 8;
+
+/// Distribution of the observations of one interval, such as request latencies.
+/// Bucket `i` holds magnitudes in `(base^(i-1), base^i]` with `base = 2^(2^-schema)`, so every
+/// bucket is the same relative width, as in Prometheus native histograms and DDSketch.
+/// Events carry histograms as JSON objects, such as `{"schema": 3, "sum": 2.5, "positive": {"1": 2}}`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub schema: i8,
+    /// Observations of exactly zero.
+    #[serde(default)]
+    pub zero: u64,
+    #[serde(default)]
+    pub sum: f64,
+    /// Counts of positive observations by bucket index.
+    #[serde(default)]
+    pub positive: BTreeMap<i32, u64>,
+    /// Counts of negative observations by the bucket index of their magnitude.
+    #[serde(default)]
+    pub negative: BTreeMap<i32, u64>,
+}
+
+impl Histogram {
+    pub fn new(schema: i8) -> Result<Self> {
+        Histogram {
+            schema,
+            ..Histogram::default()
+        }
+        .validate()
+    }
+
+    /// The histogram held by a JSON value, numbers and other objects are not histograms.
+    pub fn from_value(value: &Value) -> Result<Self> {
+        if !value.is_object() {
+            return Err(RstzError::new("Histograms are JSON objects."));
+        }
+        Histogram::deserialize(value)?.validate()
+    }
+
+    pub fn to_value(&self) -> Value {
+        serde_json::to_value(self).expect("Histograms serialize to JSON.")
+    }
+
+    pub(crate) fn validate(self) -> Result<Self> {
+        if !(MIN_SCHEMA..=MAX_SCHEMA).contains(&self.schema) {
+            return Err(RstzError::new(&format!(
+                "Histogram schema {} is not within {} and {}.",
+                self.schema, MIN_SCHEMA, MAX_SCHEMA
+            )));
+        }
+        if !self.sum.is_finite() {
+            return Err(RstzError::new("Histogram sum is not finite."));
+        }
+        Ok(self)
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        if value == 0.0 {
+            self.zero += 1;
+        } else if value.is_finite() {
+            let index = (value.abs().log2() * 2f64.powi(self.schema as i32)).ceil() as i32;
+            let buckets = if value > 0.0 { &mut self.positive } else { &mut self.negative };
+            *buckets.entry(index).or_default() += 1;
+            self.sum += value;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.zero + self.positive.values().sum::<u64>() + self.negative.values().sum::<u64>()
+    }
+
+    /// Adds the observations of `other`, in the coarser schema of the two.
+    pub fn merge(&mut self, other: &Histogram) {
+        let mut other = other.clone();
+        if other.schema < self.schema {
+            self.downscale(other.schema);
+        } else {
+            other.downscale(self.schema);
+        }
+        self.zero += other.zero;
+        self.sum += other.sum;
+        for (index, count) in other.positive {
+            *self.positive.entry(index).or_default() += count;
+        }
+        for (index, count) in other.negative {
+            *self.negative.entry(index).or_default() += count;
+        }
+    }
+
+    /// Merges buckets down to `schema`, each step halving their count.
+    fn downscale(&mut self, schema: i8) {
+        if schema >= self.schema {
+            return;
+        }
+        let factor = 1i64 << (self.schema - schema);
+        // Bucket `i` lands in bucket `ceil(i / factor)` of the coarser schema.
+        let merge = |buckets: &BTreeMap<i32, u64>| {
+            let mut merged = BTreeMap::new();
+            for (index, count) in buckets {
+                let index = (*index as i64 + factor - 1).div_euclid(factor) as i32;
+                *merged.entry(index).or_default() += count;
+            }
+            merged
+        };
+        self.positive = merge(&self.positive);
+        self.negative = merge(&self.negative);
+        self.schema = schema;
+    }
+
+    /// Estimate of the `q` quantile, within half a bucket width of the true value.
+    /// `None` for empty histograms or `q` outside `[0, 1]`.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+        let rank = (q * (count - 1) as f64).floor() as u64;
+        let base = 2f64.powf(2f64.powi(-(self.schema as i32)));
+        // Midpoint of the bucket in relative terms, DDSketch's estimate.
+        let estimate = |index: i32| 2.0 * base.powi(index) / (base + 1.0);
+        let ascending = self
+            .negative
+            .iter()
+            .rev()
+            .map(|(index, count)| (-estimate(*index), *count))
+            .chain(Some((0.0, self.zero)))
+            .chain(self.positive.iter().map(|(index, count)| (estimate(*index), *count)));
+        let mut seen = 0;
+        for (value, count) in ascending {
+            seen += count;
+            if seen > rank {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_quantiles_within_a_bucket() {
+        let mut histogram = Histogram::new(3).unwrap();
+        for ms in 1..=1000 {
+            histogram.observe(ms as f64);
+        }
+        histogram.observe(0.0);
+        histogram.observe(-5.0);
+        assert_eq!(histogram.count(), 1002);
+        // Schema 3 buckets are 9% wide, estimates are within half of that.
+        for (q, expected) in [(0.5, 500.0), (0.9, 900.0), (0.99, 990.0), (1.0, 1000.0)].iter() {
+            let estimate = histogram.quantile(*q).unwrap();
+            assert!((estimate - expected).abs() <= expected * 0.045, "{} at {}", estimate, q);
+        }
+        assert_eq!(histogram.quantile(0.001), Some(0.0));
+        assert!(histogram.quantile(0.0).unwrap() < -4.5);
+        assert_eq!(histogram.quantile(1.5), None);
+        assert_eq!(Histogram::new(3).unwrap().quantile(0.5), None);
+    }
+
+    #[test]
+    fn merges_into_the_coarser_schema() {
+        let mut fine = Histogram::new(2).unwrap();
+        let mut coarse = Histogram::new(0).unwrap();
+        for value in [1.5, 3.0, 3.5, 100.0].iter() {
+            fine.observe(*value);
+            coarse.observe(*value);
+        }
+        let mut merged = fine.clone();
+        merged.merge(&coarse);
+        assert_eq!(merged.schema, 0);
+        assert_eq!(merged.sum, 216.0);
+        let doubled: BTreeMap<i32, u64> = coarse.positive.iter().map(|(i, c)| (*i, c * 2)).collect();
+        assert_eq!(merged.positive, doubled);
+    }
+
+    #[test]
+    fn reads_json_objects() {
+        let value = serde_json::json!({"schema": 1, "sum": 3.5, "positive": {"-2": 1, "4": 2}});
+        let histogram = Histogram::from_value(&value).unwrap();
+        assert_eq!(histogram.positive.get(&-2), Some(&1));
+        assert_eq!(Histogram::from_value(&histogram.to_value()), Ok(histogram));
+        assert!(Histogram::from_value(&Value::from(3.5)).is_err());
+        assert!(Histogram::from_value(&serde_json::json!({"schema": 9})).is_err());
+        assert!(Histogram::from_value(&serde_json::json!({"zone": "b"})).is_err());
+    }
+}
@@ -0,0 +1,20 @@
+extern crate bitvec;
+extern crate chrono;
+extern crate serde;
+extern crate serde_json;
+
+pub mod clock;
+pub mod collector;
+pub mod crypto;
+pub mod encodeco;
+pub mod errors;
+pub mod events;
+pub mod grpc;
+pub mod histogram;
+pub mod parsers;
+pub mod path;
+pub mod query;
+pub mod server;
+pub mod sources;
+pub mod store;
+pub mod tree;
@@ -1,48 +1,516 @@
-extern crate bitvec;
-extern crate chrono;
-extern crate serde;
-extern crate serde_json;
-
-mod encodeco;
-mod errors;
-mod events;
-mod tree;
-
 use chrono::Duration;
-use chrono::{TimeZone, Utc};
 
-use std::convert::TryFrom;
+use std::collections::BTreeMap;
+use std::env;
 use std::fs::File;
-use std::string::String;
+use std::io::{self, BufRead, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use rstz::clock::SystemClock;
+use rstz::collector::{self, Collector};
+use rstz::crypto::{Cipher, Encryption, FileKeyProvider};
+use rstz::encodeco::{
+	Alignment, AnyDecoder, AnyEncoder, BlockLimits, Compression, EncoderStats, TSDecoder, TimestampCodec, TsEncoder,
+	ValueCodec,
+};
+use rstz::errors::{Result, RstzError};
+use rstz::parsers::{self, InputFormat};
+use rstz::path::FieldPath;
+use rstz::store::BlockStore;
+use rstz::{grpc, server, sources};
 
-use encodeco::{GorillaEncoder, TsEncoder};
-use errors::Result;
+const USAGE: &str = "usage: rstz [serve] [--listen ADDR] [--out DIR] [--field PATH]... [BLOCK OPTIONS] [--grace SECONDS]
+       rstz encode [--format json|logfmt|csv|syslog] [--field PATH]... [BLOCK OPTIONS] [FILE|-]
+       rstz inspect [--format json|logfmt|csv|syslog] [--field PATH]... [BLOCK OPTIONS] [FILE|-]
+       rstz vector [--listen ADDR] [--out DIR] [--field PATH]... [BLOCK OPTIONS] [--grace SECONDS]
+       rstz prometheus [--listen ADDR] [--out DIR] [BLOCK OPTIONS] [--grace SECONDS]
+       rstz influx [--listen ADDR] [--tcp ADDR] [--udp ADDR] [--out DIR] [BLOCK OPTIONS] [--grace SECONDS]
+       rstz statsd [--listen ADDR] [--flush SECONDS] [--out DIR] [BLOCK OPTIONS] [--grace SECONDS]
+       rstz grpc [--listen ADDR] [--out DIR] [--field PATH]... [BLOCK OPTIONS] [--grace SECONDS]
+
+BLOCK OPTIONS: [--interval MINUTES] [--align MODE] [--max-points N] [--max-bytes N] [--compress COMPRESSION]
+               [--timestamps [SERIES=]CODEC]... [--values [SERIES=]CODEC]...
+MODE is epoch, first, midnight or midnight:<IANA zone>, the default is midnight in UTC.
+COMPRESSION is none, lz4 or zstd, applied to sealed blocks it makes smaller, the default is none.
+CODEC is dod, seconds or regular for timestamps, the default is dod,
+and gorilla, chimp, chimp128, decimal, lossy:abs:BOUND, lossy:rel:FRACTION, lossy:bits:MANTISSA_BITS or histogram
+for values, the default is gorilla, which is exact. SERIES may be a series name or a full series key.
+With --out, [--keys FILE] [--cipher aes-256-gcm|chacha20-poly1305] encrypts new segments with the last key of FILE,
+a line of ID and 64 hex digits per key, earlier keys still decrypt older segments. The default cipher is aes-256-gcm.";
+
+/// How often daemons look for blocks whose window is over.
+const TICK: time::Duration = time::Duration::from_secs(1);
+
+enum Command {
+	Serve,
+	Encode,
+	Inspect,
+	Vector,
+	Prometheus,
+	Influx,
+	Statsd,
+	Grpc,
+}
+
+struct Options {
+	command: Command,
+	format: InputFormat,
+	fields: Vec<FieldPath>,
+	interval: Duration,
+	alignment: Alignment,
+	limits: BlockLimits,
+	compression: Compression,
+	timestamps: TimestampCodec,
+	series_timestamps: Vec<(String, TimestampCodec)>,
+	values: ValueCodec,
+	series_values: Vec<(String, ValueCodec)>,
+	flush: Duration,
+	grace: Duration,
+	input: String,
+	listen: Option<String>,
+	tcp: Option<String>,
+	udp: Option<String>,
+	out: Option<String>,
+	keys: Option<String>,
+	cipher: Cipher,
+}
+
+fn parse_args() -> Result<Options> {
+	let mut options = Options {
+		command: Command::Serve,
+		format: InputFormat::Json,
+		fields: Vec::new(),
+		interval: Duration::minutes(120),
+		alignment: Alignment::default(),
+		limits: BlockLimits::default(),
+		compression: Compression::default(),
+		timestamps: TimestampCodec::default(),
+		series_timestamps: Vec::new(),
+		values: ValueCodec::default(),
+		series_values: Vec::new(),
+		flush: Duration::seconds(10),
+		grace: Duration::seconds(60),
+		input: String::from("test.json"),
+		listen: None,
+		tcp: None,
+		udp: None,
+		out: None,
+		keys: None,
+		cipher: Cipher::default(),
+	};
+	let mut args = env::args().skip(1).peekable();
+	match args.peek().map(String::as_str) {
+		Some("serve") => {
+			args.next();
+		}
+		Some("encode") => {
+			options.command = Command::Encode;
+			args.next();
+		}
+		Some("inspect") => {
+			options.command = Command::Inspect;
+			args.next();
+		}
+		Some("vector") => {
+			options.command = Command::Vector;
+			args.next();
+		}
+		Some("prometheus") => {
+			options.command = Command::Prometheus;
+			args.next();
+		}
+		Some("influx") => {
+			options.command = Command::Influx;
+			args.next();
+		}
+		Some("statsd") => {
+			options.command = Command::Statsd;
+			args.next();
+		}
+		Some("grpc") => {
+			options.command = Command::Grpc;
+			args.next();
+		}
+		_ => {}
+	}
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().ok_or_else(|| RstzError::new(USAGE));
+		match arg.as_str() {
+			"--format" | "-f" => options.format = value()?.parse()?,
+			"--field" => options.fields.push(value()?.parse()?),
+			"--interval" => {
+				let minutes = value()?
+					.parse::<i64>()
+					.map_err(|_| RstzError::new("Interval must be a number of minutes."))?;
+				options.interval = Duration::minutes(minutes);
+			}
+			"--align" => options.alignment = value()?.parse()?,
+			"--timestamps" => {
+				let value = value()?;
+				match value.rsplit_once('=') {
+					Some((series, codec)) => options.series_timestamps.push((series.to_string(), codec.parse()?)),
+					None => options.timestamps = value.parse()?,
+				}
+			}
+			"--values" => {
+				let value = value()?;
+				match value.rsplit_once('=') {
+					Some((series, codec)) => options.series_values.push((series.to_string(), codec.parse()?)),
+					None => options.values = value.parse()?,
+				}
+			}
+			"--max-points" => {
+				let points = value()?
+					.parse::<usize>()
+					.map_err(|_| RstzError::new("Max points must be a number."))?;
+				options.limits.max_points = Some(points);
+			}
+			"--max-bytes" => {
+				let bytes = value()?
+					.parse::<usize>()
+					.map_err(|_| RstzError::new("Max bytes must be a number."))?;
+				options.limits.max_bytes = Some(bytes);
+			}
+			"--compress" => options.compression = value()?.parse()?,
+			"--flush" => {
+				let seconds = value()?
+					.parse::<i64>()
+					.map_err(|_| RstzError::new("Flush must be a number of seconds."))?;
+				options.flush = Duration::seconds(seconds);
+			}
+			"--grace" => {
+				let seconds = value()?
+					.parse::<i64>()
+					.map_err(|_| RstzError::new("Grace must be a number of seconds."))?;
+				options.grace = Duration::seconds(seconds);
+			}
+			"--listen" => options.listen = Some(value()?),
+			"--tcp" => options.tcp = Some(value()?),
+			"--udp" => options.udp = Some(value()?),
+			"--out" => options.out = Some(value()?),
+			"--keys" => options.keys = Some(value()?),
+			"--cipher" => options.cipher = value()?.parse()?,
+			"--help" | "-h" => return Err(RstzError::new(USAGE)),
+			_ => options.input = arg,
+		}
+	}
+	if options.fields.is_empty() {
+		options.fields.push("value".parse()?);
+	}
+	Ok(options)
+}
 
 fn main() -> Result<()> {
-	let inpt = File::open("test.json").unwrap();
-	let file_stream = events::stream_from_file(&inpt);
-	let mut encoder =
-		TsEncoder::<GorillaEncoder>::new(String::from("value"), Duration::minutes(120));
-	for it in file_stream {
-		if let Ok(event) = it {
-			let e = encoder.compress(event)?;
-			if let Some(block) = e {
-				println!("Block: {:?}", block);
-			}
-		}
-	}
-
-	let r = encoder.genblock();
-	println!("Remainder: {:?}", r);
-
-	let f = i64::from_be_bytes(*<&[u8; 8]>::try_from(&r[..8]).unwrap());
-	println!("{:?}", f);
-	let mut time = Utc.timestamp(f, 0);
-	println!("{:?}", time.to_string());
-	let d = i64::from_be_bytes(*<&[u8; 8]>::try_from(&r[8..16]).unwrap());
-	println!("{:?}", d);
-	let d2 = Duration::milliseconds(d);
-	time = time + d2;
-	println!("{:?}", time.to_string());
+	let options = parse_args()?;
+	match options.command {
+		Command::Serve => serve(options),
+		Command::Encode => encode(options),
+		Command::Inspect => inspect(options),
+		Command::Vector => vector(options),
+		Command::Prometheus => prometheus(options),
+		Command::Influx => influx(options),
+		Command::Statsd => statsd(options),
+		Command::Grpc => grpc(options),
+	}
+}
+
+fn open_store(options: &Options) -> Result<BlockStore> {
+	match (&options.out, &options.keys) {
+		(Some(dir), Some(keys)) => {
+			let keys = Arc::new(FileKeyProvider::open(Path::new(keys))?);
+			BlockStore::open_encrypted(Path::new(dir), &Encryption::new(options.cipher, keys))
+		}
+		(Some(dir), None) => BlockStore::open(Path::new(dir)),
+		(None, _) => Ok(BlockStore::in_memory()),
+	}
+}
+
+/// A collector sealing the blocks of quiet series once their window is over.
+fn open_collector(options: &Options) -> Result<Arc<Collector>> {
+	let collector = Collector::new(options.interval, open_store(options)?)
+		.with_alignment(options.alignment)
+		.with_limits(options.limits)
+		.with_compression(options.compression)
+		.with_timestamps(options.timestamps)
+		.with_values(options.values)
+		.with_grace(options.grace);
+	let collector = options
+		.series_timestamps
+		.iter()
+		.fold(collector, |collector, (series, codec)| collector.with_series_timestamps(series, *codec));
+	let collector = options
+		.series_values
+		.iter()
+		.fold(collector, |collector, (series, codec)| collector.with_series_values(series, *codec));
+	let collector = Arc::new(collector);
+	collector::spawn_ticker(&collector, Arc::new(SystemClock), TICK);
+	Ok(collector)
+}
+
+fn serve(options: Options) -> Result<()> {
+	let addr = options.listen.as_deref().unwrap_or("127.0.0.1:8080");
+	let server = http_server(addr)?;
+	let collector = open_collector(&options)?;
+	unblock_on_shutdown(&server)?;
+	println!("Serving the rstz API on http://{}", addr);
+	server::serve(&server, &collector, &options.fields);
+	println!("Shutting down, sealing open blocks.");
+	collector.flush()
+}
+
+/// Runs `stop` on SIGINT or SIGTERM.
+fn on_shutdown(stop: impl Fn() + Send + 'static) -> Result<()> {
+	ctrlc::set_handler(stop).map_err(|e| RstzError::new(&e.to_string()))
+}
+
+/// Unblocks `server` on SIGINT or SIGTERM, so its caller can seal open blocks.
+fn unblock_on_shutdown(server: &Arc<tiny_http::Server>) -> Result<()> {
+	let server = Arc::clone(server);
+	on_shutdown(move || server.unblock())
+}
+
+/// A flag set on SIGINT or SIGTERM, after which `wake` is called to unblock the listener checking it.
+fn stop_on_shutdown(wake: impl Fn() + Send + 'static) -> Result<Arc<AtomicBool>> {
+	let stop = Arc::new(AtomicBool::new(false));
+	{
+		let stop = Arc::clone(&stop);
+		on_shutdown(move || {
+			stop.store(true, Ordering::SeqCst);
+			wake();
+		})?;
+	}
+	Ok(stop)
+}
+
+fn http_server(addr: &str) -> Result<Arc<tiny_http::Server>> {
+	Ok(Arc::new(
+		tiny_http::Server::http(addr).map_err(|e| RstzError::new(&e.to_string()))?,
+	))
+}
+
+fn vector(options: Options) -> Result<()> {
+	let addr = options.listen.as_deref().unwrap_or("127.0.0.1:9000");
+	let listener = TcpListener::bind(addr)?;
+	let local: SocketAddr = listener.local_addr()?;
+	let collector = open_collector(&options)?;
+	// The listener only sees the flag once a connection comes in.
+	let stop = stop_on_shutdown(move || {
+		TcpStream::connect(local).ok();
+	})?;
+	println!("Listening for Vector events on {}", addr);
+	sources::vector::serve(
+		listener,
+		Arc::clone(&collector),
+		Arc::new(options.fields),
+		&stop,
+	)?;
+	println!("Shutting down, sealing open blocks.");
+	collector.flush()
+}
+
+fn prometheus(options: Options) -> Result<()> {
+	let addr = options.listen.as_deref().unwrap_or("127.0.0.1:9201");
+	let server = http_server(addr)?;
+	let collector = open_collector(&options)?;
+	unblock_on_shutdown(&server)?;
+	println!(
+		"Listening for Prometheus remote write on http://{}{}",
+		addr,
+		sources::prometheus::WRITE_PATH
+	);
+	sources::prometheus::serve(&server, &collector);
+	println!("Shutting down, sealing open blocks.");
+	collector.flush()
+}
+
+fn influx(options: Options) -> Result<()> {
+	let addr = options.listen.as_deref().unwrap_or("127.0.0.1:8086");
+	let server = http_server(addr)?;
+	let collector = open_collector(&options)?;
+	let tcp = options.tcp.as_deref().map(TcpListener::bind).transpose()?;
+	let udp = options.udp.as_deref().map(UdpSocket::bind).transpose()?;
+	let tcp_local = tcp.as_ref().map(TcpListener::local_addr).transpose()?;
+	let udp_local = udp.as_ref().map(UdpSocket::local_addr).transpose()?;
+	// The line protocol listeners only see the flag once a connection or datagram comes in.
+	let stop = {
+		let server = Arc::clone(&server);
+		stop_on_shutdown(move || {
+			server.unblock();
+			if let Some(local) = tcp_local {
+				TcpStream::connect(local).ok();
+			}
+			if let (Some(local), Ok(socket)) = (udp_local, UdpSocket::bind("0.0.0.0:0")) {
+				socket.send_to(&[], local).ok();
+			}
+		})?
+	};
+	let mut listeners = Vec::new();
+	if let Some(listener) = tcp {
+		let (collector, stop) = (Arc::clone(&collector), Arc::clone(&stop));
+		println!("Listening for line protocol on tcp://{}", listener.local_addr()?);
+		listeners.push(thread::spawn(move || sources::influx::serve_tcp(listener, collector, &stop)));
+	}
+	if let Some(socket) = udp {
+		let (collector, stop) = (Arc::clone(&collector), Arc::clone(&stop));
+		println!("Listening for line protocol on udp://{}", socket.local_addr()?);
+		listeners.push(thread::spawn(move || sources::influx::serve_udp(socket, &collector, &stop)));
+	}
+	println!("Listening for line protocol on http://{}/write", addr);
+	sources::influx::serve_http(&server, &collector);
+	for listener in listeners {
+		listener
+			.join()
+			.map_err(|_| RstzError::new("Line protocol listener panicked."))?;
+	}
+	println!("Shutting down, sealing open blocks.");
+	collector.flush()
+}
+
+fn statsd(options: Options) -> Result<()> {
+	let addr = options.listen.as_deref().unwrap_or("127.0.0.1:8125");
+	let socket = UdpSocket::bind(addr)?;
+	let local: SocketAddr = socket.local_addr()?;
+	let listener = sources::statsd::Listener::new(socket, options.flush)?;
+	let collector = open_collector(&options)?;
+	// An empty datagram wakes the listener rather than waiting for the flush interval.
+	let stop = stop_on_shutdown(move || {
+		if let Ok(socket) = UdpSocket::bind("0.0.0.0:0") {
+			socket.send_to(&[], local).ok();
+		}
+	})?;
+	println!("Listening for statsd metrics on udp://{}", addr);
+	listener.serve(&collector, &stop)?;
+	println!("Shutting down, sealing open blocks.");
+	collector.flush()
+}
+
+fn grpc(options: Options) -> Result<()> {
+	let addr = options
+		.listen
+		.as_deref()
+		.unwrap_or("127.0.0.1:50051")
+		.parse()
+		.map_err(|_| RstzError::new("Listen address must be IP:PORT."))?;
+	let collector = open_collector(&options)?;
+	let service = grpc::Service::new(Arc::clone(&collector), Arc::new(options.fields));
+	let runtime = tokio::runtime::Runtime::new()?;
+	// The same handler as the other commands, so SIGTERM seals open blocks too.
+	let shutdown = Arc::new(tokio::sync::Notify::new());
+	{
+		let shutdown = Arc::clone(&shutdown);
+		on_shutdown(move || shutdown.notify_one())?;
+	}
+	println!("Serving the rstz gRPC API on {}", addr);
+	runtime
+		.block_on(
+			tonic::transport::Server::builder()
+				.add_service(service.into_server())
+				.serve_with_shutdown(addr, async move {
+					shutdown.notified().await;
+				}),
+		)
+		.map_err(|e| RstzError::new(&e.to_string()))?;
+	println!("Shutting down, sealing open blocks.");
+	collector.flush()
+}
+
+fn open_input(options: &Options) -> Result<Box<dyn BufRead>> {
+	Ok(if options.input == "-" {
+		Box::new(BufReader::new(io::stdin()))
+	} else {
+		Box::new(BufReader::new(File::open(&options.input)?))
+	})
+}
+
+fn field_encoders(options: &Options) -> Vec<TsEncoder<AnyEncoder>> {
+	options
+		.fields
+		.iter()
+		.map(|field| {
+			let name = field.to_string();
+			let timestamps = options
+				.series_timestamps
+				.iter()
+				.find(|(series, _)| *series == name)
+				.map_or(options.timestamps, |(_, codec)| *codec);
+			let values = options
+				.series_values
+				.iter()
+				.find(|(series, _)| *series == name)
+				.map_or(options.values, |(_, codec)| *codec);
+			TsEncoder::new(field.clone(), options.interval)
+				.with_alignment(options.alignment)
+				.with_limits(options.limits)
+				.with_compression(options.compression)
+				.with_timestamps(timestamps)
+				.with_values(AnyEncoder::for_codec(values))
+		})
+		.collect()
+}
+
+/// Encodes the input like `encode` and prints the compression statistics of every field.
+fn inspect(options: Options) -> Result<()> {
+	let mut encoders = field_encoders(&options);
+	for it in parsers::read_events(options.format, open_input(&options)?) {
+		match it {
+			Ok(event) => {
+				for (field, encoder) in options.fields.iter().zip(encoders.iter_mut()) {
+					if let Err(e) = encoder.compress(&event) {
+						eprintln!("Skipping {} of event at {}: {}", field, event.datetime(), e);
+					}
+				}
+			}
+			Err(e) => eprintln!("Skipping event: {}", e),
+		}
+	}
+	let stats: BTreeMap<String, EncoderStats> = options
+		.fields
+		.iter()
+		.zip(encoders.iter_mut())
+		.map(|(field, encoder)| {
+			encoder.genblock();
+			(field.to_string(), encoder.stats())
+		})
+		.collect();
+	println!("{}", serde_json::to_string_pretty(&stats)?);
+	Ok(())
+}
+
+fn encode(options: Options) -> Result<()> {
+	let mut encoders = field_encoders(&options);
+	for it in parsers::read_events(options.format, open_input(&options)?) {
+		match it {
+			Ok(event) => {
+				// An event missing one field still feeds the others.
+				for (field, encoder) in options.fields.iter().zip(encoders.iter_mut()) {
+					match encoder.compress(&event) {
+						Ok(Some(block)) => println!("Block {}: {:?}", field, block),
+						Ok(None) => {}
+						Err(e) => eprintln!("Skipping {} of event at {}: {}", field, event.datetime(), e),
+					}
+				}
+			}
+			Err(e) => eprintln!("Skipping event: {}", e),
+		}
+	}
+
+	for (field, encoder) in options.fields.iter().zip(encoders.iter_mut()) {
+		let r = encoder.genblock();
+		println!("Remainder {}: {:?}", field, r);
+		if r.is_empty() {
+			continue;
+		}
+
+		let mut decoder = TSDecoder::<AnyDecoder>::new(&r);
+		println!("{:?}", decoder.header()?.to_string());
+		if let Some(first) = decoder.try_decompress()? {
+			println!("{:?}", first.timestamp().to_string());
+		}
+	}
 	Ok(())
 }
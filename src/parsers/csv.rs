@@ -0,0 +1,130 @@
+use super::{is_timestamp_key, parse_timestamp, typed_value, EventParser};
+use crate::errors::{Result, RstzError};
+use crate::events::LogEvent;
+use std::collections::BTreeMap;
+
+/// Parses CSV exports, the first line must be a header naming a timestamp and a `host` column.
+/// Quoted fields follow RFC4180 but may not span several lines.
+pub struct CsvParser {
+    header: Option<Vec<String>>,
+}
+
+impl Default for CsvParser {
+    fn default() -> Self {
+        CsvParser::new()
+    }
+}
+
+impl CsvParser {
+    pub fn new() -> Self {
+        CsvParser { header: None }
+    }
+}
+
+impl EventParser for CsvParser {
+    fn parse_line(&mut self, line: &str) -> Result<Option<LogEvent>> {
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        let fields = split_record(line)?;
+        let header = match &self.header {
+            Some(header) => header,
+            None => {
+                if !fields.iter().any(|f| is_timestamp_key(f)) || !fields.iter().any(|f| f == "host") {
+                    return Err(RstzError::new("CSV header needs a timestamp and a host column."));
+                }
+                self.header = Some(fields);
+                return Ok(None);
+            }
+        };
+        if fields.len() != header.len() {
+            return Err(RstzError::new(&format!(
+                "Expected {} columns, found {}.",
+                header.len(),
+                fields.len()
+            )));
+        }
+        let mut timestamp = None;
+        let mut host = None;
+        let mut values = BTreeMap::new();
+        for (column, field) in header.iter().zip(fields) {
+            if is_timestamp_key(column) && timestamp.is_none() {
+                timestamp = Some(parse_timestamp(&field)?);
+            } else if column == "host" && host.is_none() {
+                host = Some(field);
+            } else if !field.is_empty() {
+                values.insert(column.clone(), typed_value(&field));
+            }
+        }
+        let timestamp = timestamp.ok_or_else(RstzError::from_none)?;
+        let host = host.ok_or_else(RstzError::from_none)?;
+        Ok(Some(LogEvent::new(timestamp, host, values)))
+    }
+}
+
+fn split_record(line: &str) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(RstzError::Eof);
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    #[test]
+    fn parses_rows_after_the_header() {
+        let mut parser = CsvParser::new();
+        assert!(parser.parse_line("timestamp,host,value,note,empty").unwrap().is_none());
+        let event = parser
+            .parse_line("2020-03-28T16:29:04Z,web-1,\"1.5\",\"a, \"\"quoted\"\" note\",\r")
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.datetime(), Utc.ymd(2020, 3, 28).and_hms(16, 29, 4));
+        assert_eq!(event.host(), "web-1");
+        let values = event.values();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["value"], json!(1.5));
+        assert_eq!(values["note"], json!("a, \"quoted\" note"));
+        assert!(parser.parse_line("").unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_headers_without_timestamp_or_host() {
+        assert!(CsvParser::new().parse_line("host,value").is_err());
+        assert!(CsvParser::new().parse_line("ts,value").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_rows() {
+        let mut parser = CsvParser::new();
+        parser.parse_line("ts,host,value").unwrap();
+        assert!(parser.parse_line("1585413000,web-1").is_err());
+        assert!(parser.parse_line("1585413000,web-1,1,2").is_err());
+        assert!(parser.parse_line("soon,web-1,1").is_err());
+        assert!(parser.parse_line("1585413000,\"web-1,1").is_err());
+    }
+}
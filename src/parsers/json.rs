@@ -0,0 +1,10 @@
+use crate::errors::Result;
+use crate::events::LogEvent;
+use std::io::BufRead;
+
+/// JSON events may span several lines, so they are read as a stream rather than line by line.
+pub fn read_stream<'r, R: BufRead + 'r>(reader: R) -> impl Iterator<Item = Result<LogEvent>> + 'r {
+    serde_json::Deserializer::from_reader(reader)
+        .into_iter::<LogEvent>()
+        .map(|event| event.map_err(|e| e.into()))
+}
@@ -0,0 +1,151 @@
+use super::{is_timestamp_key, parse_timestamp, typed_value, EventParser};
+use crate::errors::{Result, RstzError};
+use crate::events::LogEvent;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Parses `ts=2020-03-28T16:29:04Z host=us-east-1 value=12` style lines.
+/// Unquoted values are typed, quoted ones are always kept as strings and bare keys become `true`.
+pub struct LogfmtParser;
+
+impl Default for LogfmtParser {
+    fn default() -> Self {
+        LogfmtParser::new()
+    }
+}
+
+impl LogfmtParser {
+    pub fn new() -> Self {
+        LogfmtParser
+    }
+}
+
+impl EventParser for LogfmtParser {
+    fn parse_line(&mut self, line: &str) -> Result<Option<LogEvent>> {
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        let mut timestamp = None;
+        let mut host = None;
+        let mut values = BTreeMap::new();
+        for (key, value) in pairs(line)? {
+            if is_timestamp_key(&key) && timestamp.is_none() {
+                let raw = value.as_str().map(String::from).unwrap_or_else(|| value.to_string());
+                timestamp = Some(parse_timestamp(&raw)?);
+            } else if key == "host" && host.is_none() {
+                host = Some(value.as_str().map(String::from).unwrap_or_else(|| value.to_string()));
+            } else {
+                values.insert(key, value);
+            }
+        }
+        let timestamp = timestamp.ok_or_else(|| RstzError::new("Missing timestamp key."))?;
+        let host = host.ok_or_else(|| RstzError::new("Missing host key."))?;
+        Ok(Some(LogEvent::new(timestamp, host, values)))
+    }
+}
+
+fn pairs(line: &str) -> Result<Vec<(String, Value)>> {
+    let mut result = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            return Ok(result);
+        }
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c.is_whitespace() {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+        if chars.peek() != Some(&'=') {
+            result.push((key, Value::Bool(true)));
+            continue;
+        }
+        chars.next();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some(c) => value.push(c),
+                        None => return Err(RstzError::Eof),
+                    },
+                    Some(c) => value.push(c),
+                    None => return Err(RstzError::Eof),
+                }
+            }
+            result.push((key, Value::String(value)));
+        } else {
+            let mut value = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+            result.push((key, typed_value(&value)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    fn parse(line: &str) -> Result<Option<LogEvent>> {
+        LogfmtParser::new().parse_line(line)
+    }
+
+    #[test]
+    fn parses_typed_values() {
+        let event = parse(r#"ts=2020-03-28T16:29:04Z host=us-east-1 value=12 load="0.5" msg="a \"b\"\tc" up name=web"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.datetime(), Utc.ymd(2020, 3, 28).and_hms(16, 29, 4));
+        assert_eq!(event.host(), "us-east-1");
+        let values = event.values();
+        assert_eq!(values["value"], json!(12));
+        assert_eq!(values["load"], json!("0.5"));
+        assert_eq!(values["msg"], json!("a \"b\"\tc"));
+        assert_eq!(values["up"], json!(true));
+        assert_eq!(values["name"], json!("web"));
+    }
+
+    #[test]
+    fn keeps_quoted_values_as_strings() {
+        let event = parse(r#"ts="1585413000" host="a" value=12 msg="12" id="007" ok="true""#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.datetime(), Utc.timestamp(1_585_413_000, 0));
+        let values = event.values();
+        assert_eq!(values["value"], json!(12));
+        assert_eq!(values["msg"], json!("12"));
+        assert_eq!(values["id"], json!("007"));
+        assert_eq!(values["ok"], json!("true"));
+    }
+
+    #[test]
+    fn skips_blank_lines() {
+        assert!(parse("  ").unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_incomplete_lines() {
+        assert!(parse("host=a value=1").is_err());
+        assert!(parse("ts=1585413000 value=1").is_err());
+        assert!(parse("ts=yesterday host=a").is_err());
+        assert!(parse(r#"ts=1585413000 host=a msg="unterminated"#).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+mod csv;
+mod json;
+mod logfmt;
+mod syslog;
+
+pub use self::csv::CsvParser;
+pub use self::logfmt::LogfmtParser;
+pub use self::syslog::SyslogParser;
+
+use crate::errors::{Result, RstzError};
+use crate::events::LogEvent;
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::Value;
+use std::io::BufRead;
+use std::str::FromStr;
+
+/// Line oriented parser turning raw records into Log Events.
+/// New formats can be plugged in by implementing this trait.
+pub trait EventParser {
+    /// Returns `Ok(None)` for lines that carry no event, like blank lines or a CSV header.
+    fn parse_line(&mut self, line: &str) -> Result<Option<LogEvent>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    Json,
+    Logfmt,
+    Csv,
+    Syslog,
+}
+
+impl FromStr for InputFormat {
+    type Err = RstzError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(InputFormat::Json),
+            "logfmt" => Ok(InputFormat::Logfmt),
+            "csv" => Ok(InputFormat::Csv),
+            "syslog" => Ok(InputFormat::Syslog),
+            _ => Err(RstzError::new(&format!("Unknown input format: {}", s))),
+        }
+    }
+}
+
+impl InputFormat {
+    /// Returns the parser for line oriented formats, JSON is read as a stream instead.
+    pub fn line_parser(self) -> Option<Box<dyn EventParser>> {
+        match self {
+            InputFormat::Json => None,
+            InputFormat::Logfmt => Some(Box::new(LogfmtParser::new())),
+            InputFormat::Csv => Some(Box::new(CsvParser::new())),
+            InputFormat::Syslog => Some(Box::new(SyslogParser::new())),
+        }
+    }
+}
+
+/// Reads every event in `reader` using the given format.
+pub fn read_events<'r, R: BufRead + 'r>(
+    format: InputFormat,
+    reader: R,
+) -> Box<dyn Iterator<Item = Result<LogEvent>> + 'r> {
+    match format.line_parser() {
+        Some(parser) => Box::new(parse_lines(parser, reader)),
+        None => Box::new(json::read_stream(reader)),
+    }
+}
+
+/// Runs a line parser over `reader`, errors carry the offending line number.
+pub fn parse_lines<'r, R: BufRead + 'r>(
+    mut parser: Box<dyn EventParser>,
+    reader: R,
+) -> impl Iterator<Item = Result<LogEvent>> + 'r {
+    reader
+        .lines()
+        .enumerate()
+        .filter_map(move |(idx, line)| match line {
+            Ok(line) => match parser.parse_line(&line) {
+                Ok(event) => event.map(Ok),
+                Err(e) => Some(Err(RstzError::new(&format!("line {}: {}", idx + 1, e)))),
+            },
+            Err(e) => Some(Err(e.into())),
+        })
+}
+
+/// Guesses the JSON type of a textual value, numbers are kept as numbers so they can be encoded.
+pub(crate) fn typed_value(raw: &str) -> Value {
+    if let Ok(i) = raw.parse::<i64>() {
+        return Value::from(i);
+    }
+    if let Ok(f) = raw.parse::<f64>() {
+        if f.is_finite() {
+            return Value::from(f);
+        }
+    }
+    match raw {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+/// Accepts RFC3339 timestamps or (fractional) seconds since the epoch.
+pub(crate) fn parse_timestamp(raw: &str) -> Result<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(raw) {
+        return Ok(dt.with_timezone(&Utc));
+    }
+    let secs = raw
+        .parse::<f64>()
+        .map_err(|_| RstzError::new(&format!("Invalid timestamp: {}", raw)))?;
+    let whole = secs.floor();
+    let nanos = (((secs - whole) * 1e9).round() as u32).min(999_999_999);
+    Utc.timestamp_opt(whole as i64, nanos)
+        .single()
+        .ok_or_else(|| RstzError::new(&format!("Timestamp out of range: {}", raw)))
+}
+
+pub(crate) fn is_timestamp_key(key: &str) -> bool {
+    matches!(key, "timestamp" | "ts" | "time")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!("JSON".parse::<InputFormat>().unwrap(), InputFormat::Json);
+        assert_eq!("syslog".parse::<InputFormat>().unwrap(), InputFormat::Syslog);
+        assert!("xml".parse::<InputFormat>().is_err());
+    }
+
+    #[test]
+    fn types_values() {
+        assert_eq!(typed_value("12"), json!(12));
+        assert_eq!(typed_value("-0.25"), json!(-0.25));
+        assert_eq!(typed_value("true"), json!(true));
+        assert_eq!(typed_value("NaN"), json!("NaN"));
+        assert_eq!(typed_value("inf"), json!("inf"));
+        assert_eq!(typed_value("web-1"), json!("web-1"));
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        let expected = Utc.ymd(2020, 3, 28).and_hms_milli(16, 29, 4, 250);
+        assert_eq!(parse_timestamp("2020-03-28T18:29:04.25+02:00").unwrap(), expected);
+        assert_eq!(parse_timestamp("1585412944.25").unwrap(), expected);
+        assert!(parse_timestamp("tomorrow").is_err());
+        assert!(parse_timestamp("1e300").is_err());
+    }
+
+    #[test]
+    fn reads_json_across_lines() {
+        let input = "{\"timestamp\": \"2020-03-28T16:29:04Z\", \"host\": \"a\",\n \"value\": 1}\n{\"timestamp\": \"2020-03-28T16:29:05Z\", \"host\": \"b\", \"value\": 2}";
+        let events: Vec<LogEvent> = read_events(InputFormat::Json, input.as_bytes())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].host(), "b");
+        assert_eq!(events[1].values()["value"], json!(2));
+
+        let mut events = read_events(InputFormat::Json, "{\"host\": \"a\"}".as_bytes());
+        assert!(events.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn numbers_line_errors() {
+        let input = "ts=1585413000 host=a value=1\n\nts=oops host=a\n";
+        let results: Vec<Result<LogEvent>> = read_events(InputFormat::Logfmt, input.as_bytes()).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        let error = results[1].as_ref().unwrap_err().to_string();
+        assert!(error.starts_with("line 3:"), "{}", error);
+    }
+}
@@ -0,0 +1,189 @@
+use super::{parse_timestamp, typed_value, EventParser};
+use crate::errors::{Result, RstzError};
+use crate::events::LogEvent;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+const NILVALUE: &str = "-";
+/// Facility 23 at severity 7, the largest priority RFC5424 allows.
+const MAX_PRI: u8 = 191;
+
+/// Parses RFC5424 syslog lines.
+/// Structured data elements become nested objects, `[metrics@1 value="12"]` is reachable as `metrics@1.value`.
+/// Parameter values are always quoted, so unlike logfmt they are typed regardless.
+pub struct SyslogParser;
+
+impl Default for SyslogParser {
+    fn default() -> Self {
+        SyslogParser::new()
+    }
+}
+
+impl SyslogParser {
+    pub fn new() -> Self {
+        SyslogParser
+    }
+}
+
+impl EventParser for SyslogParser {
+    fn parse_line(&mut self, line: &str) -> Result<Option<LogEvent>> {
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        let rest = line
+            .strip_prefix('<')
+            .ok_or_else(|| RstzError::new("Syslog line must start with a priority."))?;
+        let end = rest.find('>').ok_or(RstzError::Eof)?;
+        let pri = rest[..end]
+            .parse::<u8>()
+            .ok()
+            .filter(|pri| *pri <= MAX_PRI)
+            .ok_or_else(|| RstzError::new("Invalid syslog priority."))?;
+        let mut rest = &rest[end + 1..];
+
+        let version = next_token(&mut rest)?;
+        if version != "1" {
+            return Err(RstzError::new(&format!("Unsupported syslog version: {}", version)));
+        }
+        let timestamp = match next_token(&mut rest)? {
+            NILVALUE => return Err(RstzError::new("Missing syslog timestamp.")),
+            ts => parse_timestamp(ts)?,
+        };
+        let host = match next_token(&mut rest)? {
+            NILVALUE => return Err(RstzError::new("Missing syslog hostname.")),
+            host => host.to_string(),
+        };
+
+        let mut values = BTreeMap::new();
+        values.insert("facility".to_string(), Value::from(pri / 8));
+        values.insert("severity".to_string(), Value::from(pri % 8));
+        for key in &["appname", "procid", "msgid"] {
+            let token = next_token(&mut rest)?;
+            if token != NILVALUE {
+                values.insert(key.to_string(), Value::String(token.to_string()));
+            }
+        }
+
+        if let Some(r) = rest.strip_prefix(NILVALUE) {
+            rest = r;
+        } else {
+            while rest.starts_with('[') {
+                let (id, params) = structured_element(&mut rest)?;
+                values.insert(id, Value::Object(params));
+            }
+        }
+        if let Some(msg) = rest.strip_prefix(' ') {
+            let msg = msg.trim_start_matches('\u{feff}');
+            if !msg.is_empty() {
+                values.insert("message".to_string(), Value::String(msg.to_string()));
+            }
+        }
+        Ok(Some(LogEvent::new(timestamp, host, values)))
+    }
+}
+
+fn next_token<'l>(rest: &mut &'l str) -> Result<&'l str> {
+    let end = rest.find(' ').ok_or(RstzError::Eof)?;
+    let token = &rest[..end];
+    *rest = &rest[end + 1..];
+    Ok(token)
+}
+
+fn structured_element(rest: &mut &str) -> Result<(String, Map<String, Value>)> {
+    let body = &rest[1..];
+    let end = body.find([' ', ']']).ok_or(RstzError::Eof)?;
+    let id = body[..end].to_string();
+    let mut chars = body[end..].char_indices();
+    let mut params = Map::new();
+    loop {
+        match chars.next() {
+            Some((idx, ']')) => {
+                *rest = &body[end + idx + 1..];
+                return Ok((id, params));
+            }
+            Some((_, ' ')) => {
+                let mut name = String::new();
+                for (_, c) in &mut chars {
+                    if c == '=' {
+                        break;
+                    }
+                    name.push(c);
+                }
+                if chars.next().map(|(_, c)| c) != Some('"') {
+                    return Err(RstzError::new("Structured data values must be quoted."));
+                }
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => {
+                            let (_, c) = chars.next().ok_or(RstzError::Eof)?;
+                            value.push(c);
+                        }
+                        Some((_, c)) => value.push(c),
+                        None => return Err(RstzError::Eof),
+                    }
+                }
+                params.insert(name, typed_value(&value));
+            }
+            Some(_) => return Err(RstzError::new("Malformed structured data.")),
+            None => return Err(RstzError::Eof),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    fn parse(line: &str) -> Result<Option<LogEvent>> {
+        SyslogParser::new().parse_line(line)
+    }
+
+    #[test]
+    fn parses_structured_data() {
+        let line = r#"<165>1 2020-03-28T16:29:04.5Z web-1 nginx 42 - [metrics@1 value="12" path="/a\]b"][origin ip="10.0.0.1"] hello"#;
+        let event = parse(line).unwrap().unwrap();
+        assert_eq!(event.datetime(), Utc.ymd(2020, 3, 28).and_hms_milli(16, 29, 4, 500));
+        assert_eq!(event.host(), "web-1");
+        let values = event.values();
+        assert_eq!(values["facility"], json!(20));
+        assert_eq!(values["severity"], json!(5));
+        assert_eq!(values["appname"], json!("nginx"));
+        assert_eq!(values["procid"], json!("42"));
+        assert!(!values.contains_key("msgid"));
+        assert_eq!(values["metrics@1"], json!({"value": 12, "path": "/a]b"}));
+        assert_eq!(values["origin"], json!({"ip": "10.0.0.1"}));
+        assert_eq!(values["message"], json!("hello"));
+    }
+
+    #[test]
+    fn parses_nil_structured_data() {
+        let event = parse("<0>1 2020-03-28T16:29:04Z web-1 - - - -").unwrap().unwrap();
+        let values = event.values();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["facility"], json!(0));
+        assert_eq!(values["severity"], json!(0));
+    }
+
+    #[test]
+    fn accepts_priorities_up_to_191() {
+        assert!(parse("<191>1 2020-03-28T16:29:04Z web-1 - - - -").unwrap().is_some());
+        assert!(parse("<192>1 2020-03-28T16:29:04Z web-1 - - - -").is_err());
+        assert!(parse("<255>1 2020-03-28T16:29:04Z web-1 - - - -").is_err());
+        assert!(parse("<x>1 2020-03-28T16:29:04Z web-1 - - - -").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(parse("1 2020-03-28T16:29:04Z web-1 - - - -").is_err());
+        assert!(parse("<13>2 2020-03-28T16:29:04Z web-1 - - - -").is_err());
+        assert!(parse("<13>1 - web-1 - - - -").is_err());
+        assert!(parse("<13>1 2020-03-28T16:29:04Z - - - - -").is_err());
+        assert!(parse("<13>1 2020-03-28T16:29:04Z web-1 - - - [a b=1]").is_err());
+        assert!(parse(r#"<13>1 2020-03-28T16:29:04Z web-1 - - - [a b="1"#).is_err());
+        assert!(parse("<13>1 2020-03-28T16:29:04Z").is_err());
+    }
+}
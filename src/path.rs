@@ -0,0 +1,191 @@
+use crate::errors::{Result, RstzError};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+///Path to a (possibly nested) value inside a Log Event.
+///Accepts dotted paths (`metrics.latency.p99`, `cpus[0].load`), a small JSONPath subset
+///(`$.metrics['latency'].p99`) and JSON pointers (`/metrics/latency/p99`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldPath {
+    raw: String,
+    segments: Vec<String>,
+}
+
+impl FieldPath {
+    /// A path made of a single top-level key, taken literally.
+    pub fn key(name: &str) -> Self {
+        FieldPath {
+            raw: name.to_string(),
+            segments: vec![name.to_string()],
+        }
+    }
+
+    pub fn parse(path: &str) -> Result<Self> {
+        let segments = if path.starts_with('/') {
+            parse_pointer(path)
+        } else {
+            parse_dotted(path)?
+        };
+        if segments.is_empty() {
+            return Err(RstzError::new("Empty field path."));
+        }
+        Ok(FieldPath {
+            raw: path.to_string(),
+            segments,
+        })
+    }
+
+    /// Looks the path up in a flattened map of values, following its parsed segments only.
+    /// Keys containing dots are addressed with the quoted (`$['a.b']`) or pointer (`/a.b`) syntax.
+    pub fn lookup<'v>(&self, values: &'v BTreeMap<String, Value>) -> Option<&'v Value> {
+        let mut current = values.get(&self.segments[0])?;
+        for segment in &self.segments[1..] {
+            current = match current {
+                Value::Object(map) => map.get(segment)?,
+                Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        Some(current)
+    }
+}
+
+impl FromStr for FieldPath {
+    type Err = RstzError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        FieldPath::parse(s)
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+fn parse_pointer(path: &str) -> Vec<String> {
+    path[1..]
+        .split('/')
+        .map(|s| s.replace("~1", "/").replace("~0", "~"))
+        .collect()
+}
+
+fn parse_dotted(path: &str) -> Result<Vec<String>> {
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('[') {
+            let end = r
+                .find(']')
+                .ok_or_else(|| RstzError::new("Unclosed '[' in field path."))?;
+            let inner = r[..end].trim();
+            let unquoted = inner
+                .strip_prefix('\'')
+                .and_then(|s| s.strip_suffix('\''))
+                .or_else(|| inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')));
+            match unquoted {
+                Some(key) => segments.push(key.to_string()),
+                None if inner.parse::<usize>().is_ok() => segments.push(inner.to_string()),
+                None => return Err(RstzError::new("Bad index in field path.")),
+            }
+            rest = &r[end + 1..];
+        } else {
+            let r = rest.strip_prefix('.').unwrap_or(rest);
+            let end = r.find(['.', '[']).unwrap_or(r.len());
+            if end == 0 {
+                return Err(RstzError::new("Empty segment in field path."));
+            }
+            segments.push(r[..end].to_string());
+            rest = &r[end..];
+        }
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn segments(path: &str) -> Vec<String> {
+        FieldPath::parse(path).unwrap().segments
+    }
+
+    fn values() -> BTreeMap<String, Value> {
+        let mut values = BTreeMap::new();
+        values.insert(
+            "metrics".to_string(),
+            json!({"latency": {"p99": 12.5}, "a/b": 1, "c~d": 2, "cpus": [{"load": 0.5}, {"load": 0.75}]}),
+        );
+        values.insert("metrics.latency.p99".to_string(), json!(3.0));
+        values.insert("status".to_string(), json!("ok"));
+        values
+    }
+
+    #[test]
+    fn parses_every_form() {
+        assert_eq!(
+            segments("metrics.latency.p99"),
+            ["metrics", "latency", "p99"]
+        );
+        assert_eq!(segments("cpus[0].load"), ["cpus", "0", "load"]);
+        assert_eq!(
+            segments("$.metrics['latency'].p99"),
+            ["metrics", "latency", "p99"]
+        );
+        assert_eq!(
+            segments("$['metrics'][\"latency\"]"),
+            ["metrics", "latency"]
+        );
+        assert_eq!(segments("$['a.b'][ 1 ]"), ["a.b", "1"]);
+        assert_eq!(
+            segments("/metrics/latency/p99"),
+            ["metrics", "latency", "p99"]
+        );
+        assert_eq!(FieldPath::key("a.b").segments, ["a.b"]);
+    }
+
+    #[test]
+    fn unescapes_json_pointers() {
+        assert_eq!(segments("/metrics/a~1b"), ["metrics", "a/b"]);
+        assert_eq!(segments("/metrics/c~0d"), ["metrics", "c~d"]);
+        assert_eq!(segments("/~01"), ["~1"]);
+        assert_eq!(segments("/"), [""]);
+    }
+
+    #[test]
+    fn rejects_invalid_paths() {
+        for path in &["", "$", "a..b", "a.", ".", "a[", "a[x]", "a['b'", "$.[0]"] {
+            assert!(FieldPath::parse(path).is_err(), "{}", path);
+        }
+        assert!("a[b]".parse::<FieldPath>().is_err());
+    }
+
+    #[test]
+    fn looks_up_nested_values() {
+        let values = values();
+        let lookup = |path: &str| FieldPath::parse(path).unwrap().lookup(&values).cloned();
+        assert_eq!(lookup("metrics.latency.p99"), Some(json!(12.5)));
+        assert_eq!(lookup("$['metrics.latency.p99']"), Some(json!(3.0)));
+        assert_eq!(lookup("/metrics.latency.p99"), Some(json!(3.0)));
+        assert_eq!(lookup("$.metrics['latency'].p99"), Some(json!(12.5)));
+        assert_eq!(lookup("/metrics/latency/p99"), Some(json!(12.5)));
+        assert_eq!(lookup("metrics.cpus[1].load"), Some(json!(0.75)));
+        assert_eq!(lookup("/metrics/a~1b"), Some(json!(1)));
+        assert_eq!(lookup("/metrics/c~0d"), Some(json!(2)));
+        assert_eq!(lookup("status"), Some(json!("ok")));
+        assert_eq!(lookup("metrics.cpus[2].load"), None);
+        assert_eq!(lookup("metrics.cpus.load"), None);
+        assert_eq!(lookup("status.code"), None);
+        assert_eq!(lookup("missing"), None);
+    }
+
+    #[test]
+    fn displays_the_raw_path() {
+        let path: FieldPath = "$.metrics['latency']".parse().unwrap();
+        assert_eq!(path.to_string(), "$.metrics['latency']");
+    }
+}
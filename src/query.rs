@@ -0,0 +1,277 @@
+use crate::encodeco::{AnyDecoder, ErrorBound, TSDecoder};
+use crate::errors::{Result, RstzError};
+use crate::events::DataPoint;
+use crate::histogram::Histogram;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Decodes the points of `blocks` that fall within `[start, end]`, ordered by time.
+/// Fails on the first block that does not decode rather than returning part of the range.
+pub fn range(blocks: &[Vec<u8>], start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<DataPoint>> {
+    let mut points = Vec::new();
+    for block in blocks {
+        let mut decoder = TSDecoder::<AnyDecoder>::new(block);
+        if decoder.header()? > end {
+            continue;
+        }
+        while let Some(point) = decoder.try_decompress()? {
+            if point.timestamp() >= start && point.timestamp() <= end {
+                points.push(point);
+            }
+        }
+    }
+    points.sort_by_key(|p| p.timestamp());
+    Ok(points)
+}
+
+/// Distinct error bounds of the lossy blocks among `blocks`, empty when every value is exact.
+pub fn error_bounds(blocks: &[Vec<u8>]) -> Result<Vec<ErrorBound>> {
+    let mut bounds = Vec::new();
+    for block in blocks {
+        let bound = TSDecoder::<AnyDecoder>::new(block).values()?.error_bound();
+        if let Some(bound) = bound.filter(|bound| !bounds.contains(bound)) {
+            bounds.push(bound);
+        }
+    }
+    Ok(bounds)
+}
+
+/// `error_bounds` as a header value, such as `abs:0.5, rel:0.01`.
+pub fn error_bounds_header(blocks: &[Vec<u8>]) -> Result<Option<String>> {
+    let bounds: Vec<String> = error_bounds(blocks)?.iter().map(ErrorBound::to_string).collect();
+    Ok(Some(bounds.join(", ")).filter(|header| !header.is_empty()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Count,
+    First,
+    Last,
+    /// Per second increase of a counter, resets are treated as restarts from zero.
+    Rate,
+    /// Histograms of a step merged into one.
+    Merge,
+    /// Quantile of the histograms of a step merged together, such as `quantile:0.99`.
+    Quantile(f64),
+}
+
+impl FromStr for Aggregation {
+    type Err = RstzError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sum" => Ok(Aggregation::Sum),
+            "avg" => Ok(Aggregation::Avg),
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            "count" => Ok(Aggregation::Count),
+            "first" => Ok(Aggregation::First),
+            "last" => Ok(Aggregation::Last),
+            "rate" => Ok(Aggregation::Rate),
+            "merge" => Ok(Aggregation::Merge),
+            _ if s.starts_with("quantile:") => match s["quantile:".len()..].parse::<f64>() {
+                Ok(q) if (0.0..=1.0).contains(&q) => Ok(Aggregation::Quantile(q)),
+                _ => Err(RstzError::new(&format!("Quantiles are within 0 and 1: {}", s))),
+            },
+            _ => Err(RstzError::new(&format!("Unknown aggregation: {}", s))),
+        }
+    }
+}
+
+impl Aggregation {
+    /// Reduces time ordered `(timestamp, value)` pairs, `None` when there is not enough data.
+    /// Histogram aggregations have no numeric reduction.
+    pub fn reduce(self, points: &[(DateTime<Utc>, f64)]) -> Option<f64> {
+        let values = points.iter().map(|(_, v)| *v);
+        match self {
+            Aggregation::Sum => Some(values.sum()),
+            Aggregation::Avg if !points.is_empty() => Some(values.sum::<f64>() / points.len() as f64),
+            Aggregation::Min => values.reduce(f64::min),
+            Aggregation::Max => values.reduce(f64::max),
+            Aggregation::Count => Some(points.len() as f64),
+            Aggregation::First => points.first().map(|(_, v)| *v),
+            Aggregation::Last => points.last().map(|(_, v)| *v),
+            Aggregation::Rate if points.len() > 1 => {
+                let increase: f64 = points
+                    .windows(2)
+                    .map(|w| if w[1].1 >= w[0].1 { w[1].1 - w[0].1 } else { w[1].1 })
+                    .sum();
+                let (first, last) = (points[0].0, points[points.len() - 1].0);
+                let seconds = last.signed_duration_since(first).num_milliseconds() as f64 / 1000.0;
+                if seconds > 0.0 {
+                    Some(increase / seconds)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Groups points in `step` wide buckets aligned to the epoch and reduces each of them.
+/// Points holding values the aggregation cannot read are ignored, numbers for most of them and
+/// histograms for `merge` and `quantile`. Buckets are stamped with their start.
+pub fn aggregate(points: &[DataPoint], step: Duration, aggregation: Aggregation) -> Vec<DataPoint> {
+    match aggregation {
+        Aggregation::Merge | Aggregation::Quantile(_) => {
+            let histograms = points
+                .iter()
+                .filter_map(|p| Histogram::from_value(p.value()).ok().map(|h| (p.timestamp(), h)));
+            windows(histograms, step, |bucket| {
+                let (first, rest) = bucket.split_first()?;
+                let mut merged = first.1.clone();
+                for (_, histogram) in rest {
+                    merged.merge(histogram);
+                }
+                match aggregation {
+                    Aggregation::Quantile(q) => merged.quantile(q).map(Value::from),
+                    _ => Some(merged.to_value()),
+                }
+            })
+        }
+        _ => {
+            let numeric = points
+                .iter()
+                .filter_map(|p| p.value().as_f64().map(|v| (p.timestamp(), v)));
+            windows(numeric, step, |bucket| aggregation.reduce(bucket).map(Value::from))
+        }
+    }
+}
+
+fn windows<T>(
+    points: impl Iterator<Item = (DateTime<Utc>, T)>,
+    step: Duration,
+    reduce: impl Fn(&[(DateTime<Utc>, T)]) -> Option<Value>,
+) -> Vec<DataPoint> {
+    let step_ms = step.num_milliseconds().max(1);
+    let mut result = Vec::new();
+    let mut bucket = Vec::new();
+    let mut bucket_start = None;
+    for (timestamp, value) in points {
+        let start = timestamp.timestamp_millis().div_euclid(step_ms) * step_ms;
+        if bucket_start != Some(start) {
+            flush(&mut result, bucket_start, &bucket, &reduce);
+            bucket.clear();
+            bucket_start = Some(start);
+        }
+        bucket.push((timestamp, value));
+    }
+    flush(&mut result, bucket_start, &bucket, &reduce);
+    result
+}
+
+fn flush<T>(
+    result: &mut Vec<DataPoint>,
+    start: Option<i64>,
+    bucket: &[(DateTime<Utc>, T)],
+    reduce: impl Fn(&[(DateTime<Utc>, T)]) -> Option<Value>,
+) {
+    let start = start.and_then(|ms| Utc.timestamp_millis_opt(ms).single());
+    if let (Some(start), Some(value)) = (start, reduce(bucket)) {
+        result.push(DataPoint::new(start, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp(1_585_411_200 + seconds, 0)
+    }
+
+    /// A counter sampled every 15 seconds, reset to zero after the fourth sample.
+    fn counter() -> Vec<(DateTime<Utc>, f64)> {
+        [10.0, 20.0, 35.0, 50.0, 5.0, 25.0]
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (at(i as i64 * 15), *v))
+            .collect()
+    }
+
+    fn points(pairs: &[(DateTime<Utc>, f64)]) -> Vec<DataPoint> {
+        pairs.iter().map(|(t, v)| DataPoint::new(*t, Value::from(*v))).collect()
+    }
+
+    #[test]
+    fn parses_aggregations() {
+        assert_eq!("rate".parse::<Aggregation>(), Ok(Aggregation::Rate));
+        assert_eq!("quantile:0.99".parse::<Aggregation>(), Ok(Aggregation::Quantile(0.99)));
+        assert!("quantile:1.5".parse::<Aggregation>().is_err());
+        assert!("quantile:".parse::<Aggregation>().is_err());
+        assert!("median".parse::<Aggregation>().is_err());
+    }
+
+    #[test]
+    fn reduces_buckets() {
+        let points = counter();
+        let reduce = |aggregation: &str| aggregation.parse::<Aggregation>().unwrap().reduce(&points);
+        assert_eq!(reduce("sum"), Some(145.0));
+        assert_eq!(reduce("avg"), Some(145.0 / 6.0));
+        assert_eq!(reduce("min"), Some(5.0));
+        assert_eq!(reduce("max"), Some(50.0));
+        assert_eq!(reduce("count"), Some(6.0));
+        assert_eq!(reduce("first"), Some(10.0));
+        assert_eq!(reduce("last"), Some(25.0));
+        // 40 up to the reset, 5 from zero and 20 after it, over 75 seconds.
+        assert_eq!(reduce("rate"), Some(65.0 / 75.0));
+        assert_eq!(reduce("merge"), None);
+
+        assert_eq!(Aggregation::Sum.reduce(&[]), Some(0.0));
+        assert_eq!(Aggregation::Avg.reduce(&[]), None);
+        assert_eq!(Aggregation::Min.reduce(&[]), None);
+        assert_eq!(Aggregation::Rate.reduce(&points[..1]), None);
+        assert_eq!(Aggregation::Rate.reduce(&[(at(0), 1.0), (at(0), 2.0)]), None);
+    }
+
+    #[test]
+    fn aggregates_steps_aligned_to_the_epoch() {
+        let mut points = points(&counter());
+        points.push(DataPoint::new(at(80), Value::from("not a number")));
+        let step = Duration::seconds(30);
+        let sums: Vec<(DateTime<Utc>, Value)> = aggregate(&points, step, Aggregation::Sum)
+            .into_iter()
+            .map(|p| (p.timestamp(), p.value().clone()))
+            .collect();
+        assert_eq!(
+            sums,
+            vec![
+                (at(0), Value::from(30.0)),
+                (at(30), Value::from(85.0)),
+                (at(60), Value::from(30.0))
+            ]
+        );
+        let rates = aggregate(&points, step, Aggregation::Rate);
+        assert_eq!(rates[0].value(), &Value::from(10.0 / 15.0));
+        assert!(aggregate(&[], step, Aggregation::Count).is_empty());
+    }
+
+    #[test]
+    fn merges_histograms_per_step() {
+        let mut points = Vec::new();
+        for i in 0..4 {
+            let mut histogram = Histogram::new(3).unwrap();
+            for v in 1..=25 {
+                histogram.observe((v * (i + 1)) as f64);
+            }
+            points.push(DataPoint::new(at(i * 15), histogram.to_value()));
+        }
+        points.push(DataPoint::new(at(70), Value::from(1.0)));
+        let step = Duration::seconds(30);
+
+        let merged = aggregate(&points, step, Aggregation::Merge);
+        assert_eq!(merged.len(), 2);
+        let first = Histogram::from_value(merged[0].value()).unwrap();
+        assert_eq!(first.count(), 50);
+        let p50 = aggregate(&points, step, Aggregation::Quantile(0.5));
+        let p50 = p50[1].value().as_f64().unwrap();
+        // Multiples of 3 up to 75 and of 4 up to 100, half of them are at most 44.
+        assert!((p50 - 44.0).abs() / 44.0 < 0.1, "{}", p50);
+    }
+}
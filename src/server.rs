@@ -0,0 +1,300 @@
+use crate::collector::{log_routes, Collector};
+use crate::errors::{Result, RstzError};
+use crate::events::LogEvent;
+use crate::parsers::parse_timestamp;
+use crate::path::FieldPath;
+use crate::query::{self, Aggregation};
+use chrono::{Duration, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+type JsonResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+#[derive(Serialize)]
+struct IngestSummary {
+    accepted: usize,
+    rejected: usize,
+}
+
+/// JSON API over a collector:
+/// `POST /ingest` takes NDJSON Log Events, `GET /query?series=..&start=..&end=..&agg=..&step=..`
+/// returns decoded points or aggregates, `merge` and `quantile:Q` ones over histograms, along with
+/// an `X-Error-Bound` header when lossy blocks were read, `GET /series` lists the catalog and `GET /stats?series=..`
+/// reports how well a series compresses.
+/// Returns once the server is unblocked, leaving the caller to flush open blocks.
+pub fn serve(server: &Server, collector: &Collector, fields: &[FieldPath]) {
+    for mut request in server.incoming_requests() {
+        let response = match handle(&mut request, collector, fields) {
+            Ok(response) => response,
+            Err(e) => error(400, &e.to_string()),
+        };
+        if let Err(e) = request.respond(response) {
+            eprintln!("Failed to respond: {}", e);
+        }
+    }
+}
+
+fn handle(request: &mut Request, collector: &Collector, fields: &[FieldPath]) -> Result<JsonResponse> {
+    let url = request.url().to_string();
+    let (path, raw_query) = url.split_once('?').unwrap_or((&url, ""));
+    let params = query_params(raw_query);
+    match (request.method(), path) {
+        (Method::Post, "/ingest") => ingest(request, collector, fields),
+        (Method::Get, "/query") => query(&params, collector),
+        (Method::Get, "/series") => json(&collector.series()),
+        (Method::Get, "/stats") => stats(&params, collector),
+        _ => Ok(error(404, "Not found")),
+    }
+}
+
+/// Lines that are not Log Events are counted as rejected, the others are still ingested.
+fn ingest(request: &mut Request, collector: &Collector, fields: &[FieldPath]) -> Result<JsonResponse> {
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+    let mut summary = IngestSummary {
+        accepted: 0,
+        rejected: 0,
+    };
+    for line in body.lines().filter(|l| !l.trim().is_empty()) {
+        let event: LogEvent = match serde_json::from_str(line) {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("Skipping line: {}", e);
+                summary.rejected += 1;
+                continue;
+            }
+        };
+        let routes = log_routes(&event, fields);
+        match collector.ingest_routes(&event, &routes) {
+            Ok(()) if !routes.is_empty() => summary.accepted += 1,
+            Ok(()) => summary.rejected += 1,
+            Err(e) => {
+                eprintln!("Skipping event: {}", e);
+                summary.rejected += 1;
+            }
+        }
+    }
+    json(&summary)
+}
+
+fn query(params: &BTreeMap<String, String>, collector: &Collector) -> Result<JsonResponse> {
+    let series = params
+        .get("series")
+        .ok_or_else(|| RstzError::new("Missing series parameter."))?;
+    let start = match params.get("start") {
+        Some(start) => parse_timestamp(start)?,
+        None => Utc.timestamp_opt(0, 0).unwrap(),
+    };
+    let end = match params.get("end") {
+        Some(end) => parse_timestamp(end)?,
+        None => Utc::now(),
+    };
+    if !collector.contains(series) {
+        return Ok(error(404, &format!("Unknown series: {}", series)));
+    }
+    let blocks = collector.blocks(series);
+    let decoded = query::range(&blocks, start, end)
+        .and_then(|points| Ok((points, query::error_bounds_header(&blocks)?)));
+    let (points, bounds) = match decoded {
+        Ok(decoded) => decoded,
+        Err(e) => return Ok(error(500, &format!("Stored blocks do not decode: {}", e))),
+    };
+    let response = match params.get("agg") {
+        Some(agg) => {
+            let aggregation: Aggregation = agg.parse()?;
+            let step = match params.get("step") {
+                Some(step) => step
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| RstzError::new("Step must be a positive number of seconds."))?,
+                None => 60,
+            };
+            json(&query::aggregate(&points, Duration::seconds(step), aggregation))
+        }
+        None => json(&points),
+    }?;
+    Ok(match bounds {
+        Some(bounds) => response.with_header(
+            Header::from_bytes(&b"X-Error-Bound"[..], bounds.as_bytes())
+                .map_err(|_| RstzError::new("Error bounds are not a valid header."))?,
+        ),
+        None => response,
+    })
+}
+
+fn stats(params: &BTreeMap<String, String>, collector: &Collector) -> Result<JsonResponse> {
+    let series = params
+        .get("series")
+        .ok_or_else(|| RstzError::new("Missing series parameter."))?;
+    match collector.stats(series) {
+        Some(stats) => json(&stats),
+        None if collector.contains(series) => Ok(error(404, &format!("No encoder open for series: {}", series))),
+        None => Ok(error(404, &format!("Unknown series: {}", series))),
+    }
+}
+
+fn json_header() -> Header {
+    Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("Static header is valid.")
+}
+
+fn json<T: Serialize>(body: &T) -> Result<JsonResponse> {
+    Ok(Response::from_data(serde_json::to_vec(body)?).with_header(json_header()))
+}
+
+fn error(status: u16, message: &str) -> JsonResponse {
+    let body = serde_json::json!({ "error": message });
+    Response::from_data(body.to_string().into_bytes())
+        .with_status_code(status)
+        .with_header(json_header())
+}
+
+fn query_params(query: &str) -> BTreeMap<String, String> {
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (k, v) = p.split_once('=').unwrap_or((p, ""));
+            (percent_decode(k), percent_decode(v))
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).filter(|h| h.iter().all(u8::is_ascii_hexdigit));
+        match (bytes[i], hex) {
+            (b'+', _) => decoded.push(b' '),
+            (b'%', Some(hex)) => {
+                let hex = std::str::from_utf8(hex).expect("Hex digits are ASCII.");
+                decoded.push(u8::from_str_radix(hex, 16).expect("Checked hex digits."));
+                i += 2;
+            }
+            (b, _) => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::BlockStore;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    const EVENTS: &str = r#"{"timestamp":"2020-03-28T16:00:00Z","host":"a","latency":10.0}
+{"timestamp":"2020-03-28T16:00:30Z","host":"a","latency":
+{"timestamp":"2020-03-28T16:01:10Z","host":"a","latency":30.0}
+
+{"timestamp":"2020-03-28T16:01:20Z","host":"a","message":"no numbers"}
+"#;
+    const SERIES: &str = "latency%7Bhost%3D%22a%22%7D";
+
+    /// Serves `collector` on a free port until the returned server is unblocked.
+    fn start(collector: Collector) -> (Arc<Server>, SocketAddr) {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let addr = server.server_addr().to_ip().unwrap();
+        let fields = vec![FieldPath::key("latency")];
+        {
+            let server = Arc::clone(&server);
+            thread::spawn(move || serve(&server, &collector, &fields));
+        }
+        (server, addr)
+    }
+
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap_or_default().to_string();
+        (status, body)
+    }
+
+    fn collector() -> Collector {
+        Collector::new(Duration::minutes(120), BlockStore::in_memory())
+    }
+
+    #[test]
+    fn ingests_and_queries() {
+        let (server, addr) = start(collector());
+        let (status, body) = request(addr, "POST", "/ingest", EVENTS);
+        assert_eq!((status, body.as_str()), (200, r#"{"accepted":2,"rejected":2}"#));
+
+        let (status, body) = request(addr, "GET", "/series", "");
+        assert_eq!((status, body.as_str()), (200, r#"["latency{host=\"a\"}"]"#));
+
+        let (status, body) = request(addr, "GET", &format!("/query?series={}", SERIES), "");
+        assert_eq!(status, 200);
+        let points: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1]["value"], 30.0);
+
+        let path = format!("/query?series={}&agg=avg&step=3600", SERIES);
+        let (status, body) = request(addr, "GET", &path, "");
+        assert_eq!(status, 200);
+        let points: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0]["value"], 20.0);
+
+        let path = format!("/query?series={}&start=2020-03-28T16:01:00Z", SERIES);
+        let (_, body) = request(addr, "GET", &path, "");
+        assert_eq!(serde_json::from_str::<Vec<serde_json::Value>>(&body).unwrap().len(), 1);
+        server.unblock();
+    }
+
+    #[test]
+    fn reports_bad_requests() {
+        let (server, addr) = start(collector());
+        request(addr, "POST", "/ingest", EVENTS);
+        assert_eq!(request(addr, "GET", "/query", "").0, 400);
+        assert_eq!(request(addr, "GET", "/query?series=cpu", "").0, 404);
+        assert_eq!(request(addr, "GET", &format!("/query?series={}&agg=median", SERIES), "").0, 400);
+        assert_eq!(request(addr, "GET", &format!("/query?series={}&agg=sum&step=0", SERIES), "").0, 400);
+        assert_eq!(request(addr, "GET", &format!("/query?series={}&start=soon", SERIES), "").0, 400);
+        assert_eq!(request(addr, "DELETE", "/series", "").0, 404);
+        assert_eq!(request(addr, "GET", "/stats?series=cpu", "").0, 404);
+        assert_eq!(request(addr, "GET", &format!("/stats?series={}", SERIES), "").0, 200);
+        server.unblock();
+    }
+
+    #[test]
+    fn fails_queries_over_corrupt_blocks() {
+        let mut store = BlockStore::in_memory();
+        let mut block = vec![0x07; 16];
+        block.push(0);
+        store.append("broken", block).unwrap();
+        let (server, addr) = start(Collector::new(Duration::minutes(120), store));
+        let (status, body) = request(addr, "GET", "/query?series=broken", "");
+        assert_eq!(status, 500);
+        assert!(body.contains("Unsupported block format version 7."), "{}", body);
+        server.unblock();
+    }
+
+    #[test]
+    fn decodes_query_strings() {
+        let params = query_params("series=cpu%7Bhost%3D%22a%22%7D&agg=quantile%3A0.9&flag&q=a+b");
+        assert_eq!(params["series"], "cpu{host=\"a\"}");
+        assert_eq!(params["agg"], "quantile:0.9");
+        assert_eq!(params["flag"], "");
+        assert_eq!(params["q"], "a b");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+}
@@ -0,0 +1,477 @@
+use crate::collector::{series_key, Collector, Route};
+use crate::errors::{Result, RstzError};
+use crate::events::LogEvent;
+use crate::path::FieldPath;
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Method, Request, Response, Server};
+
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+/// Unit of line protocol timestamps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precision {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+    Minutes,
+    Hours,
+}
+
+impl FromStr for Precision {
+    type Err = RstzError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "n" | "ns" => Ok(Precision::Nanoseconds),
+            "u" | "us" => Ok(Precision::Microseconds),
+            "ms" => Ok(Precision::Milliseconds),
+            "s" => Ok(Precision::Seconds),
+            "m" => Ok(Precision::Minutes),
+            "h" => Ok(Precision::Hours),
+            _ => Err(RstzError::new(&format!("Unknown precision: {}", s))),
+        }
+    }
+}
+
+impl Precision {
+    fn nanos(self) -> i64 {
+        match self {
+            Precision::Nanoseconds => 1,
+            Precision::Microseconds => 1_000,
+            Precision::Milliseconds => 1_000_000,
+            Precision::Seconds => 1_000_000_000,
+            Precision::Minutes => 60_000_000_000,
+            Precision::Hours => 3_600_000_000_000,
+        }
+    }
+}
+
+/// A parsed `measurement,tag=v field=1.0 ts` line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub measurement: String,
+    pub tags: BTreeMap<String, String>,
+    pub fields: BTreeMap<String, Value>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl Point {
+    pub fn parse(line: &str, precision: Precision) -> Result<Self> {
+        let sections = split(line, ' ', true);
+        let (series, fields, timestamp) = match sections.as_slice() {
+            [series, fields] => (*series, *fields, None),
+            [series, fields, ts] => (*series, *fields, Some(*ts)),
+            _ => return Err(RstzError::new("Expected measurement, fields and optional timestamp.")),
+        };
+
+        let mut series = split(series, ',', false).into_iter();
+        let measurement = unescape(series.next().unwrap_or_default());
+        if measurement.is_empty() {
+            return Err(RstzError::new("Missing measurement."));
+        }
+        let mut tags = BTreeMap::new();
+        for tag in series {
+            let (key, value) = key_value(tag)?;
+            tags.insert(key, unescape(value));
+        }
+
+        let mut parsed = BTreeMap::new();
+        for field in split(fields, ',', true) {
+            let (key, value) = key_value(field)?;
+            parsed.insert(key, field_value(value)?);
+        }
+
+        let timestamp = match timestamp {
+            Some(ts) => {
+                let ts = ts
+                    .parse::<i64>()
+                    .map_err(|_| RstzError::new(&format!("Invalid timestamp: {}", ts)))?;
+                let nanos = ts
+                    .checked_mul(precision.nanos())
+                    .ok_or_else(|| RstzError::new("Timestamp out of range."))?;
+                Some(Utc.timestamp_nanos(nanos))
+            }
+            None => None,
+        };
+        Ok(Point {
+            measurement,
+            tags,
+            fields: parsed,
+            timestamp,
+        })
+    }
+
+    /// Numeric fields feed `measurement_field{tags}` series, the `host` tag becomes the event host.
+    pub fn into_routes(self) -> (LogEvent, Vec<Route>) {
+        let labels = || self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+        let routes = self
+            .fields
+            .iter()
+            .filter(|(_, value)| value.is_number())
+            .map(|(field, _)| {
+                let name = format!("{}_{}", self.measurement, field);
+                (series_key(&name, labels()), FieldPath::key(field))
+            })
+            .collect();
+        let host = self.tags.get("host").cloned().unwrap_or_default();
+        let timestamp = self.timestamp.unwrap_or_else(Utc::now);
+        (LogEvent::new(timestamp, host, self.fields), routes)
+    }
+}
+
+/// Splits on `sep`, skipping escaped characters and, when asked, separators inside double quotes.
+fn split(s: &str, sep: char, quotes: bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut quoted = false;
+    for (idx, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' if quotes => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&s[start..idx]);
+                start = idx + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn unescape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next)) if matches!(next, ',' | '=' | ' ' | '"' | '\\') => {
+                result.push(next);
+                chars.next();
+            }
+            (c, _) => result.push(c),
+        }
+    }
+    result
+}
+
+fn key_value(pair: &str) -> Result<(String, &str)> {
+    match split(pair, '=', true).as_slice() {
+        [key, value] if !key.is_empty() => Ok((unescape(key), value)),
+        _ => Err(RstzError::new(&format!("Expected key=value, found {}", pair))),
+    }
+}
+
+fn field_value(raw: &str) -> Result<Value> {
+    let invalid = || RstzError::new(&format!("Invalid field value: {}", raw));
+    if let Some(s) = raw.strip_prefix('"') {
+        let s = s.strip_suffix('"').ok_or_else(invalid)?;
+        return Ok(Value::String(unescape(s)));
+    }
+    if let Some(i) = raw.strip_suffix('i') {
+        return i.parse::<i64>().map(Value::from).map_err(|_| invalid());
+    }
+    if let Some(u) = raw.strip_suffix('u') {
+        return u.parse::<u64>().map(Value::from).map_err(|_| invalid());
+    }
+    match raw {
+        "t" | "T" | "true" | "True" | "TRUE" => Ok(Value::Bool(true)),
+        "f" | "F" | "false" | "False" | "FALSE" => Ok(Value::Bool(false)),
+        _ => raw
+            .parse::<f64>()
+            .ok()
+            .filter(|f| f.is_finite())
+            .map(Value::from)
+            .ok_or_else(invalid),
+    }
+}
+
+/// Points of a payload that were stored whole, those only some fields of were stored
+/// and those not stored at all.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Ingested {
+    pub accepted: usize,
+    pub partial: usize,
+    pub rejected: usize,
+}
+
+impl Ingested {
+    fn add(&mut self, other: Ingested) {
+        self.accepted += other.accepted;
+        self.partial += other.partial;
+        self.rejected += other.rejected;
+    }
+}
+
+/// Parses and stores every line of a payload.
+/// Bad lines are reported, counted and skipped so one malformed point does not drop a whole batch.
+/// Every field of a point is tried, so one its series refuses does not keep the others out.
+pub fn ingest_lines(body: &str, precision: Precision, collector: &Collector) -> Ingested {
+    let mut ingested = Ingested::default();
+    for line in body.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        let (event, routes) = match Point::parse(line, precision) {
+            Ok(point) => point.into_routes(),
+            Err(e) => {
+                eprintln!("Skipping point: {}", e);
+                ingested.rejected += 1;
+                continue;
+            }
+        };
+        let mut stored = 0;
+        for (series, field) in &routes {
+            match collector.ingest(series, field, &event) {
+                Ok(()) => stored += 1,
+                Err(e) => eprintln!("Skipping {} of point: {}", series, e),
+            }
+        }
+        if stored == routes.len() {
+            ingested.accepted += 1;
+        } else if stored > 0 {
+            ingested.partial += 1;
+        } else {
+            ingested.rejected += 1;
+        }
+    }
+    ingested
+}
+
+/// Accepts newline delimited points over TCP, timestamps in nanoseconds.
+/// Returns at the first connection accepted once `stop` is set, leaving the caller to flush open blocks.
+pub fn serve_tcp(listener: TcpListener, collector: Arc<Collector>, stop: &AtomicBool) {
+    for stream in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept a line protocol connection: {}", e);
+                continue;
+            }
+        };
+        let collector = Arc::clone(&collector);
+        thread::spawn(move || match handle_connection(stream, &collector) {
+            Ok(ingested) if ingested.rejected > 0 || ingested.partial > 0 => eprintln!(
+                "Line protocol connection closed, {} points rejected, {} partly stored.",
+                ingested.rejected, ingested.partial
+            ),
+            Ok(_) => {}
+            Err(e) => eprintln!("Line protocol connection closed: {}", e),
+        });
+    }
+}
+
+fn handle_connection(stream: TcpStream, collector: &Collector) -> Result<Ingested> {
+    let mut total = Ingested::default();
+    for line in BufReader::new(stream).lines() {
+        total.add(ingest_lines(&line?, Precision::Nanoseconds, collector));
+    }
+    Ok(total)
+}
+
+/// Accepts datagrams holding one or more points, timestamps in nanoseconds.
+/// Errors are reported and the socket keeps being read until a datagram arrives once `stop` is set.
+pub fn serve_udp(socket: UdpSocket, collector: &Collector, stop: &AtomicBool) {
+    let mut buf = vec![0; MAX_DATAGRAM];
+    loop {
+        let received = socket.recv_from(&mut buf);
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let len = match received {
+            Ok((len, _)) => len,
+            Err(e) => {
+                eprintln!("Failed to receive a line protocol datagram: {}", e);
+                continue;
+            }
+        };
+        match std::str::from_utf8(&buf[..len]) {
+            Ok(body) => {
+                let ingested = ingest_lines(body, Precision::Nanoseconds, collector);
+                if ingested.rejected > 0 || ingested.partial > 0 {
+                    eprintln!(
+                        "Rejected {} points of a datagram, {} partly stored.",
+                        ingested.rejected, ingested.partial
+                    );
+                }
+            }
+            Err(e) => eprintln!("Skipping datagram: {}", e),
+        }
+    }
+}
+
+/// Serves the InfluxDB 1.x `/write` and 2.x `/api/v2/write` endpoints, honouring `precision`.
+/// Returns once the server is unblocked.
+pub fn serve_http(server: &Server, collector: &Collector) {
+    for mut request in server.incoming_requests() {
+        let path = request.url().split('?').next().unwrap_or_default().to_string();
+        let response = match (request.method(), path.as_str()) {
+            (Method::Post, "/write") | (Method::Post, "/api/v2/write") => {
+                match handle_write(&mut request, collector) {
+                    Ok(ingested) if ingested.rejected == 0 && ingested.partial == 0 => {
+                        Response::from_data(Vec::new()).with_status_code(204)
+                    }
+                    // InfluxDB answers partial writes with a 400 as well, the good points are kept.
+                    Ok(ingested) => Response::from_string(format!(
+                        "partial write: {} points rejected, {} partly stored, {} accepted",
+                        ingested.rejected, ingested.partial, ingested.accepted
+                    ))
+                    .with_status_code(400),
+                    Err(e) => Response::from_string(e.to_string()).with_status_code(400),
+                }
+            }
+            (Method::Get, "/ping") => Response::from_data(Vec::new()).with_status_code(204),
+            _ => Response::from_data(Vec::new()).with_status_code(404),
+        };
+        if let Err(e) = request.respond(response) {
+            eprintln!("Failed to respond: {}", e);
+        }
+    }
+}
+
+fn handle_write(request: &mut Request, collector: &Collector) -> Result<Ingested> {
+    let precision = request
+        .url()
+        .split('?')
+        .nth(1)
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| param.strip_prefix("precision="))
+        .map_or(Ok(Precision::Nanoseconds), str::parse)?;
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+    Ok(ingest_lines(&body, precision, collector))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encodeco::TimestampCodec;
+    use crate::query;
+    use crate::store::BlockStore;
+    use chrono::Duration;
+    use std::io::Write;
+    use std::net::Shutdown;
+
+    fn collector() -> Collector {
+        Collector::new(Duration::minutes(120), BlockStore::in_memory())
+    }
+
+    #[test]
+    fn parses_lines() {
+        let point = Point::parse(
+            r#"cpu\ load,host=web\,1,region=eu usage=0.5,cores=8i,name="a \"b\", c",up=t 1585411200000"#,
+            Precision::Milliseconds,
+        )
+        .unwrap();
+        assert_eq!(point.measurement, "cpu load");
+        assert_eq!(point.tags["host"], "web,1");
+        assert_eq!(point.fields["usage"], Value::from(0.5));
+        assert_eq!(point.fields["cores"], Value::from(8));
+        assert_eq!(point.fields["name"], Value::from("a \"b\", c"));
+        assert_eq!(point.fields["up"], Value::Bool(true));
+        assert_eq!(point.timestamp, Some(Utc.timestamp(1_585_411_200, 0)));
+        assert!(Point::parse("cpu", Precision::Seconds).is_err());
+        assert!(Point::parse("cpu usage=", Precision::Seconds).is_err());
+        assert!(Point::parse("cpu usage=1 soon", Precision::Seconds).is_err());
+        assert!(Point::parse("cpu usage=1 9223372036854775807", Precision::Hours).is_err());
+    }
+
+    #[test]
+    fn counts_rejected_lines() {
+        let collector = collector();
+        let body = "cpu,host=a usage=1 1585411200\n# comment\ncpu usage=\n\ncpu,host=a usage=2 1585411210\nmem free=x";
+        let ingested = ingest_lines(body, Precision::Seconds, &collector);
+        assert_eq!(
+            ingested,
+            Ingested {
+                accepted: 2,
+                partial: 0,
+                rejected: 2
+            }
+        );
+        collector.flush().unwrap();
+        let points = query::range(&collector.blocks("cpu_usage{host=\"a\"}"), Utc.timestamp(0, 0), Utc::now()).unwrap();
+        assert_eq!(points.len(), 2);
+    }
+
+    #[test]
+    fn counts_partly_stored_points() {
+        let collector = collector().with_series_timestamps("cpu_usage", TimestampCodec::Seconds);
+        let body = "cpu usage=1,idle=2 1585411200500\ncpu usage=3,idle=4 1585411210000";
+        let ingested = ingest_lines(body, Precision::Milliseconds, &collector);
+        assert_eq!(
+            ingested,
+            Ingested {
+                accepted: 1,
+                partial: 1,
+                rejected: 0
+            }
+        );
+        collector.flush().unwrap();
+        let idle = query::range(&collector.blocks("cpu_idle"), Utc.timestamp(0, 0), Utc::now()).unwrap();
+        assert_eq!(idle.len(), 2);
+        let usage = query::range(&collector.blocks("cpu_usage"), Utc.timestamp(0, 0), Utc::now()).unwrap();
+        assert_eq!(usage.len(), 1);
+    }
+
+    #[test]
+    fn keeps_reading_datagrams_after_bad_ones() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let collector = Arc::new(collector());
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (collector, stop) = (Arc::clone(&collector), Arc::clone(&stop));
+            thread::spawn(move || serve_udp(socket, &collector, &stop))
+        };
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"broken\ncpu usage=1 1585411200000000000", addr).unwrap();
+        client.send_to(&[0xff, 0xfe], addr).unwrap();
+        client.send_to(b"mem free=3 1585411200000000000", addr).unwrap();
+        let deadline = Utc::now() + Duration::seconds(5);
+        while collector.series().len() < 2 && Utc::now() < deadline {
+            thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(collector.series(), vec!["cpu_usage".to_string(), "mem_free".to_string()]);
+
+        stop.store(true, Ordering::SeqCst);
+        client.send_to(&[], addr).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn serves_tcp_until_stopped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let collector = Arc::new(collector());
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (collector, stop) = (Arc::clone(&collector), Arc::clone(&stop));
+            thread::spawn(move || serve_tcp(listener, collector, &stop))
+        };
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        writeln!(stream, "cpu usage=1 1585411200000000000\nbroken").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let deadline = Utc::now() + Duration::seconds(5);
+        while collector.series().is_empty() && Utc::now() < deadline {
+            thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(collector.series(), vec!["cpu_usage".to_string()]);
+
+        stop.store(true, Ordering::SeqCst);
+        TcpStream::connect(addr).unwrap();
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,4 @@
+pub mod influx;
+pub mod prometheus;
+pub mod statsd;
+pub mod vector;
@@ -0,0 +1,603 @@
+use crate::collector::{parse_series_key, series_key, Collector};
+use crate::errors::{Result, RstzError};
+use crate::events::LogEvent;
+use crate::path::FieldPath;
+use crate::query;
+use chrono::{TimeZone, Utc};
+use prost::Message;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+pub const WRITE_PATH: &str = "/api/v1/write";
+pub const READ_PATH: &str = "/api/v1/read";
+
+/// Remote write payload, only the fields rstz stores are declared.
+#[derive(Clone, PartialEq, Message)]
+pub struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ReadRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub queries: Vec<Query>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Query {
+    #[prost(int64, tag = "1")]
+    pub start_timestamp_ms: i64,
+    #[prost(int64, tag = "2")]
+    pub end_timestamp_ms: i64,
+    #[prost(message, repeated, tag = "3")]
+    pub matchers: Vec<LabelMatcher>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LabelMatcher {
+    #[prost(enumeration = "MatchType", tag = "1")]
+    pub r#type: i32,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub value: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+#[repr(i32)]
+pub enum MatchType {
+    Eq = 0,
+    Neq = 1,
+    Re = 2,
+    Nre = 3,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ReadResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub results: Vec<QueryResult>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct QueryResult {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+/// A label matcher with its regular expression compiled, built once per query.
+struct Matcher<'q> {
+    name: &'q str,
+    kind: MatchType,
+    value: &'q str,
+    regex: Option<Regex>,
+}
+
+impl LabelMatcher {
+    /// Regular expressions are fully anchored, the way Prometheus matches them.
+    fn compile(&self) -> Result<Matcher<'_>> {
+        let kind = MatchType::try_from(self.r#type)
+            .map_err(|_| RstzError::new("Unknown label matcher type."))?;
+        let regex = match kind {
+            MatchType::Re | MatchType::Nre => Some(
+                Regex::new(&format!("^(?:{})$", self.value))
+                    .map_err(|e| RstzError::new(&e.to_string()))?,
+            ),
+            MatchType::Eq | MatchType::Neq => None,
+        };
+        Ok(Matcher {
+            name: &self.name,
+            kind,
+            value: &self.value,
+            regex,
+        })
+    }
+}
+
+impl Matcher<'_> {
+    /// A missing label reads as the empty string.
+    fn matches(&self, labels: &[(String, String)]) -> bool {
+        let value = labels
+            .iter()
+            .find(|(name, _)| name == self.name)
+            .map_or("", |(_, value)| value.as_str());
+        match (&self.regex, self.kind) {
+            (Some(re), kind) => re.is_match(value) == (kind == MatchType::Re),
+            (None, MatchType::Neq) => value != self.value,
+            (None, _) => value == self.value,
+        }
+    }
+}
+
+impl TimeSeries {
+    /// `__name__{label="value",...}`, the key used for this series in the store.
+    pub fn key(&self) -> Result<String> {
+        let name = self
+            .labels
+            .iter()
+            .find(|l| l.name == "__name__")
+            .ok_or_else(|| RstzError::new("Time series without __name__ label."))?;
+        let labels = self
+            .labels
+            .iter()
+            .filter(|l| l.name != "__name__")
+            .map(|l| (l.name.as_str(), l.value.as_str()));
+        Ok(series_key(&name.value, labels))
+    }
+}
+
+/// Decodes a snappy compressed protobuf message, the framing used by both remote write and read.
+pub fn decode_snappy<M: Message + Default>(body: &[u8]) -> Result<M> {
+    let raw = snap::raw::Decoder::new()
+        .decompress_vec(body)
+        .map_err(|e| RstzError::new(&format!("Invalid snappy payload: {}", e)))?;
+    M::decode(raw.as_slice()).map_err(|e| RstzError::new(&format!("Invalid protobuf message: {}", e)))
+}
+
+pub fn encode_snappy<M: Message>(message: &M) -> Result<Vec<u8>> {
+    snap::raw::Encoder::new()
+        .compress_vec(&message.encode_to_vec())
+        .map_err(|e| RstzError::new(&e.to_string()))
+}
+
+/// Samples of a remote write that were stored, the non finite ones that were dropped
+/// and the ones their series refused.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Written {
+    pub stored: usize,
+    pub dropped: usize,
+    pub rejected: usize,
+}
+
+/// Feeds every sample to its series encoder.
+/// Series keys and timestamps are checked up front so a malformed request stores nothing.
+/// A sample its encoder or the store refuses is counted as rejected, the others are still stored.
+/// Non finite samples, staleness markers included, cannot be represented as JSON values;
+/// they are dropped and counted.
+pub fn ingest_write_request(request: &WriteRequest, collector: &Collector) -> Result<Written> {
+    let field: FieldPath = "value".parse()?;
+    let mut batches = Vec::with_capacity(request.timeseries.len());
+    let mut written = Written::default();
+    for ts in &request.timeseries {
+        let key = ts.key()?;
+        let host = ts
+            .labels
+            .iter()
+            .find(|l| l.name == "instance")
+            .map(|l| l.value.clone())
+            .unwrap_or_default();
+        let mut samples = Vec::with_capacity(ts.samples.len());
+        for sample in &ts.samples {
+            if !sample.value.is_finite() {
+                written.dropped += 1;
+                continue;
+            }
+            let timestamp = Utc
+                .timestamp_millis_opt(sample.timestamp)
+                .single()
+                .ok_or_else(|| RstzError::new("Sample timestamp out of range."))?;
+            samples.push((timestamp, sample.value));
+        }
+        batches.push((key, host, samples));
+    }
+    for (key, host, samples) in batches {
+        for (timestamp, value) in samples {
+            let mut values = BTreeMap::new();
+            values.insert("value".to_string(), Value::from(value));
+            match collector.ingest(&key, &field, &LogEvent::new(timestamp, host.clone(), values)) {
+                Ok(()) => written.stored += 1,
+                Err(e) => {
+                    eprintln!("Rejected sample of {} at {}: {}", key, timestamp, e);
+                    written.rejected += 1;
+                }
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// Answers every query with the stored samples of the series matching it.
+/// Matchers are compiled once, up front, so an invalid one fails the read before any series is walked.
+pub fn read(request: &ReadRequest, collector: &Collector) -> Result<ReadResponse> {
+    let matchers = request
+        .queries
+        .iter()
+        .map(|q| q.matchers.iter().map(LabelMatcher::compile).collect())
+        .collect::<Result<Vec<Vec<_>>>>()?;
+    let mut results = Vec::new();
+    for (q, matchers) in request.queries.iter().zip(&matchers) {
+        let start = Utc.timestamp_millis_opt(q.start_timestamp_ms).single();
+        let end = Utc.timestamp_millis_opt(q.end_timestamp_ms).single();
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Err(RstzError::new("Query range out of bounds.")),
+        };
+        let mut timeseries = Vec::new();
+        for key in collector.series() {
+            let (name, mut labels) = parse_series_key(&key)?;
+            labels.push(("__name__".to_string(), name));
+            if !matchers.iter().all(|m| m.matches(&labels)) {
+                continue;
+            }
+            let samples: Vec<Sample> = query::range(&collector.blocks(&key), start, end)?
+                .iter()
+                .filter_map(|p| {
+                    p.value().as_f64().map(|value| Sample {
+                        value,
+                        timestamp: p.timestamp().timestamp_millis(),
+                    })
+                })
+                .collect();
+            if samples.is_empty() {
+                continue;
+            }
+            labels.sort();
+            let labels = labels
+                .into_iter()
+                .map(|(name, value)| Label { name, value })
+                .collect();
+            timeseries.push(TimeSeries { labels, samples });
+        }
+        results.push(QueryResult { timeseries });
+    }
+    Ok(ReadResponse { results })
+}
+
+/// Serves the remote write and remote read endpoints until the server is unblocked.
+pub fn serve(server: &Server, collector: &Collector) {
+    for mut request in server.incoming_requests() {
+        let response = match (request.method(), request.url()) {
+            (Method::Post, WRITE_PATH) => match handle_write(&mut request, collector) {
+                Ok(written) => {
+                    if written.dropped > 0 {
+                        eprintln!("Dropped {} non finite samples from remote write.", written.dropped);
+                    }
+                    if written.rejected == 0 {
+                        Response::from_data(Vec::new()).with_status_code(204)
+                    } else {
+                        // A 400 keeps Prometheus from resending the samples that were stored.
+                        Response::from_string(format!(
+                            "partial write: {} samples rejected, {} stored",
+                            written.rejected, written.stored
+                        ))
+                        .with_status_code(400)
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Rejected remote write: {}", e);
+                    Response::from_string(e.to_string()).with_status_code(400)
+                }
+            },
+            (Method::Post, READ_PATH) => match handle_read(&mut request, collector) {
+                Ok(body) => Response::from_data(body)
+                    .with_header(header("Content-Type", "application/x-protobuf"))
+                    .with_header(header("Content-Encoding", "snappy")),
+                Err(e) if e.is_corrupt_block() => {
+                    eprintln!("Failed remote read: {}", e);
+                    Response::from_string(e.to_string()).with_status_code(500)
+                }
+                Err(e) => {
+                    eprintln!("Rejected remote read: {}", e);
+                    Response::from_string(e.to_string()).with_status_code(400)
+                }
+            },
+            _ => Response::from_data(Vec::new()).with_status_code(404),
+        };
+        if let Err(e) = request.respond(response) {
+            eprintln!("Failed to respond: {}", e);
+        }
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("Static header is valid.")
+}
+
+fn read_body(request: &mut Request) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    request.as_reader().read_to_end(&mut body)?;
+    Ok(body)
+}
+
+fn handle_write(request: &mut Request, collector: &Collector) -> Result<Written> {
+    let write: WriteRequest = decode_snappy(&read_body(request)?)?;
+    ingest_write_request(&write, collector)
+}
+
+fn handle_read(request: &mut Request, collector: &Collector) -> Result<Vec<u8>> {
+    let read_request: ReadRequest = decode_snappy(&read_body(request)?)?;
+    encode_snappy(&read(&read_request, collector)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encodeco::TimestampCodec;
+    use crate::store::BlockStore;
+    use chrono::Duration;
+    use std::io::Read;
+
+    fn label(name: &str, value: &str) -> Label {
+        Label {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn payload() -> Vec<u8> {
+        let request = WriteRequest {
+            timeseries: vec![
+                TimeSeries {
+                    labels: vec![
+                        label("__name__", "up"),
+                        label("job", "node"),
+                        label("instance", "localhost:9100"),
+                    ],
+                    samples: (0..10)
+                        .map(|i| Sample {
+                            value: 1.0,
+                            timestamp: 1_585_413_000_000 + i * 15_000,
+                        })
+                        .collect(),
+                },
+                TimeSeries {
+                    labels: vec![label("__name__", "node_load1"), label("job", "node")],
+                    samples: vec![
+                        Sample {
+                            value: 0.37,
+                            timestamp: 1_585_413_000_000,
+                        },
+                        Sample {
+                            value: f64::NAN,
+                            timestamp: 1_585_413_015_000,
+                        },
+                    ],
+                },
+            ],
+        };
+        encode_snappy(&request).unwrap()
+    }
+
+    /// A remote write body laid out the way Prometheus sends it: sorted labels, a staleness marker
+    /// and a metadata entry, a field rstz does not declare.
+    const FIXTURE: &[u8] = include_bytes!("testdata/remote_write.snappy");
+
+    fn matcher(kind: MatchType, name: &str, value: &str) -> LabelMatcher {
+        LabelMatcher {
+            r#type: kind as i32,
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn decodes_payload() {
+        let request = decode_snappy::<WriteRequest>(&payload()).unwrap();
+        assert_eq!(request.timeseries.len(), 2);
+        assert_eq!(
+            request.timeseries[0].key().unwrap(),
+            "up{instance=\"localhost:9100\",job=\"node\"}"
+        );
+        assert_eq!(request.timeseries[1].key().unwrap(), "node_load1{job=\"node\"}");
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(decode_snappy::<WriteRequest>(b"not snappy").is_err());
+    }
+
+    #[test]
+    fn stores_samples_per_series() {
+        let collector = Collector::new(Duration::minutes(120), BlockStore::in_memory());
+        let request = decode_snappy::<WriteRequest>(&payload()).unwrap();
+        assert_eq!(
+            ingest_write_request(&request, &collector).unwrap(),
+            Written { stored: 11, dropped: 1, rejected: 0 }
+        );
+
+        collector.flush().unwrap();
+        let series = collector.series();
+        assert_eq!(
+            series,
+            vec![
+                "node_load1{job=\"node\"}".to_string(),
+                "up{instance=\"localhost:9100\",job=\"node\"}".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn stores_the_fixture() {
+        let collector = Collector::new(Duration::minutes(120), BlockStore::in_memory());
+        let request = decode_snappy::<WriteRequest>(FIXTURE).unwrap();
+        assert_eq!(
+            ingest_write_request(&request, &collector).unwrap(),
+            Written { stored: 6, dropped: 1, rejected: 0 }
+        );
+
+        collector.flush().unwrap();
+        assert_eq!(
+            collector.series(),
+            vec![
+                "node_cpu_seconds_total{cpu=\"0\",instance=\"localhost:9100\",job=\"node\",mode=\"idle\"}".to_string(),
+                "node_load1{instance=\"localhost:9100\",job=\"node\"}".to_string(),
+                "up{instance=\"localhost:9100\",job=\"node\"}".to_string(),
+            ]
+        );
+        let blocks = collector.blocks("node_load1{instance=\"localhost:9100\",job=\"node\"}");
+        let (start, end) = (Utc.timestamp_millis(1_585_413_000_000), Utc.timestamp_millis(1_585_413_060_000));
+        let points = query::range(&blocks, start, end).unwrap();
+        let values: Vec<f64> = points.iter().filter_map(|p| p.value().as_f64()).collect();
+        assert_eq!(values, vec![0.37, 0.41]);
+    }
+
+    #[test]
+    fn counts_samples_their_series_rejects() {
+        let collector = Collector::new(Duration::minutes(120), BlockStore::in_memory())
+            .with_timestamps(TimestampCodec::Seconds);
+        let mut request = decode_snappy::<WriteRequest>(&payload()).unwrap();
+        request.timeseries[0].samples[3].timestamp += 500;
+        assert_eq!(
+            ingest_write_request(&request, &collector).unwrap(),
+            Written { stored: 10, dropped: 1, rejected: 1 }
+        );
+    }
+
+    #[test]
+    fn stores_nothing_when_a_series_is_invalid() {
+        let collector = Collector::new(Duration::minutes(120), BlockStore::in_memory());
+        let mut request = decode_snappy::<WriteRequest>(&payload()).unwrap();
+        request.timeseries.push(TimeSeries {
+            labels: vec![label("job", "node")],
+            samples: vec![Sample {
+                value: 1.0,
+                timestamp: 1_585_413_000_000,
+            }],
+        });
+        assert!(ingest_write_request(&request, &collector).is_err());
+
+        request.timeseries.pop();
+        request.timeseries[1].samples[0].timestamp = i64::MAX;
+        assert!(ingest_write_request(&request, &collector).is_err());
+
+        collector.flush().unwrap();
+        assert!(collector.series().is_empty());
+    }
+
+    #[test]
+    fn counts_dropped_staleness_markers() {
+        let collector = Collector::new(Duration::minutes(120), BlockStore::in_memory());
+        // Prometheus marks stale series with this signalling NaN.
+        let stale = f64::from_bits(0x7ff0_0000_0000_0002);
+        let request = WriteRequest {
+            timeseries: vec![TimeSeries {
+                labels: vec![label("__name__", "up")],
+                samples: vec![
+                    Sample {
+                        value: 1.0,
+                        timestamp: 1_585_413_000_000,
+                    },
+                    Sample {
+                        value: stale,
+                        timestamp: 1_585_413_015_000,
+                    },
+                    Sample {
+                        value: f64::INFINITY,
+                        timestamp: 1_585_413_030_000,
+                    },
+                ],
+            }],
+        };
+        assert_eq!(
+            ingest_write_request(&request, &collector).unwrap(),
+            Written { stored: 1, dropped: 2, rejected: 0 }
+        );
+    }
+
+    #[test]
+    fn reads_back_written_samples() {
+        let collector = Collector::new(Duration::minutes(120), BlockStore::in_memory());
+        let write = decode_snappy::<WriteRequest>(&payload()).unwrap();
+        ingest_write_request(&write, &collector).unwrap();
+
+        let read_request = ReadRequest {
+            queries: vec![
+                Query {
+                    start_timestamp_ms: 1_585_413_000_000,
+                    end_timestamp_ms: 1_585_413_060_000,
+                    matchers: vec![
+                        matcher(MatchType::Re, "__name__", "up|node_.*"),
+                        matcher(MatchType::Eq, "job", "node"),
+                    ],
+                },
+                Query {
+                    start_timestamp_ms: 1_585_413_000_000,
+                    end_timestamp_ms: 1_585_413_060_000,
+                    matchers: vec![matcher(MatchType::Nre, "__name__", "node_.*")],
+                },
+            ],
+        };
+        let response = read(&read_request, &collector).unwrap();
+        assert_eq!(response.results.len(), 2);
+
+        let all = &response.results[0].timeseries;
+        assert_eq!(all.len(), 2);
+        assert_eq!(
+            all[0].samples,
+            vec![Sample {
+                value: 0.37,
+                timestamp: 1_585_413_000_000
+            }]
+        );
+        let up = &response.results[1].timeseries;
+        assert_eq!(up.len(), 1);
+        assert_eq!(up[0].samples, write.timeseries[0].samples[..5].to_vec());
+        assert!(up[0].labels.contains(&label("__name__", "up")));
+    }
+
+    #[test]
+    fn rejects_invalid_regex_before_reading() {
+        let collector = Collector::new(Duration::minutes(120), BlockStore::in_memory());
+        let read_request = ReadRequest {
+            queries: vec![Query {
+                start_timestamp_ms: 1_585_413_000_000,
+                end_timestamp_ms: 1_585_413_060_000,
+                matchers: vec![matcher(MatchType::Re, "__name__", "node_(")],
+            }],
+        };
+        assert!(read(&read_request, &collector).is_err());
+    }
+
+    #[test]
+    fn serves_over_http() {
+        let server = std::sync::Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let addr = server.server_addr().to_ip().unwrap();
+        let collector = std::sync::Arc::new(Collector::new(Duration::minutes(120), BlockStore::in_memory()));
+        let handle = {
+            let (server, collector) = (server.clone(), collector.clone());
+            std::thread::spawn(move || serve(&server, &collector))
+        };
+
+        let body = payload();
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Encoding: snappy\r\nContent-Type: application/x-protobuf\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            WRITE_PATH,
+            body.len()
+        );
+        std::io::Write::write_all(&mut stream, head.as_bytes()).unwrap();
+        std::io::Write::write_all(&mut stream, &body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+        server.unblock();
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,367 @@
+use crate::clock::{Clock, SystemClock};
+use crate::collector::{series_key, Collector};
+use crate::errors::{Result, RstzError};
+use crate::events::LogEvent;
+use crate::path::FieldPath;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const MAX_DATAGRAM: usize = 64 * 1024;
+const PERCENTILES: [(&str, f64); 3] = [("p50", 0.5), ("p90", 0.9), ("p99", 0.99)];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricValue {
+    Counter(f64),
+    /// Gauges prefixed with a sign adjust the current value instead of replacing it.
+    Gauge { value: f64, relative: bool },
+    /// Timers, histograms and distributions are all summarized the same way.
+    Timer(f64),
+    Set(String),
+}
+
+/// A single `name:value|type|@rate|#tag:value` line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub value: MetricValue,
+    pub rate: f64,
+    pub tags: Vec<(String, String)>,
+}
+
+impl Metric {
+    pub fn parse(line: &str) -> Result<Self> {
+        let invalid = || RstzError::new(&format!("Invalid statsd line: {}", line));
+        let (name, rest) = line.split_once(':').ok_or_else(invalid)?;
+        let mut parts = rest.split('|');
+        let raw = parts.next().ok_or_else(invalid)?;
+        let kind = parts.next().ok_or_else(invalid)?;
+        let number = || raw.parse::<f64>().ok().filter(|v| v.is_finite()).ok_or_else(invalid);
+        let value = match kind {
+            "c" => MetricValue::Counter(number()?),
+            "g" => MetricValue::Gauge {
+                value: number()?,
+                relative: raw.starts_with('+') || raw.starts_with('-'),
+            },
+            "ms" | "h" | "d" => MetricValue::Timer(number()?),
+            "s" => MetricValue::Set(raw.to_string()),
+            _ => return Err(RstzError::new(&format!("Unknown statsd type: {}", kind))),
+        };
+
+        let mut rate = 1.0;
+        let mut tags = Vec::new();
+        for part in parts {
+            if let Some(r) = part.strip_prefix('@') {
+                rate = r.parse::<f64>().ok().filter(|r| *r > 0.0 && *r <= 1.0).ok_or_else(invalid)?;
+            } else if let Some(t) = part.strip_prefix('#') {
+                for tag in t.split(',').filter(|t| !t.is_empty()) {
+                    let (k, v) = tag.split_once(':').unwrap_or((tag, ""));
+                    tags.push((k.to_string(), v.to_string()));
+                }
+            }
+        }
+        tags.sort();
+        Ok(Metric {
+            name: name.to_string(),
+            value,
+            rate,
+            tags,
+        })
+    }
+}
+
+type MetricId = (String, Vec<(String, String)>);
+
+#[derive(Default)]
+struct TimerStats {
+    count: f64,
+    values: Vec<f64>,
+}
+
+/// Accumulates metrics between flushes the way statsd does.
+/// Counters, timers and sets start over after each flush, gauges keep their last value.
+#[derive(Default)]
+pub struct Aggregator {
+    counters: BTreeMap<MetricId, f64>,
+    gauges: BTreeMap<MetricId, f64>,
+    timers: BTreeMap<MetricId, TimerStats>,
+    sets: BTreeMap<MetricId, BTreeSet<String>>,
+}
+
+impl Aggregator {
+    pub fn new() -> Self {
+        Aggregator::default()
+    }
+
+    pub fn add(&mut self, metric: Metric) {
+        let id = (metric.name, metric.tags);
+        match metric.value {
+            MetricValue::Counter(v) => *self.counters.entry(id).or_insert(0.0) += v / metric.rate,
+            MetricValue::Gauge { value, relative: true } => {
+                *self.gauges.entry(id).or_insert(0.0) += value
+            }
+            MetricValue::Gauge { value, relative: false } => {
+                self.gauges.insert(id, value);
+            }
+            MetricValue::Timer(v) => {
+                let stats = self.timers.entry(id).or_default();
+                stats.count += 1.0 / metric.rate;
+                stats.values.push(v);
+            }
+            MetricValue::Set(member) => {
+                self.sets.entry(id).or_default().insert(member);
+            }
+        }
+    }
+
+    /// Returns the value of every series for the interval that just ended.
+    pub fn flush(&mut self) -> Vec<(String, f64)> {
+        let mut series = Vec::new();
+        let mut push = |(name, tags): &MetricId, suffix: Option<&str>, value: f64| {
+            let name = match suffix {
+                Some(suffix) => format!("{}_{}", name, suffix),
+                None => name.clone(),
+            };
+            let labels = tags.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+            series.push((series_key(&name, labels), value));
+        };
+        for (id, count) in &self.counters {
+            push(id, None, *count);
+        }
+        for (id, value) in &self.gauges {
+            push(id, None, *value);
+        }
+        for (id, members) in &self.sets {
+            push(id, None, members.len() as f64);
+        }
+        for (id, stats) in self.timers.iter_mut() {
+            stats.values.sort_by(|a, b| a.total_cmp(b));
+            let values = &stats.values;
+            let sum: f64 = values.iter().sum();
+            push(id, Some("count"), stats.count);
+            push(id, Some("sum"), sum);
+            push(id, Some("min"), values[0]);
+            push(id, Some("max"), values[values.len() - 1]);
+            push(id, Some("mean"), sum / values.len() as f64);
+            for (suffix, q) in PERCENTILES.iter() {
+                let rank = ((q * values.len() as f64).ceil() as usize).max(1);
+                push(id, Some(suffix), values[rank - 1]);
+            }
+        }
+        self.counters.clear();
+        self.timers.clear();
+        self.sets.clear();
+        series
+    }
+}
+
+/// Receives statsd datagrams and writes the aggregates at every flush interval.
+/// Flushes are stamped at the interval boundary so series are regularly spaced.
+pub struct Listener {
+    socket: UdpSocket,
+    interval: Duration,
+    aggregator: Aggregator,
+    clock: Arc<dyn Clock>,
+    next_flush: DateTime<Utc>,
+}
+
+impl Listener {
+    pub fn new(socket: UdpSocket, interval: Duration) -> Result<Self> {
+        if interval <= Duration::zero() {
+            return Err(RstzError::new("Flush interval must be positive."));
+        }
+        Ok(Listener {
+            socket,
+            interval,
+            aggregator: Aggregator::new(),
+            clock: Arc::new(SystemClock),
+            next_flush: Self::boundary(Utc::now(), interval) + interval,
+        })
+    }
+
+    /// Reads the time of flushes from `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.next_flush = Self::boundary(clock.now(), self.interval) + self.interval;
+        self.clock = clock;
+        self
+    }
+
+    fn boundary(time: DateTime<Utc>, interval: Duration) -> DateTime<Utc> {
+        let step = interval.num_milliseconds().max(1);
+        let ms = time.timestamp_millis().div_euclid(step) * step;
+        Utc.timestamp_millis_opt(ms).single().unwrap_or(time)
+    }
+
+    /// Waits for one datagram, or flushes when the interval is over.
+    pub fn poll(&mut self, collector: &Collector) -> Result<()> {
+        let now = self.clock.now();
+        if now >= self.next_flush {
+            let at = self.next_flush;
+            self.next_flush = Self::boundary(now, self.interval) + self.interval;
+            return self.flush(at, collector);
+        }
+        let wait = (self.next_flush - now)
+            .to_std()
+            .map_err(|e| RstzError::new(&e.to_string()))?;
+        self.socket
+            .set_read_timeout(Some(wait.max(std::time::Duration::from_millis(1))))?;
+        let mut buf = vec![0; MAX_DATAGRAM];
+        let len = match self.socket.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for line in String::from_utf8_lossy(&buf[..len]).lines().filter(|l| !l.is_empty()) {
+            match Metric::parse(line) {
+                Ok(metric) => self.aggregator.add(metric),
+                Err(e) => eprintln!("Skipping metric: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Series that fail to store are reported and skipped, so they do not hold back the others.
+    fn flush(&mut self, at: DateTime<Utc>, collector: &Collector) -> Result<()> {
+        let field = FieldPath::key("value");
+        for (series, value) in self.aggregator.flush() {
+            let mut values = BTreeMap::new();
+            values.insert("value".to_string(), Value::from(value));
+            if let Err(e) = collector.ingest(&series, &field, &LogEvent::new(at, String::new(), values)) {
+                eprintln!("Skipping {}: {}", series, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Polls until `stop` is set, then writes what was aggregated since the last flush.
+    /// Setting `stop` takes effect at the next datagram or flush interval.
+    /// Errors are reported and polling goes on.
+    pub fn serve(mut self, collector: &Collector, stop: &AtomicBool) -> Result<()> {
+        while !stop.load(Ordering::SeqCst) {
+            if let Err(e) = self.poll(collector) {
+                eprintln!("Failed to poll statsd metrics: {}", e);
+            }
+        }
+        let at = self.next_flush;
+        self.flush(at, collector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::encodeco::ValueCodec;
+    use crate::query;
+    use crate::store::BlockStore;
+
+    #[test]
+    fn parses_dogstatsd_lines() {
+        let metric = Metric::parse("page.views:3|c|@0.5|#env:prod,host:a").unwrap();
+        assert_eq!(metric.name, "page.views");
+        assert_eq!(metric.value, MetricValue::Counter(3.0));
+        assert_eq!(metric.rate, 0.5);
+        assert_eq!(
+            metric.tags,
+            vec![
+                ("env".to_string(), "prod".to_string()),
+                ("host".to_string(), "a".to_string())
+            ]
+        );
+        assert_eq!(
+            Metric::parse("temp:-2|g").unwrap().value,
+            MetricValue::Gauge {
+                value: -2.0,
+                relative: true
+            }
+        );
+        assert!(Metric::parse("broken").is_err());
+        assert!(Metric::parse("x:1|zz").is_err());
+    }
+
+    #[test]
+    fn aggregates_per_interval() {
+        let mut aggregator = Aggregator::new();
+        for line in &["hits:1|c", "hits:2|c|@0.5", "temp:10|g", "temp:+5|g", "users:a|s", "users:b|s", "users:a|s"] {
+            aggregator.add(Metric::parse(line).unwrap());
+        }
+        for v in 1..=10 {
+            aggregator.add(Metric::parse(&format!("lat:{}|ms", v)).unwrap());
+        }
+        let flushed: BTreeMap<_, _> = aggregator.flush().into_iter().collect();
+        assert_eq!(flushed["hits"], 5.0);
+        assert_eq!(flushed["temp"], 15.0);
+        assert_eq!(flushed["users"], 2.0);
+        assert_eq!(flushed["lat_count"], 10.0);
+        assert_eq!(flushed["lat_sum"], 55.0);
+        assert_eq!(flushed["lat_min"], 1.0);
+        assert_eq!(flushed["lat_max"], 10.0);
+        assert_eq!(flushed["lat_p50"], 5.0);
+        assert_eq!(flushed["lat_p99"], 10.0);
+
+        let flushed: BTreeMap<_, _> = aggregator.flush().into_iter().collect();
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed["temp"], 15.0);
+    }
+
+    #[test]
+    fn flushes_datagrams_from_localhost() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let clock = Arc::new(ManualClock::new(Utc.ymd(2020, 3, 28).and_hms_milli(16, 0, 0, 30)));
+        let mut listener = Listener::new(socket, Duration::milliseconds(100))
+            .unwrap()
+            .with_clock(clock.clone());
+        let collector = Collector::new(Duration::minutes(120), BlockStore::in_memory());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for _ in 0..4 {
+            client.send_to(b"requests:1|c|#host:a\nqueue:7|g", addr).unwrap();
+            listener.poll(&collector).unwrap();
+            clock.advance(Duration::milliseconds(100));
+            listener.poll(&collector).unwrap();
+        }
+
+        assert_eq!(collector.series(), vec!["queue".to_string(), "requests{host=\"a\"}".to_string()]);
+        let start = Utc.timestamp_millis_opt(0).unwrap();
+        let points = query::range(&collector.blocks("queue"), start, clock.now()).unwrap();
+        let times: Vec<_> = points.iter().map(|p| p.timestamp()).collect();
+        let first = Utc.ymd(2020, 3, 28).and_hms_milli(16, 0, 0, 100);
+        assert_eq!(times, (0..4).map(|i| first + Duration::milliseconds(i * 100)).collect::<Vec<_>>());
+        assert!(points.iter().all(|p| p.value().as_f64() == Some(7.0)));
+        let requests = query::range(&collector.blocks("requests{host=\"a\"}"), start, clock.now()).unwrap();
+        assert!(requests.iter().all(|p| p.value().as_f64() == Some(1.0)));
+    }
+
+    #[test]
+    fn skips_series_that_fail_to_store() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let clock = Arc::new(ManualClock::new(Utc.ymd(2020, 3, 28).and_hms(16, 0, 0)));
+        let mut listener = Listener::new(socket, Duration::seconds(10))
+            .unwrap()
+            .with_clock(clock.clone());
+        // Numbers do not fit the histogram codec, so every flush of `bad` fails.
+        let collector = Collector::new(Duration::minutes(120), BlockStore::in_memory())
+            .with_series_values("bad", ValueCodec::Histogram);
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for value in 2..4 {
+            client.send_to(format!("bad:1|g\ngood:{}|g", value).as_bytes(), addr).unwrap();
+            listener.poll(&collector).unwrap();
+            clock.advance(Duration::seconds(10));
+            listener.poll(&collector).unwrap();
+        }
+
+        collector.flush().unwrap();
+        let start = Utc.timestamp_millis_opt(0).unwrap();
+        let good = query::range(&collector.blocks("good"), start, clock.now()).unwrap();
+        let values: Vec<_> = good.iter().filter_map(|p| p.value().as_f64()).collect();
+        assert_eq!(values, vec![2.0, 3.0]);
+        assert!(collector.blocks("bad").is_empty());
+    }
+}
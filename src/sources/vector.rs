@@ -0,0 +1,258 @@
+use crate::collector::{log_routes, series_key, Collector, Route};
+use crate::errors::{Result, RstzError};
+use crate::events::LogEvent;
+use crate::path::FieldPath;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// An event in Vector's `native_json` codec.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorEvent {
+    Log(BTreeMap<String, Value>),
+    Metric(Metric),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Metric {
+    pub name: String,
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, Value>,
+    pub timestamp: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub value: MetricValue,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricValue {
+    Counter { value: f64 },
+    Gauge { value: f64 },
+    Set { values: Vec<String> },
+    Distribution { samples: Vec<Sample> },
+    AggregatedHistogram { count: u64, sum: f64 },
+    AggregatedSummary { count: u64, sum: f64 },
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Sample {
+    pub value: f64,
+    pub rate: u32,
+}
+
+impl VectorEvent {
+    /// Turns the event into a Log Event plus the series it feeds.
+    /// Logs feed one series per `log_fields` entry, metrics feed `name` or its `_count`/`_sum`/... statistics.
+    pub fn into_routes(self, log_fields: &[FieldPath]) -> Result<(LogEvent, Vec<Route>)> {
+        match self {
+            VectorEvent::Log(mut values) => {
+                let timestamp = match values.remove("timestamp") {
+                    Some(ts) => serde_json::from_value(ts)?,
+                    None => Utc::now(),
+                };
+                let host = match values.remove("host") {
+                    Some(Value::String(host)) => host,
+                    _ => return Err(RstzError::new("Vector log event without host.")),
+                };
+                let event = LogEvent::new(timestamp, host, values);
+                let routes = log_routes(&event, log_fields);
+                Ok((event, routes))
+            }
+            VectorEvent::Metric(metric) => metric.into_routes(),
+        }
+    }
+}
+
+impl Metric {
+    pub fn full_name(&self) -> String {
+        match &self.namespace {
+            Some(ns) => format!("{}_{}", ns, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Numeric statistics of the metric, named after the suffix used in their series.
+    pub fn statistics(&self) -> Vec<(&'static str, f64)> {
+        match &self.value {
+            MetricValue::Counter { value } | MetricValue::Gauge { value } => vec![("value", *value)],
+            MetricValue::Set { values } => vec![("value", values.len() as f64)],
+            MetricValue::Distribution { samples } => {
+                let count: f64 = samples.iter().map(|s| s.rate as f64).sum();
+                let sum: f64 = samples.iter().map(|s| s.value * s.rate as f64).sum();
+                let min = samples.iter().map(|s| s.value).fold(f64::INFINITY, f64::min);
+                let max = samples.iter().map(|s| s.value).fold(f64::NEG_INFINITY, f64::max);
+                vec![("count", count), ("sum", sum), ("min", min), ("max", max)]
+            }
+            MetricValue::AggregatedHistogram { count, sum }
+            | MetricValue::AggregatedSummary { count, sum } => {
+                vec![("count", *count as f64), ("sum", *sum)]
+            }
+        }
+    }
+
+    fn into_routes(self) -> Result<(LogEvent, Vec<Route>)> {
+        let tags: BTreeMap<String, String> = self
+            .tags
+            .iter()
+            .map(|(k, v)| (k.clone(), v.as_str().map(String::from).unwrap_or_else(|| v.to_string())))
+            .collect();
+        let name = self.full_name();
+        let mut values = BTreeMap::new();
+        let mut routes = Vec::new();
+        for (stat, value) in self.statistics() {
+            if !value.is_finite() {
+                continue;
+            }
+            let series_name = match stat {
+                "value" => name.clone(),
+                _ => format!("{}_{}", name, stat),
+            };
+            let labels = tags.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+            routes.push((series_key(&series_name, labels), stat.parse()?));
+            values.insert(stat.to_string(), Value::from(value));
+        }
+        let host = tags.get("host").cloned().unwrap_or_default();
+        let timestamp = self.timestamp.unwrap_or_else(Utc::now);
+        Ok((LogEvent::new(timestamp, host, values), routes))
+    }
+}
+
+/// Accepts newline delimited `native_json` events, as sent by Vector's `socket` sink in tcp mode.
+/// Returns at the first connection accepted once `stop` is set, leaving the caller to flush open blocks.
+pub fn serve(
+    listener: TcpListener,
+    collector: Arc<Collector>,
+    log_fields: Arc<Vec<FieldPath>>,
+    stop: &AtomicBool,
+) -> Result<()> {
+    for stream in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = stream?;
+        let collector = Arc::clone(&collector);
+        let log_fields = Arc::clone(&log_fields);
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &collector, &log_fields) {
+                eprintln!("Vector connection closed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    collector: &Collector,
+    log_fields: &[FieldPath],
+) -> Result<()> {
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Err(e) = ingest_line(&line, collector, log_fields) {
+            eprintln!("Skipping event: {}", e);
+        }
+    }
+    Ok(())
+}
+
+pub fn ingest_line(line: &str, collector: &Collector, log_fields: &[FieldPath]) -> Result<()> {
+    let event: VectorEvent = serde_json::from_str(line)?;
+    let (event, routes) = event.into_routes(log_fields)?;
+    collector.ingest_routes(&event, &routes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query;
+    use crate::store::BlockStore;
+    use chrono::{Duration, TimeZone};
+    use std::io::Write;
+    use std::net::Shutdown;
+    use std::sync::atomic::Ordering;
+
+    const LOG: &str = r#"{"log":{"timestamp":"2020-03-28T16:00:00Z","host":"web-1","latency":{"ms":12.5},"path":"/"}}"#;
+    const COUNTER: &str = r#"{"metric":{"name":"requests","namespace":"nginx","tags":{"host":"web-1","code":200},"timestamp":"2020-03-28T16:00:00Z","kind":"incremental","counter":{"value":42.0}}}"#;
+    const GAUGE: &str = r#"{"metric":{"name":"load","timestamp":"2020-03-28T16:00:10Z","kind":"absolute","gauge":{"value":0.75}}}"#;
+    const DISTRIBUTION: &str = r#"{"metric":{"name":"latency","timestamp":"2020-03-28T16:00:20Z","kind":"incremental","distribution":{"samples":[{"value":2.0,"rate":3},{"value":10.0,"rate":1}],"statistic":"histogram"}}}"#;
+
+    fn collector() -> Collector {
+        Collector::new(Duration::minutes(120), BlockStore::in_memory())
+    }
+
+    fn values(collector: &Collector, series: &str) -> Vec<f64> {
+        let (start, end) = (Utc.timestamp(0, 0), Utc::now());
+        collector.flush().unwrap();
+        query::range(&collector.blocks(series), start, end)
+            .unwrap()
+            .iter()
+            .filter_map(|p| p.value().as_f64())
+            .collect()
+    }
+
+    #[test]
+    fn routes_log_fields() {
+        let event: VectorEvent = serde_json::from_str(LOG).unwrap();
+        let fields = vec!["latency.ms".parse().unwrap(), "path".parse().unwrap()];
+        let (event, routes) = event.into_routes(&fields).unwrap();
+        assert_eq!(event.host(), "web-1");
+        assert_eq!(event.datetime(), Utc.ymd(2020, 3, 28).and_hms(16, 0, 0));
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].1, fields[0]);
+
+        let without_host = r#"{"log":{"message":"hello"}}"#;
+        let event: VectorEvent = serde_json::from_str(without_host).unwrap();
+        assert!(event.into_routes(&fields).is_err());
+    }
+
+    #[test]
+    fn routes_metric_statistics() {
+        let collector = collector();
+        for line in &[COUNTER, GAUGE, DISTRIBUTION] {
+            ingest_line(line, &collector, &[]).unwrap();
+        }
+        assert_eq!(values(&collector, "nginx_requests{code=\"200\",host=\"web-1\"}"), vec![42.0]);
+        assert_eq!(values(&collector, "load"), vec![0.75]);
+        assert_eq!(values(&collector, "latency_count"), vec![4.0]);
+        assert_eq!(values(&collector, "latency_sum"), vec![16.0]);
+        assert_eq!(values(&collector, "latency_min"), vec![2.0]);
+        assert_eq!(values(&collector, "latency_max"), vec![10.0]);
+        assert!(ingest_line(r#"{"metric":{"name":"x"}}"#, &collector, &[]).is_err());
+    }
+
+    #[test]
+    fn serves_until_stopped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let collector = Arc::new(collector());
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (collector, stop) = (Arc::clone(&collector), Arc::clone(&stop));
+            thread::spawn(move || serve(listener, collector, Arc::new(Vec::new()), &stop))
+        };
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        writeln!(stream, "{}\nnot json\n\n{}", GAUGE, COUNTER).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let deadline = Utc::now() + Duration::seconds(5);
+        while collector.series().len() < 2 && Utc::now() < deadline {
+            thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(collector.series().len(), 2);
+
+        stop.store(true, Ordering::SeqCst);
+        TcpStream::connect(addr).unwrap();
+        handle.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,329 @@
+use crate::crypto::{Cipher, Encryption, RecordCipher};
+use crate::errors::{Result, RstzError};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SEGMENT_MAGIC: &[u8; 8] = b"RSTZSEG\x01";
+const ENCRYPTED_MAGIC: &[u8; 8] = b"RSTZSEG\x02";
+const SEGMENT_EXTENSION: &str = "rstz";
+
+/// Keeps sealed blocks grouped by series.
+/// When backed by a directory every block is also appended to a segment file,
+/// segment records are `[u16 key len][key][u32 block len][block]` after an 8 byte magic.
+/// Encrypted segments follow their magic with `[u8 cipher][u8 key id len][key id]`,
+/// and hold each record as `[u32 len][nonce][ciphertext][tag]`, see `RecordCipher`.
+pub struct BlockStore {
+    dir: Option<PathBuf>,
+    // Written at the start of the segment the first append creates.
+    header: Vec<u8>,
+    segment: Option<BufWriter<File>>,
+    cipher: Option<RecordCipher>,
+    blocks: BTreeMap<String, Vec<Vec<u8>>>,
+}
+
+impl BlockStore {
+    pub fn in_memory() -> Self {
+        BlockStore {
+            dir: None,
+            header: Vec::new(),
+            segment: None,
+            cipher: None,
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    /// Loads every segment found in `dir`, the first append starts a new one.
+    /// Encrypted segments fail to load with `RstzError::UnknownKey`.
+    pub fn open(dir: &Path) -> Result<Self> {
+        BlockStore::open_with(dir, None)
+    }
+
+    /// Like `open`, with the new segment encrypted under the current key of `encryption`.
+    /// Segments are decrypted with the key their header names, plain ones still load.
+    pub fn open_encrypted(dir: &Path, encryption: &Encryption) -> Result<Self> {
+        BlockStore::open_with(dir, Some(encryption))
+    }
+
+    fn open_with(dir: &Path, encryption: Option<&Encryption>) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut store = BlockStore::in_memory();
+        let mut segments: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION))
+            .collect();
+        segments.sort();
+        let last = segments.len().saturating_sub(1);
+        for (n, path) in segments.iter().enumerate() {
+            let loaded = store.load_segment(path, encryption)?;
+            if let Some(len) = loaded.filter(|_| n == last) {
+                // A crash while appending leaves part of a record behind, which the new
+                // segment would otherwise keep in the middle of the directory for good.
+                eprintln!("Truncating {} after its last complete record.", path.display());
+                OpenOptions::new().write(true).open(path)?.set_len(len as u64)?;
+            } else if loaded.is_some() {
+                return Err(RstzError::new(&format!("{} ends in a partial record.", path.display())));
+            }
+        }
+
+        store.header = match encryption {
+            Some(encryption) => {
+                let (id, key) = encryption.keys.current()?;
+                let mut header = ENCRYPTED_MAGIC.to_vec();
+                header.push(encryption.cipher.id());
+                header.push(u8::try_from(id.len()).map_err(|_| RstzError::new("Key id too long."))?);
+                header.extend_from_slice(id.as_bytes());
+                store.cipher = Some(RecordCipher::new(encryption.cipher, &key, &header));
+                header
+            }
+            None => SEGMENT_MAGIC.to_vec(),
+        };
+        store.dir = Some(dir.to_path_buf());
+        Ok(store)
+    }
+
+    /// Loads the records of a segment, returning the length of its complete records
+    /// when it ends in a partial one.
+    fn load_segment(&mut self, path: &Path, encryption: Option<&Encryption>) -> Result<Option<usize>> {
+        let mut data = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut data)?;
+        if data.starts_with(SEGMENT_MAGIC) {
+            let mut rest = &data[SEGMENT_MAGIC.len()..];
+            while !rest.is_empty() {
+                let mut record = rest;
+                match self.load_record(&mut record) {
+                    Ok(()) => rest = record,
+                    Err(RstzError::Eof) => return Ok(Some(data.len() - rest.len())),
+                    Err(e) => return Err(e),
+                }
+            }
+            return Ok(None);
+        }
+        if !data.starts_with(ENCRYPTED_MAGIC) {
+            return Err(RstzError::new(&format!("{} is not a segment file.", path.display())));
+        }
+        let mut rest = &data[ENCRYPTED_MAGIC.len()..];
+        let cipher = Cipher::from_id(take_array::<1>(&mut rest)?[0])?;
+        let id_len = take_array::<1>(&mut rest)?[0] as usize;
+        let id = String::from_utf8_lossy(take(&mut rest, id_len)?).into_owned();
+        let key = match encryption {
+            Some(encryption) => encryption.keys.key(&id)?,
+            None => return Err(RstzError::UnknownKey(id)),
+        };
+        let mut records = RecordCipher::new(cipher, &key, &data[..data.len() - rest.len()]);
+        while !rest.is_empty() {
+            let mut sealed = rest;
+            let sealed_len = match take_array(&mut sealed) {
+                Ok(len) => u32::from_be_bytes(len) as usize,
+                Err(_) => return Ok(Some(data.len() - rest.len())),
+            };
+            let record = match take(&mut sealed, sealed_len) {
+                Ok(record) => records.open(record)?,
+                Err(_) => return Ok(Some(data.len() - rest.len())),
+            };
+            self.load_record(&mut record.as_slice())?;
+            rest = sealed;
+        }
+        Ok(None)
+    }
+
+    fn load_record(&mut self, rest: &mut &[u8]) -> Result<()> {
+        let key_len = u16::from_be_bytes(take_array(rest)?) as usize;
+        let key = String::from_utf8(take(rest, key_len)?.to_vec())
+            .map_err(|e| RstzError::new(&e.to_string()))?;
+        let block_len = u32::from_be_bytes(take_array(rest)?) as usize;
+        let block = take(rest, block_len)?.to_vec();
+        self.blocks.entry(key).or_default().push(block);
+        Ok(())
+    }
+
+    pub fn append(&mut self, series: &str, block: Vec<u8>) -> Result<()> {
+        if block.is_empty() {
+            return Ok(());
+        }
+        if let (Some(dir), None) = (&self.dir, &self.segment) {
+            // Stores that never append leave no empty segment behind.
+            let mut segment = BufWriter::new(new_segment(dir)?);
+            segment.write_all(&self.header)?;
+            self.segment = Some(segment);
+        }
+        if let Some(segment) = self.segment.as_mut() {
+            let key_len = u16::try_from(series.len())
+                .map_err(|_| RstzError::new("Series key too long."))?;
+            let block_len = u32::try_from(block.len())
+                .map_err(|_| RstzError::new("Block too large."))?;
+            let mut record = Vec::with_capacity(6 + series.len() + block.len());
+            record.extend_from_slice(&key_len.to_be_bytes());
+            record.extend_from_slice(series.as_bytes());
+            record.extend_from_slice(&block_len.to_be_bytes());
+            record.extend_from_slice(&block);
+            if let Some(cipher) = self.cipher.as_mut() {
+                record = cipher.seal(&record)?;
+                let sealed_len =
+                    u32::try_from(record.len()).map_err(|_| RstzError::new("Block too large."))?;
+                segment.write_all(&sealed_len.to_be_bytes())?;
+            }
+            segment.write_all(&record)?;
+            // Hands every sealed block to the OS, `sync` makes them durable.
+            segment.flush()?;
+        }
+        self.blocks
+            .entry(series.to_string())
+            .or_default()
+            .push(block);
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> Result<()> {
+        if let Some(segment) = self.segment.as_mut() {
+            segment.flush()?;
+            segment.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    pub fn series(&self) -> impl Iterator<Item = &String> {
+        self.blocks.keys()
+    }
+
+    pub fn blocks(&self, series: &str) -> &[Vec<u8>] {
+        self.blocks.get(series).map_or(&[], |b| b.as_slice())
+    }
+}
+
+/// Creates the next segment of `dir`, named after the current millisecond and a counter
+/// for stores opened within the same one, so names sort in creation order.
+fn new_segment(dir: &Path) -> Result<File> {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| RstzError::new(&e.to_string()))?
+        .as_millis();
+    for n in 0..10_000 {
+        let path = dir.join(format!("segment-{}-{:04}.{}", millis, n, SEGMENT_EXTENSION));
+        match OpenOptions::new().create_new(true).write(true).open(path) {
+            Ok(file) => return Ok(file),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(RstzError::new("Too many segments opened within a millisecond."))
+}
+
+fn take<'d>(rest: &mut &'d [u8], len: usize) -> Result<&'d [u8]> {
+    if rest.len() < len {
+        return Err(RstzError::Eof);
+    }
+    let (head, tail) = rest.split_at(len);
+    *rest = tail;
+    Ok(head)
+}
+
+fn take_array<const N: usize>(rest: &mut &[u8]) -> Result<[u8; N]> {
+    let mut array = [0; N];
+    array.copy_from_slice(take(rest, N)?);
+    Ok(array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rstz-store-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn segments(dir: &Path) -> Vec<PathBuf> {
+        let mut segments: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect();
+        segments.sort();
+        segments
+    }
+
+    #[test]
+    fn reloads_segments_in_order() {
+        let dir = scratch("reload");
+        let mut store = BlockStore::open(&dir).unwrap();
+        store.append("cpu{host=\"a\"}", vec![1, 2, 3]).unwrap();
+        store.append("mem", vec![9; 300]).unwrap();
+        store.append("mem", Vec::new()).unwrap();
+        drop(store);
+        let mut store = BlockStore::open(&dir).unwrap();
+        store.append("cpu{host=\"a\"}", vec![4, 5]).unwrap();
+        store.sync().unwrap();
+        drop(store);
+
+        let store = BlockStore::open(&dir).unwrap();
+        assert_eq!(store.series().collect::<Vec<_>>(), vec!["cpu{host=\"a\"}", "mem"]);
+        assert_eq!(store.blocks("cpu{host=\"a\"}"), &[vec![1, 2, 3], vec![4, 5]][..]);
+        assert_eq!(store.blocks("mem"), &[vec![9; 300]][..]);
+        assert!(store.blocks("disk").is_empty());
+        assert_eq!(segments(&dir).len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn truncates_a_torn_last_record() {
+        let dir = scratch("torn");
+        let mut store = BlockStore::open(&dir).unwrap();
+        store.append("cpu", vec![1, 2, 3]).unwrap();
+        store.append("cpu", vec![4, 5, 6]).unwrap();
+        drop(store);
+        let path = segments(&dir).pop().unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 2).unwrap();
+
+        let store = BlockStore::open(&dir).unwrap();
+        assert_eq!(store.blocks("cpu"), &[vec![1, 2, 3]][..]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len - 12);
+        drop(store);
+        // Once cut, the segment loads cleanly even though it is no longer the last one.
+        let store = BlockStore::open(&dir).unwrap();
+        assert_eq!(store.blocks("cpu").len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_partial_records_before_the_last_segment() {
+        let dir = scratch("partial");
+        let mut store = BlockStore::open(&dir).unwrap();
+        store.append("cpu", vec![1, 2, 3]).unwrap();
+        drop(store);
+        let mut store = BlockStore::open(&dir).unwrap();
+        store.append("cpu", vec![4]).unwrap();
+        drop(store);
+        let first = segments(&dir).remove(0);
+        let mut data = fs::read(&first).unwrap();
+        data.extend_from_slice(&[0, 3, b'c']);
+        fs::write(&first, &data).unwrap();
+        assert!(BlockStore::open(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn starts_segments_on_the_first_append() {
+        let dir = scratch("lazy");
+        drop(BlockStore::open(&dir).unwrap());
+        let mut store = BlockStore::open(&dir).unwrap();
+        assert!(segments(&dir).is_empty());
+        store.append("cpu", Vec::new()).unwrap();
+        assert!(segments(&dir).is_empty());
+        store.append("cpu", vec![1]).unwrap();
+        store.append("cpu", vec![2]).unwrap();
+        assert_eq!(segments(&dir).len(), 1);
+        drop(store);
+        assert_eq!(BlockStore::open(&dir).unwrap().blocks("cpu"), &[vec![1], vec![2]][..]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_blocks_in_memory() {
+        let mut store = BlockStore::in_memory();
+        store.append("cpu", vec![1]).unwrap();
+        store.sync().unwrap();
+        assert_eq!(store.blocks("cpu"), &[vec![1]][..]);
+    }
+}
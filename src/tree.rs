@@ -1,39 +1,29 @@
-extern crate chrono;
-
-use crate::errors::RstzError;
-use crate::events::DataPoint;
-use chrono::Duration;
-use node::KEY_BYTE_LENGHT;
-use node::{ChdPtr, Node, NodeType};
-use std::rc::Rc;
+use node::{ChdPtr, Node, NodeType, TSNode, KEY_BYTE_LENGHT};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 mod node {
 
-    use crate::errors::RstzError;
-
     pub(super) type ChdPtr = Option<Box<NodeType>>;
 
     const MAX_CHILDREN_PER_NODE: usize = 32; // Use module to fit the 254 possible results in a byte into 32.
     pub const KEY_BYTE_LENGHT: usize = 16;
 
     pub enum NodeType {
-        TreeNode(Node),
+        TreeNode(Box<Node>),
         LeafNode(TSNode),
     }
 
+    /// Children are indexed by how many leading key bytes they share with the node,
+    /// the child at `KEY_BYTE_LENGHT` holds the node's own key.
     pub struct Node {
-        pub pidx: Option<usize>, //Index id in parent Node
         key: [u8; KEY_BYTE_LENGHT],
         children: [ChdPtr; MAX_CHILDREN_PER_NODE],
     }
 
     impl Node {
-        pub(crate) fn new(
-            pidx: Option<usize>,
-            key: [u8; KEY_BYTE_LENGHT],
-        ) -> Self {
+        pub(crate) fn new(key: [u8; KEY_BYTE_LENGHT]) -> Self {
             Node {
-                pidx,
                 key,
                 children: Default::default(),
             }
@@ -47,71 +37,181 @@ mod node {
             &self.children[idx]
         }
 
-        pub fn add_child(&mut self, child: NodeType, index: usize) -> Result<(), RstzError> {
-            if index >= MAX_CHILDREN_PER_NODE {
-                return Err(RstzError::new("Child index out of bounds"));
-            }
-            self.children[index] = Some(Box::new(child));
-            Ok(())
+        pub fn children(&self) -> impl Iterator<Item = &NodeType> {
+            self.children.iter().filter_map(|c| c.as_deref())
+        }
+
+        pub fn keycmp(&self, key: &[u8; KEY_BYTE_LENGHT]) -> usize {
+            self.key
+                .iter()
+                .zip(key.iter())
+                .take_while(|(a, b)| a == b)
+                .count()
         }
     }
 
+    /// Series sharing a key, more than one only when their digests collide.
     pub struct TSNode {
-        ts: Vec<u8>,
+        pub key: [u8; KEY_BYTE_LENGHT],
+        pub series: Vec<String>,
     }
 }
 
+/// Catalog of known series, keyed by a 16 byte digest of the series name.
+/// Nodes are only split when two keys land on the same slot.
+/// Leaves keep the series names, so series whose digests collide stay apart.
 pub struct LazzyTree {
-    root: Box<node::Node>,
-    timewindow: Duration,
+    root: Box<Node>,
+    len: usize,
+}
+
+impl Default for LazzyTree {
+    fn default() -> Self {
+        LazzyTree::new()
+    }
 }
 
 impl LazzyTree {
-    pub fn new(
-        key: [u8; KEY_BYTE_LENGHT],
-        value: &str,
-        timewindow: Duration,
-    ) -> Self {
-        let root_node = Node::new(None, key);
-        
+    pub fn new() -> Self {
         LazzyTree {
-            root: Box::new(node::Node::new(None, key)),
-            timewindow,
+            root: Box::new(Node::new([0; KEY_BYTE_LENGHT])),
+            len: 0,
+        }
+    }
+
+    pub fn key_for(series: &str) -> [u8; KEY_BYTE_LENGHT] {
+        let mut key = [0; KEY_BYTE_LENGHT];
+        for (seed, chunk) in key.chunks_mut(8).enumerate() {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            series.hash(&mut hasher);
+            chunk.copy_from_slice(&hasher.finish().to_be_bytes());
         }
+        key
     }
 
-    /*
-    pub fn insert(&mut self, key: [u8; KEY_BYTE_LENGHT], value: &str) -> Result<(), RstzError> {
-        let pidx = LazzyTree::keycmp(self.root.as_ref(), key);
-        LazzyTree::place(pidx, self.root.child_as_mut(pidx), key, value)
+    /// Adds `series` to the catalog, returns false when it was already there.
+    pub fn insert(&mut self, series: &str) -> bool {
+        self.insert_keyed(LazzyTree::key_for(series), series)
     }
 
-     fn place(
-        pidx: usize,
-        ptr: &mut ChdPtr,
-        key: [u8; KEY_BYTE_LENGHT],
-        value: &str,
-    ) -> Result<(), RstzError> {
-        if let Some(child) = ptr {
-            match child.as_mut() {
-                NodeType::TreeNode(n) => {
-                    let npidx = LazzyTree::keycmp(n, key);
-                    LazzyTree::place(npidx, &mut n.children[npidx], key, value)?
+    fn insert_keyed(&mut self, key: [u8; KEY_BYTE_LENGHT], series: &str) -> bool {
+        let pidx = self.root.keycmp(&key);
+        let added = LazzyTree::place(self.root.child_as_mut(pidx), key, series);
+        if added {
+            self.len += 1;
+        }
+        added
+    }
+
+    fn place(ptr: &mut ChdPtr, key: [u8; KEY_BYTE_LENGHT], series: &str) -> bool {
+        let split = matches!(ptr.as_deref(), Some(NodeType::LeafNode(leaf)) if leaf.key != key);
+        if split {
+            // The leaf moves under a new node keyed like itself, making room for the new key.
+            if let Some(leaf) = ptr.take() {
+                let leaf_key = match leaf.as_ref() {
+                    NodeType::LeafNode(l) => l.key,
+                    NodeType::TreeNode(_) => unreachable!("Only leaves are split."),
+                };
+                let mut node = Node::new(leaf_key);
+                *node.child_as_mut(KEY_BYTE_LENGHT) = Some(leaf);
+                *ptr = Some(Box::new(NodeType::TreeNode(Box::new(node))));
+            }
+        }
+        match ptr.as_deref_mut() {
+            None => {
+                *ptr = Some(Box::new(NodeType::LeafNode(TSNode {
+                    key,
+                    series: vec![series.to_string()],
+                })));
+                true
+            }
+            Some(NodeType::TreeNode(n)) => {
+                let npidx = n.keycmp(&key);
+                LazzyTree::place(n.child_as_mut(npidx), key, series)
+            }
+            Some(NodeType::LeafNode(leaf)) if leaf.series.iter().any(|s| s == series) => false,
+            Some(NodeType::LeafNode(leaf)) => {
+                leaf.series.push(series.to_string());
+                true
+            }
+        }
+    }
+
+    pub fn contains(&self, series: &str) -> bool {
+        self.contains_keyed(LazzyTree::key_for(series), series)
+    }
+
+    fn contains_keyed(&self, key: [u8; KEY_BYTE_LENGHT], series: &str) -> bool {
+        let mut ptr = self.root.child_as_ref(self.root.keycmp(&key));
+        loop {
+            match ptr.as_deref() {
+                None => return false,
+                Some(NodeType::LeafNode(leaf)) => {
+                    return leaf.key == key && leaf.series.iter().any(|s| s == series)
                 }
-                NodeType::LeafNode(d) => {}
+                Some(NodeType::TreeNode(n)) => ptr = n.child_as_ref(n.keycmp(&key)),
             }
         }
-        Ok(())
-    } 
-
-    fn keycmp(ptr: &Node, key: [u8; KEY_BYTE_LENGHT]) -> usize {
-        let mut res: usize = 0;
-        for i in 0..KEY_BYTE_LENGHT {
-            if ptr.key[i] != key[i] {
-                return res;
+    }
+
+    /// Every series in the catalog, sorted by name.
+    pub fn series(&self) -> Vec<&str> {
+        let mut series = Vec::with_capacity(self.len);
+        let mut stack: Vec<&Node> = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            for child in node.children() {
+                match child {
+                    NodeType::TreeNode(n) => stack.push(n),
+                    NodeType::LeafNode(leaf) => {
+                        series.extend(leaf.series.iter().map(String::as_str))
+                    }
+                }
             }
-            res += 1;
         }
-        res
-    } */
+        series.sort_unstable();
+        series
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalogs_series() {
+        let mut tree = LazzyTree::new();
+        let names: Vec<String> = (0..500).map(|i| format!("cpu{{host=\"{}\"}}", i)).collect();
+        for name in &names {
+            assert!(tree.insert(name));
+        }
+        assert!(!tree.insert(&names[42]));
+        assert!(names.iter().all(|name| tree.contains(name)));
+        assert!(!tree.contains("cpu"));
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(tree.series(), sorted);
+    }
+
+    #[test]
+    fn keeps_colliding_series_apart() {
+        let mut tree = LazzyTree::new();
+        let key = LazzyTree::key_for("a");
+        assert!(tree.insert_keyed(key, "a"));
+        assert!(!tree.contains_keyed(key, "b"));
+        assert!(tree.insert_keyed(key, "b"));
+        assert!(!tree.insert_keyed(key, "b"));
+        assert!(tree.contains_keyed(key, "a") && tree.contains_keyed(key, "b"));
+        // A key sharing a prefix splits the leaf and both series move along.
+        let mut near = key;
+        near[15] ^= 1;
+        assert!(tree.insert_keyed(near, "c"));
+        assert!(tree.contains_keyed(key, "b") && tree.contains_keyed(near, "c"));
+        assert_eq!(tree.series(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn defaults_to_empty() {
+        assert!(LazzyTree::default().series().is_empty());
+    }
 }